@@ -16,19 +16,23 @@
 
 use futures::future::BoxFuture;
 use futures::FutureExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use std::{
     collections::VecDeque,
     task::{Context, Poll, Waker},
 };
+use tokio::sync::OwnedSemaphorePermit;
 use tracing::{instrument, Instrument, Span};
 
 use crate::deadline::Deadline;
+use crate::particle_data_store::CleanupKey;
 use crate::particle_effects::RawRoutingEffects;
-use crate::particle_executor::{FutResult, ParticleExecutor};
+use crate::particle_executor::{FutResult, ParticleExecutor, VmOutcome};
 use crate::particle_functions::{Functions, SingleCallStat};
 use crate::spawner::{SpawnFunctions, Spawner};
-use crate::{AquaRuntime, InterpretationStats, ParticleDataStore, ParticleEffects};
+use crate::{AquaRuntime, DataStore, InterpretationStats, ParticleEffects};
 use fluence_keypair::KeyPair;
 use fluence_libp2p::PeerId;
 use particle_execution::{ParticleFunctionStatic, ServiceFunction};
@@ -37,10 +41,10 @@ use types::DealId;
 
 struct Reusables<RT> {
     vm_id: usize,
-    vm: Option<RT>,
+    vm: VmOutcome<RT>,
 }
 
-type AVMCallResult<RT> = FutResult<(usize, Option<RT>), RawRoutingEffects, InterpretationStats>;
+type AVMCallResult<RT> = FutResult<(usize, VmOutcome<RT>), RawRoutingEffects, InterpretationStats>;
 type AVMTask<RT> = BoxFuture<
     'static,
     (
@@ -50,10 +54,16 @@ type AVMTask<RT> = BoxFuture<
         Arc<Span>,
     ),
 >;
-pub struct Actor<RT, F> {
+pub struct Actor<RT, F, DS> {
     /// Particle of that actor is expired after that deadline
     deadline: Deadline,
     future: Option<AVMTask<RT>>,
+    /// Id of the VM owned by `future`
+    executing_vm_id: Option<usize>,
+    /// When `future` was started, in milliseconds
+    execution_started_at: Option<u64>,
+    /// Share of the global VM budget taken by `future`
+    vm_permit: Option<OwnedSemaphorePermit>,
     mailbox: VecDeque<ExtendedParticle>,
     waker: Option<Waker>,
     functions: Functions<F>,
@@ -67,45 +77,97 @@ pub struct Actor<RT, F> {
     /// TODO: for the clean-up, I don't think we need it here!
     particle_token: String,
     key_pair: KeyPair,
-    data_store: Arc<ParticleDataStore>,
+    data_store: Arc<DS>,
     spawner: Spawner,
     deal_id: Option<DealId>,
+    /// Max local re-ingest depth of the ingested particles, propagated to the effects
+    hops: u32,
+    /// Total interpretation time of the particles executed by the actor
+    execution_time: Duration,
+    /// Particle is sent by the management peer or the host, so it's given VMs first
+    priority: bool,
+    /// Approximate number of bytes held by the actor and its mailbox
+    memory: usize,
+    load: Arc<ActorLoad>,
 }
 
-impl<RT, F> Actor<RT, F>
+/// Running totals of the mailboxes and memory of all actors sharing it,
+/// kept up to date on ingest, execution and drop of the actors
+#[derive(Debug, Default)]
+pub struct ActorLoad {
+    mailbox_size: AtomicUsize,
+    memory: AtomicUsize,
+}
+
+impl ActorLoad {
+    pub fn mailbox_size(&self) -> usize {
+        self.mailbox_size.load(Ordering::Relaxed)
+    }
+
+    pub fn memory(&self) -> usize {
+        self.memory.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, particles: usize, memory: usize) {
+        self.mailbox_size.fetch_add(particles, Ordering::Relaxed);
+        self.memory.fetch_add(memory, Ordering::Relaxed);
+    }
+
+    fn sub(&self, particles: usize, memory: usize) {
+        self.mailbox_size.fetch_sub(particles, Ordering::Relaxed);
+        self.memory.fetch_sub(memory, Ordering::Relaxed);
+    }
+}
+
+impl<RT, F, DS> Actor<RT, F, DS>
 where
     RT: AquaRuntime,
     F: ParticleFunctionStatic,
+    DS: DataStore,
 {
     // TODO: temporary (I hope), need to do smth clever with particle_token
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         particle: &Particle,
+        deadline: Deadline,
         functions: Functions<F>,
         current_peer_id: PeerId,
         particle_token: String,
         key_pair: KeyPair,
-        data_store: Arc<ParticleDataStore>,
+        data_store: Arc<DS>,
         deal_id: Option<DealId>,
         spawner: Spawner,
+        priority: bool,
+        load: Arc<ActorLoad>,
     ) -> Self {
+        // Clone particle without data
+        let particle = Particle {
+            data: vec![],
+            ..particle.clone()
+        };
+        let memory = std::mem::size_of::<Self>() + particle_memory_estimate(&particle);
+        load.add(0, memory);
         Self {
-            deadline: Deadline::from(particle),
+            deadline,
             functions,
             future: None,
+            executing_vm_id: None,
+            execution_started_at: None,
+            vm_permit: None,
             mailbox: <_>::default(),
             waker: None,
-            // Clone particle without data
-            particle: Particle {
-                data: vec![],
-                ..particle.clone()
-            },
+            particle,
             current_peer_id,
             particle_token,
             key_pair,
             data_store,
             spawner,
             deal_id,
+            hops: 0,
+            execution_time: Duration::ZERO,
+            priority,
+            memory,
+            load,
         }
     }
 
@@ -113,36 +175,98 @@ where
         self.deadline.is_expired(now_ms)
     }
 
+    /// Time left until the actor's particle expires
+    pub fn expires_in(&self, now_ms: u64) -> Duration {
+        self.deadline.remaining(now_ms)
+    }
+
     pub fn is_executing(&self) -> bool {
         self.future.is_some()
     }
 
-    pub fn cleanup_key(&self) -> (String, PeerId, Vec<u8>, String) {
-        let particle_id = self.particle.id.clone();
-        let signature = self.particle.signature.clone();
-        let token = self.particle_token.clone();
-        (particle_id, self.current_peer_id, signature, token)
+    pub fn is_priority(&self) -> bool {
+        self.priority
+    }
+
+    /// Id of the VM that is lost if the actor is dropped in the middle of execution
+    pub fn executing_vm_id(&self) -> Option<usize> {
+        self.executing_vm_id
+    }
+
+    /// Time the current execution is running for, `None` if nothing is executing
+    pub fn execution_elapsed(&self, now_ms: u64) -> Option<Duration> {
+        self.execution_started_at
+            .map(|started_at| Duration::from_millis(now_ms.saturating_sub(started_at)))
+    }
+
+    /// Time the particle is allowed to live for
+    pub fn ttl(&self) -> Duration {
+        Duration::from_millis(self.particle.ttl as u64)
+    }
+
+    /// Drops the in-flight AVM call, returns id of the VM that was lost with it
+    pub fn cancel_execution(&mut self) -> Option<usize> {
+        self.future.take();
+        self.execution_started_at.take();
+        self.vm_permit.take();
+        self.executing_vm_id.take()
+    }
+
+    /// Holds the permit until the current execution is finished
+    pub fn hold_vm_permit(&mut self, permit: OwnedSemaphorePermit) {
+        self.vm_permit = Some(permit);
+    }
+
+    pub fn has_pending_calls(&self) -> bool {
+        !self.functions.is_idle()
+    }
+
+    pub fn execution_time(&self) -> Duration {
+        self.execution_time
+    }
+
+    pub fn particle_id(&self) -> &str {
+        &self.particle.id
+    }
+
+    pub fn init_peer_id(&self) -> PeerId {
+        self.particle.init_peer_id
+    }
+
+    pub fn cleanup_key(&self) -> CleanupKey {
+        CleanupKey {
+            particle_id: self.particle.id.clone(),
+            peer_id: self.current_peer_id,
+            signature: self.particle.signature.clone(),
+            particle_token: self.particle_token.clone(),
+        }
     }
 
     pub fn mailbox_size(&self) -> usize {
         self.mailbox.len()
     }
 
+    /// Particles waiting to be executed, in the order of execution
+    pub fn mailbox(&self) -> impl Iterator<Item = &ExtendedParticle> {
+        self.mailbox.iter()
+    }
+
     pub fn set_function(&mut self, function: ServiceFunction) {
         self.functions.set_function(function)
     }
 
     #[instrument(level = tracing::Level::INFO, skip_all)]
     pub fn ingest(&mut self, particle: ExtendedParticle) {
+        self.hops = self.hops.max(particle.hops);
+        let memory = particle_memory_estimate(&particle.particle);
+        self.memory += memory;
+        self.load.add(1, memory);
         self.mailbox.push_back(particle);
         self.wake();
     }
 
     /// Polls actor for result on previously ingested particle
-    pub fn poll_completed(
-        &mut self,
-        cx: &mut Context<'_>,
-    ) -> Poll<FutResult<(usize, Option<RT>), RawRoutingEffects, InterpretationStats>> {
+    pub fn poll_completed(&mut self, cx: &mut Context<'_>) -> Poll<AVMCallResult<RT>> {
         self.waker = Some(cx.waker().clone());
 
         self.functions.poll(cx);
@@ -167,6 +291,10 @@ where
             let _span_guard = span.enter();
 
             self.future.take();
+            self.executing_vm_id.take();
+            self.execution_started_at.take();
+            self.vm_permit.take();
+            self.execution_time += stats.interpretation_time;
 
             let spawner = self.spawner.clone();
             let waker = cx.waker().clone();
@@ -186,7 +314,8 @@ where
                         ..self.particle.clone()
                     },
                     parent_span,
-                ),
+                )
+                .with_hops(self.hops),
                 next_peers: effects.next_peers,
             };
             return Some(Poll::Ready(FutResult {
@@ -202,7 +331,14 @@ where
     ///
     /// If actor is in the middle of executing previous particle, vm is returned
     /// If actor's mailbox is empty, vm is returned
-    pub fn poll_next(&mut self, vm_id: usize, vm: RT, cx: &mut Context<'_>) -> ActorPoll<RT> {
+    /// Execution is abandoned if it doesn't finish before the particle expires
+    pub fn poll_next(
+        &mut self,
+        vm_id: usize,
+        vm: RT,
+        now_ms: u64,
+        cx: &mut Context<'_>,
+    ) -> ActorPoll<RT> {
         self.waker = Some(cx.waker().clone());
 
         self.functions.poll(cx);
@@ -217,6 +353,11 @@ where
 
         // Take the next particle
         let ext_particle = self.mailbox.pop_front();
+        if let Some(ext_particle) = &ext_particle {
+            let memory = particle_memory_estimate(&ext_particle.particle);
+            self.memory -= memory;
+            self.load.sub(1, memory);
+        }
 
         if ext_particle.is_none() && calls.is_empty() {
             debug_assert!(stats.is_empty(), "stats must be empty if calls are empty");
@@ -237,6 +378,7 @@ where
         let data_store = self.data_store.clone();
         let key_pair = self.key_pair.clone();
         let peer_id = self.current_peer_id;
+        let timeout = self.deadline.remaining(now_ms);
 
         let (async_span, linking_span) =
             self.create_spans(call_spans, ext_particle, particle.id.as_str());
@@ -252,6 +394,7 @@ where
                             (particle.clone(), calls),
                             peer_id,
                             key_pair,
+                            timeout,
                         )
                         .in_current_span()
                         .await;
@@ -268,6 +411,8 @@ where
                 .instrument(async_span)
                 .boxed(),
         );
+        self.executing_vm_id = Some(vm_id);
+        self.execution_started_at = Some(now_ms);
         self.wake();
 
         ActorPoll::Executing(stats)
@@ -300,6 +445,21 @@ where
     }
 }
 
+impl<RT, F, DS> Drop for Actor<RT, F, DS> {
+    fn drop(&mut self) {
+        self.load.sub(self.mailbox.len(), self.memory);
+    }
+}
+
+/// Approximate number of bytes held by the particle
+pub fn particle_memory_estimate(particle: &Particle) -> usize {
+    std::mem::size_of::<Particle>()
+        + particle.id.len()
+        + particle.script.len()
+        + particle.signature.len()
+        + particle.data.len()
+}
+
 pub enum ActorPoll<RT> {
     Executing(Vec<SingleCallStat>),
     Vm(usize, RT),
@@ -19,17 +19,17 @@ use std::sync::Arc;
 use std::task::Poll;
 use std::time::Duration;
 
-use futures::StreamExt;
 use marine_wasmtime_backend::WasmtimeWasmBackend;
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tracing::{instrument, Instrument};
 
 use health::HealthCheckRegistry;
 use particle_execution::{ParticleFunctionStatic, ServiceFunction};
 use particle_protocol::ExtendedParticle;
 use particle_services::{PeerScope, WasmBackendConfig};
-use peer_metrics::{ParticleExecutorMetrics, VmPoolMetrics};
+use peer_metrics::{MetricsSink, ParticleOrigin, VmPoolMetrics, WorkerLabel, WorkerType};
 use workers::{Event, KeyStorage, PeerScopes, Receiver, Workers};
 
 use crate::command::Command;
@@ -37,7 +37,8 @@ use crate::command::Command::{AddService, Ingest, RemoveService};
 use crate::error::AquamarineApiError;
 use crate::vm_pool::VmPool;
 use crate::{
-    AquaRuntime, DataStoreConfig, ParticleDataStore, Plumber, RemoteRoutingEffects, VmPoolConfig,
+    AquaRuntime, DataStoreConfig, ParticleDataStore, Plumber, PlumberConfig, RemoteRoutingEffects,
+    VmPoolConfig,
 };
 
 pub type EffectsChannel = mpsc::Sender<Result<RemoteRoutingEffects, AquamarineApiError>>;
@@ -57,9 +58,10 @@ impl<RT: AquaRuntime, F: ParticleFunctionStatic> AquamarineBackend<RT, F> {
         vm_config: RT::Config,
         avm_wasm_backend_config: WasmBackendConfig,
         data_store_config: DataStoreConfig,
+        plumber_config: PlumberConfig,
         builtins: F,
         out: EffectsChannel,
-        plumber_metrics: Option<ParticleExecutorMetrics>,
+        plumber_metrics: Option<Arc<dyn MetricsSink>>,
         vm_pool_metrics: Option<VmPoolMetrics>,
         health_registry: Option<&mut HealthCheckRegistry>,
         workers: Arc<Workers>,
@@ -75,19 +77,28 @@ impl<RT: AquaRuntime, F: ParticleFunctionStatic> AquamarineBackend<RT, F> {
             data_store_config.particles_dir,
             data_store_config.particles_vault_dir,
             data_store_config.particles_anomaly_dir,
+        )
+        .with_cleanup_parallelism(data_store_config.cleanup_parallelism)
+        .with_prefetch_limits(
+            data_store_config.prefetch_capacity,
+            data_store_config.prefetch_ttl,
         );
         let data_store: Arc<ParticleDataStore> = Arc::new(data_store);
         let avm_wasm_backend = WasmtimeWasmBackend::new(avm_wasm_backend_config.into())?;
 
+        let host_label = WorkerLabel::new(WorkerType::Host, scopes.get_host_peer_id().to_string());
         let vm_pool = VmPool::new(
             config.pool_size,
             vm_config.clone(),
             vm_pool_metrics,
+            host_label,
             health_registry,
             avm_wasm_backend.clone(),
+            config.recreation_policy,
         );
         let plumber = Plumber::new(
             vm_config,
+            plumber_config,
             vm_pool,
             data_store.clone(),
             builtins,
@@ -120,16 +131,27 @@ impl<RT: AquaRuntime, F: ParticleFunctionStatic> AquamarineBackend<RT, F> {
                     let _guard = span.entered();
                     // set new particle to be executed
                     // every particle that comes from the connection pool first executed on the host peer id
-                    self.plumber.ingest(particle, function, PeerScope::Host);
+                    self.plumber.ingest(
+                        particle,
+                        function,
+                        PeerScope::Host,
+                        ParticleOrigin::Network,
+                    );
                 }
                 Poll::Ready(Some(AddService {
                     service,
                     functions,
                     fallback,
-                })) => self.plumber.add_service(service, functions, fallback),
+                })) => {
+                    if let Err(err) = self.plumber.add_service(service, functions, fallback) {
+                        log::error!("Could not add service: {}", err);
+                    }
+                }
 
                 Poll::Ready(Some(RemoveService { service })) => {
-                    self.plumber.remove_service(service)
+                    if let Err(err) = self.plumber.remove_service(service) {
+                        log::error!("Could not remove service: {}", err);
+                    }
                 }
 
                 Poll::Pending | Poll::Ready(None) => break,
@@ -178,20 +200,40 @@ impl<RT: AquaRuntime, F: ParticleFunctionStatic> AquamarineBackend<RT, F> {
         wake
     }
 
-    pub fn start(mut self) -> JoinHandle<()> {
+    /// Once `stop` is cancelled, the task stops taking new particles and finishes
+    /// with `Plumber::shutdown`, which waits for the interpretations in progress
+    /// no longer than the configured `shutdown_timeout`
+    pub fn start(mut self, stop: CancellationToken) -> JoinHandle<()> {
         let data_store = self.data_store.clone();
-        let mut stream = futures::stream::poll_fn(move |cx| self.poll(cx).map(|_| Some(()))).fuse();
         let result = tokio::task::Builder::new()
             .name("Aquamarine")
             .spawn(
                 async move {
-                    data_store
-                        .initialize()
-                        .await
-                        .expect("Could not initialize data store");
+                    if let Err(err) = data_store.initialize().await {
+                        tracing::error!("Could not initialize data store: {err}, repairing it");
+                        let repaired = match data_store.repair().await {
+                            Ok(()) => data_store.initialize().await,
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = repaired {
+                            tracing::error!("Could not repair data store, particle data may not be persisted: {err}");
+                        }
+                    }
+                    // Pools of the workers loaded on start are created before their particles
+                    // are restored, so they aren't rejected for a missing pool
+                    self.process_worker_events();
+                    if let Err(err) = self.plumber.restore().await {
+                        tracing::error!("Could not restore particles queued before restart: {err}");
+                    }
                     loop {
-                        stream.next().await;
+                        tokio::select! {
+                            _ = futures::future::poll_fn(|cx| self.poll(cx)) => {},
+                            _ = stop.cancelled() => break,
+                        }
                     }
+
+                    tracing::info!("Stopping Aquamarine");
+                    self.plumber.shutdown().await;
                 }
                 .in_current_span(),
             )
@@ -15,7 +15,12 @@
  */
 
 use fs_utils::to_abs_path;
+
+use crate::particle_data_store::{
+    DEFAULT_CLEANUP_PARALLELISM, DEFAULT_PREFETCH_CAPACITY, DEFAULT_PREFETCH_TTL,
+};
 use libp2p::PeerId;
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::time::Duration;
 
@@ -42,6 +47,40 @@ pub struct VmPoolConfig {
     pub pool_size: usize,
     /// Timeout of a particle execution
     pub execution_timeout: Duration,
+    /// How lost VMs are recreated
+    pub recreation_policy: VmRecreationPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct VmRecreationPolicy {
+    /// Delay before recreating a lost VM, doubled for every rapid recreation in a row
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Recreation is rapid if it happens within that window after the previous one
+    pub rapid_window: Duration,
+    /// Pool is marked degraded and stops recreating VMs after that many rapid recreations in a row,
+    /// never if `None`
+    pub max_rapid_recreations: Option<usize>,
+    /// Degraded pool tries to recreate one VM after that delay and recovers if it succeeds
+    pub degraded_cooldown: Duration,
+    /// Idle VM is recreated after that many executions to reclaim memory held by the Wasm engine
+    pub max_vm_executions: Option<usize>,
+    /// Idle VM is recreated once it lives that long
+    pub max_vm_age: Option<Duration>,
+}
+
+impl Default for VmRecreationPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            rapid_window: Duration::from_secs(30),
+            max_rapid_recreations: None,
+            degraded_cooldown: Duration::from_secs(60),
+            max_vm_executions: None,
+            max_vm_age: None,
+        }
+    }
 }
 
 impl VmConfig {
@@ -71,10 +110,156 @@ impl VmPoolConfig {
         Self {
             pool_size,
             execution_timeout,
+            recreation_policy: <_>::default(),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct PlumberConfig {
+    /// How long a particle waits for its worker's runtime to appear before being dropped
+    pub worker_runtime_wait: Duration,
+    /// Max number of undelivered effects kept for inspection, the oldest are evicted first
+    pub max_dead_letters: usize,
+    /// Max number of times a particle may be re-ingested locally as an effect of its own execution
+    pub max_local_hops: u32,
+    /// Plumber is saturated when there are no free VMs and actors' mailboxes hold at least that many particles
+    pub saturation_mailbox_threshold: usize,
+    /// Max number of verified particle signatures remembered to skip re-verification, 0 disables the cache
+    pub signature_cache_size: usize,
+    /// Max number of particles whose data is removed in a single cleanup
+    pub cleanup_batch_size: usize,
+    /// Verify particle signatures on the blocking thread pool instead of the poll thread
+    pub offload_signature_verification: bool,
+    /// What particles are routed to the same actor
+    pub actor_keying: ActorKeying,
+    /// Max number of add_service/remove_service tasks running at once, the rest wait for a slot
+    pub max_service_tasks: usize,
+    /// Actor is evicted once its executions took longer than that in total, regardless of TTL
+    pub max_actor_execution_time: Option<Duration>,
+    /// If set, interpretation running longer than the particle's TTL multiplied by that factor
+    /// is cancelled, and its VM is recreated
+    pub execution_grace_factor: Option<f64>,
+    /// If set, only particles initiated by these peers are admitted. Host and management peers are always admitted
+    pub peer_allowlist: Option<HashSet<PeerId>>,
+    /// Particles initiated by these peers are rejected. Host and management peers are always admitted
+    pub peer_blocklist: HashSet<PeerId>,
+    /// Particles expired no longer than that many milliseconds ago are still admitted.
+    /// If set, particles created later than that many milliseconds from now are rejected.
+    pub clock_skew_tolerance_ms: Option<u64>,
+    /// If set, actors created while the plumber is saturated expire no later than that from now
+    pub saturated_ttl: Option<Duration>,
+    /// If set, worker pools are queued and at most that many of them are created per poll
+    pub worker_pools_per_poll: Option<usize>,
+    /// Evict actor right after its particle is routed only to remote peers, if it has nothing else to do.
+    /// Data of the evicted particle is removed, so a particle coming back is executed from scratch
+    pub evict_remote_only_actors: bool,
+    /// If set, expired actors are cleaned up once in that interval instead of on every poll
+    pub cleanup_interval: Option<Duration>,
+    /// Each cleanup interval is prolonged by a random part of that fraction of it,
+    /// so nodes restarted together don't clean up at the same time
+    pub cleanup_jitter: f64,
+    /// Seed of the cleanup jitter, random if not set
+    pub cleanup_jitter_seed: Option<u64>,
+    /// Effects addressed to that peer are re-ingested to the host instead of being sent over the network
+    pub loopback_peer: Option<PeerId>,
+    /// Drop and report effects addressed to peers that aren't local instead of sending them
+    /// to the network. Meant to catch misrouted particles in single-node deployments
+    pub strict_peer_scopes: bool,
+    /// If set, VM pool of a worker is removed after no particles were executed on it for that long.
+    /// The pool is created again when a particle for the worker arrives
+    pub worker_pool_idle_timeout: Option<Duration>,
+    /// If set, new particles are shed while the estimated memory held by the buffered events,
+    /// mailboxes and actors exceeds that many bytes
+    pub memory_budget: Option<usize>,
+    /// Report particles whose script finished with a nonzero return code as `AquamarineApiError::AvmError`
+    pub report_avm_errors: bool,
+    /// If set to more than 1 and the plumber runs on a multi-threaded runtime, actors of different
+    /// workers are polled in parallel on their worker runtimes, at most that many workers at once
+    pub worker_polling_threads: Option<usize>,
+    /// If set, at most that many VMs execute particles at once across the host and all worker pools.
+    /// Otherwise it's bounded only by the sum of the pool sizes
+    pub max_executing_vms: Option<usize>,
+    /// Reject particles of a worker that has no VM pool with `AquamarineApiError::NoWorkerPool`.
+    /// Otherwise they wait in the mailboxes until the pool is created
+    pub reject_without_worker_pool: bool,
+    /// What to do with particles for a worker whose VM pool has no VMs
+    pub no_capacity_policy: NoCapacityPolicy,
+    /// Max number of particles waiting in the mailbox of a host actor, unbounded if not set
+    pub max_host_mailbox_size: Option<usize>,
+    /// Max number of particles waiting in the mailbox of a worker actor, unbounded if not set
+    pub max_worker_mailbox_size: Option<usize>,
+    /// How long `Plumber::shutdown` waits for the interpretations in progress to finish
+    pub shutdown_timeout: Duration,
+    /// If set, `Plumber::poll` yields to the runtime once it runs longer than that,
+    /// which is also checked between the polled actors,
+    /// and resumes from the same stage and actor on the next poll
+    pub poll_time_budget: Option<Duration>,
+    /// A warning is logged when the memory of an AquaVM reaches that fraction of its memory limit
+    pub memory_limit_warning_ratio: f64,
+}
+
+impl Default for PlumberConfig {
+    fn default() -> Self {
+        Self {
+            worker_runtime_wait: Duration::from_secs(1),
+            max_dead_letters: 1024,
+            max_local_hops: 1000,
+            saturation_mailbox_threshold: 10_000,
+            signature_cache_size: 1024,
+            cleanup_batch_size: 1024,
+            offload_signature_verification: false,
+            actor_keying: <_>::default(),
+            max_service_tasks: 64,
+            max_actor_execution_time: None,
+            execution_grace_factor: None,
+            peer_allowlist: None,
+            peer_blocklist: <_>::default(),
+            clock_skew_tolerance_ms: None,
+            saturated_ttl: None,
+            worker_pools_per_poll: None,
+            evict_remote_only_actors: false,
+            cleanup_interval: None,
+            cleanup_jitter: 0.0,
+            cleanup_jitter_seed: None,
+            loopback_peer: None,
+            strict_peer_scopes: false,
+            worker_pool_idle_timeout: None,
+            memory_budget: None,
+            report_avm_errors: false,
+            worker_polling_threads: None,
+            max_executing_vms: None,
+            reject_without_worker_pool: false,
+            no_capacity_policy: <_>::default(),
+            max_host_mailbox_size: None,
+            max_worker_mailbox_size: None,
+            shutdown_timeout: Duration::from_secs(10),
+            poll_time_budget: None,
+            memory_limit_warning_ratio: 0.9,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ActorKeying {
+    /// Particles with the same signature share an actor
+    #[default]
+    Signature,
+    /// Particles share an actor only if their signature, init peer id and particle id match
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoCapacityPolicy {
+    /// Particles wait in the mailboxes until the pool gets VMs
+    #[default]
+    Keep,
+    /// Particles are rejected with `AquamarineApiError::NoCapacity`
+    Reject,
+    /// Up to that many particles wait in the mailboxes, the rest are rejected
+    Queue { max_particles: usize },
+}
+
 #[derive(Debug, Clone)]
 pub struct DataStoreConfig {
     /// Dir for the interpreter to persist particle data
@@ -85,6 +270,12 @@ pub struct DataStoreConfig {
     pub particles_vault_dir: PathBuf,
     /// Dir to store particles data of AquaVM performance anomalies
     pub particles_anomaly_dir: PathBuf,
+    /// Max number of particles whose data is removed at once
+    pub cleanup_parallelism: usize,
+    /// Max number of prefetched particle data entries kept in memory
+    pub prefetch_capacity: usize,
+    /// Prefetched particle data not read within that time is dropped
+    pub prefetch_ttl: Duration,
 }
 
 impl DataStoreConfig {
@@ -94,6 +285,9 @@ impl DataStoreConfig {
             particles_dir: config_utils::particles_dir(&base_dir),
             particles_vault_dir: config_utils::particles_vault_dir(&base_dir),
             particles_anomaly_dir: config_utils::particles_anomaly_dir(&base_dir),
+            cleanup_parallelism: DEFAULT_CLEANUP_PARALLELISM,
+            prefetch_capacity: DEFAULT_PREFETCH_CAPACITY,
+            prefetch_ttl: DEFAULT_PREFETCH_TTL,
         }
     }
 }
@@ -14,6 +14,8 @@
  * limitations under the License.
  */
 
+use std::time::Duration;
+
 use particle_protocol::Particle;
 
 #[derive(Debug, Clone)]
@@ -22,6 +24,8 @@ pub struct Deadline {
     timestamp: u64,
     // TTL in milliseconds
     ttl: u32,
+    // Allowed difference between the clocks of the particle's creator and ours, in milliseconds
+    clock_skew_tolerance: u64,
 }
 
 impl Deadline {
@@ -29,12 +33,21 @@ impl Deadline {
         Self {
             timestamp: particle.timestamp,
             ttl: particle.ttl,
+            clock_skew_tolerance: 0,
+        }
+    }
+
+    pub fn with_clock_skew_tolerance(self, clock_skew_tolerance: u64) -> Self {
+        Self {
+            clock_skew_tolerance,
+            ..self
         }
     }
 
     pub fn is_expired(&self, now_ms: u64) -> bool {
         self.timestamp
             .checked_add(self.ttl as u64)
+            .and_then(|ts| ts.checked_add(self.clock_skew_tolerance))
             // Whether ts is in the past
             .map(|ts| ts < now_ms)
             // If timestamp + ttl gives overflow, consider particle expired
@@ -43,4 +56,25 @@ impl Deadline {
                 true
             })
     }
+
+    /// Time left until the particle expires
+    pub fn remaining(&self, now_ms: u64) -> Duration {
+        let expires_at = self
+            .timestamp
+            .saturating_add(self.ttl as u64)
+            .saturating_add(self.clock_skew_tolerance);
+        Duration::from_millis(expires_at.saturating_sub(now_ms))
+    }
+
+    /// Shortens the deadline so it comes no later than `window_ms` from `now_ms`
+    pub fn clamp(self, now_ms: u64, window_ms: u64) -> Self {
+        let latest = now_ms.saturating_add(window_ms);
+        let ttl = latest.saturating_sub(self.timestamp).min(self.ttl as u64) as u32;
+        Self { ttl, ..self }
+    }
+
+    /// Whether the particle was created later than `now_ms`, even considering clock skew
+    pub fn is_from_future(&self, now_ms: u64) -> bool {
+        self.timestamp > now_ms.saturating_add(self.clock_skew_tolerance)
+    }
 }
@@ -57,6 +57,65 @@ pub enum AquamarineApiError {
         worker_id: String,
         particle_id: String,
     },
+    #[error("AquamarineApiError::MaxHopsExceeded: particle_id = {particle_id}, hops = {hops}")]
+    MaxHopsExceeded { particle_id: String, hops: u32 },
+    #[error("AquamarineApiError::Overloaded: particle_id = {particle_id}")]
+    Overloaded { particle_id: String },
+    #[error("AquamarineApiError::BudgetExceeded: particle_id = {particle_id}, budget = {budget}")]
+    BudgetExceeded {
+        particle_id: String,
+        budget: FormattedDuration,
+    },
+    #[error("AquamarineApiError::PeerNotAllowed: particle_id = {particle_id}, init_peer_id = {init_peer_id}")]
+    PeerNotAllowed {
+        particle_id: String,
+        init_peer_id: String,
+    },
+    #[error("AquamarineApiError::ParticleFromFuture: particle_id = {particle_id}, timestamp = {timestamp}")]
+    ParticleFromFuture { particle_id: String, timestamp: u64 },
+    #[error(
+        "AquamarineApiError::NoWorkerPool: worker_id = {worker_id}, particle_id = {particle_id}"
+    )]
+    NoWorkerPool {
+        worker_id: String,
+        particle_id: String,
+    },
+    #[error(
+        "AquamarineApiError::NoCapacity: worker_id = {worker_id}, particle_id = {particle_id}"
+    )]
+    NoCapacity {
+        worker_id: String,
+        particle_id: String,
+    },
+    #[error(
+        "AquamarineApiError::NoSuchWorker: worker_id = {worker_id}, particle_id = {particle_id}"
+    )]
+    NoSuchWorker {
+        worker_id: String,
+        particle_id: String,
+    },
+    #[error(
+        "AquamarineApiError::WorkerRuntimeNotFound: worker_id = {worker_id}, particle_id = {particle_id}"
+    )]
+    WorkerRuntimeNotFound {
+        worker_id: String,
+        particle_id: String,
+    },
+    #[error("AquamarineApiError::MailboxFull: particle_id = {particle_id}")]
+    MailboxFull { particle_id: String },
+    #[error("AquamarineApiError::AvmError: particle_id = {particle_id}, ret_code = {ret_code}, message = {message}")]
+    AvmError {
+        particle_id: String,
+        ret_code: i64,
+        message: String,
+    },
+    #[error(
+        "AquamarineApiError::ExecutionTimeout: particle_id = {particle_id}, timeout = {timeout}"
+    )]
+    ExecutionTimeout {
+        particle_id: String,
+        timeout: FormattedDuration,
+    },
 }
 
 impl AquamarineApiError {
@@ -66,6 +125,18 @@ impl AquamarineApiError {
             AquamarineApiError::OneshotCancelled { particle_id } => Some(particle_id),
             AquamarineApiError::ExecutionTimedOut { particle_id, .. } => Some(particle_id),
             AquamarineApiError::WorkerIsNotActive { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::MaxHopsExceeded { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::Overloaded { particle_id } => Some(particle_id),
+            AquamarineApiError::BudgetExceeded { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::PeerNotAllowed { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::ParticleFromFuture { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::NoWorkerPool { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::NoCapacity { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::NoSuchWorker { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::WorkerRuntimeNotFound { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::MailboxFull { particle_id } => Some(particle_id),
+            AquamarineApiError::AvmError { particle_id, .. } => Some(particle_id),
+            AquamarineApiError::ExecutionTimeout { particle_id, .. } => Some(particle_id),
             // Should it be `None`  considering usage of signature as particle id?
             // It can compromise valid particles into thinking they are invalid.
             // But still there can be a case when signature was generated wrong
@@ -77,6 +148,12 @@ impl AquamarineApiError {
     }
 }
 
+#[derive(Debug, Error)]
+pub enum ServiceTaskError {
+    #[error("Could not spawn service task: {0}")]
+    Spawn(#[from] std::io::Error),
+}
+
 impl std::error::Error for ExecutionError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match &self {
@@ -36,7 +36,10 @@ mod log;
 mod particle_data_store;
 mod particle_executor;
 mod particle_functions;
+mod particle_token;
 mod plumber;
+mod prefetch_cache;
+mod signature_cache;
 mod spawner;
 
 mod aqua_runtime;
@@ -48,11 +51,21 @@ mod vm_pool;
 
 pub use crate::aqua_runtime::AquaRuntime;
 pub use crate::aquamarine::{AquamarineApi, AquamarineBackend};
-pub use crate::config::{DataStoreConfig, VmConfig, VmPoolConfig};
+pub use crate::config::{
+    ActorKeying, DataStoreConfig, NoCapacityPolicy, PlumberConfig, VmConfig, VmPoolConfig,
+    VmRecreationPolicy,
+};
 pub use crate::particle_effects::{InterpretationStats, ParticleEffects, RemoteRoutingEffects};
 pub type AVMRunner = avm_server::avm_runner::AVMRunner<WasmtimeWasmBackend>;
-pub use error::AquamarineApiError;
+pub use error::{AquamarineApiError, ServiceTaskError};
 pub use marine_wasmtime_backend::WasmtimeWasmBackend;
-pub use particle_data_store::{DataStoreError, ParticleDataStore};
+pub use particle_data_store::{
+    CleanupKey, CleanupReport, DataStore, DataStoreError, ParticleDataStore, QueuedParticle,
+};
 pub use particle_services::WasmBackendConfig;
-pub use plumber::Plumber;
+pub use particle_token::{ParticleTokenSigner, RootKeyPairSigner};
+pub use plumber::{
+    ActorInspection, DeadLetter, DealUsage, IngestOutcome, Plumber, RejectReason, ResetMode,
+    ResetReport,
+};
+pub use vm_pool::VmPoolConfigSummary;
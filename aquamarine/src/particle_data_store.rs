@@ -15,28 +15,122 @@
  */
 
 use std::borrow::Cow;
+use std::future::Future;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
+use async_trait::async_trait;
 use avm_server::avm_runner::RawAVMOutcome;
 use avm_server::{AnomalyData, CallResults, ParticleParameters};
 use fluence_libp2p::PeerId;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::instrument;
 
 use now_millis::now_ms;
 use particle_execution::{ParticleVault, VaultError};
+use particle_protocol::Particle;
+use types::peer_scope::PeerScope;
+
+use crate::prefetch_cache::PrefetchCache;
 
 type Result<T> = std::result::Result<T, DataStoreError>;
 
+/// Identifies all the data an actor leaves behind: particle data and the particle vault
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CleanupKey {
+    pub particle_id: String,
+    /// Peer id in whose scope the particle was executed
+    pub peer_id: PeerId,
+    pub signature: Vec<u8>,
+    /// Particle token, the vault of the particle is keyed by it
+    pub particle_token: String,
+}
+
+/// Outcome of `batch_cleanup_data`
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    /// Number of keys whose data was removed
+    pub cleaned: usize,
+    /// Keys whose data couldn't be removed, along with the reason
+    pub failed: Vec<(CleanupKey, DataStoreError)>,
+}
+
+/// Particle that was waiting in an actor's mailbox when the plumber was stopped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedParticle {
+    pub particle: Particle,
+    pub peer_scope: PeerScope,
+    /// Local re-ingest depth of the particle
+    pub hops: u32,
+}
+
+/// Storage of the data particles leave behind between executions
+#[async_trait]
+pub trait DataStore: Send + Sync + 'static {
+    async fn initialize(&self) -> Result<()>;
+
+    async fn store_data(
+        &self,
+        data: &[u8],
+        particle_id: &str,
+        current_peer_id: &str,
+        signature: &[u8],
+    ) -> Result<()>;
+
+    async fn read_data(
+        &self,
+        particle_id: &str,
+        current_peer_id: &str,
+        signature: &[u8],
+    ) -> Result<Vec<u8>>;
+
+    async fn batch_cleanup_data(&self, cleanup_keys: Vec<CleanupKey>) -> CleanupReport;
+
+    /// Saves particles left in the actors' mailboxes, replacing the previous snapshot
+    async fn store_mailboxes(&self, particles: &[QueuedParticle]) -> Result<()>;
+
+    /// Takes the saved snapshot of the mailboxes, so it's restored only once
+    async fn take_mailboxes(&self) -> Result<Vec<QueuedParticle>>;
+
+    fn detect_anomaly(
+        &self,
+        execution_time: Duration,
+        memory_delta: usize,
+        outcome: &RawAVMOutcome,
+    ) -> bool;
+
+    #[allow(clippy::too_many_arguments)]
+    async fn save_anomaly_data(
+        &self,
+        air_script: &str,
+        current_data: &[u8],
+        call_results: &CallResults,
+        particle_parameters: &ParticleParameters<'_>,
+        particle_signature: &[u8],
+        outcome: &RawAVMOutcome,
+        execution_time: Duration,
+        memory_delta: usize,
+    ) -> Result<()>;
+}
+
 #[derive(Debug, Clone)]
 pub struct ParticleDataStore {
     pub particle_data_store: PathBuf,
     pub vault: ParticleVault,
     pub anomaly_data_store: PathBuf,
+    /// Particle data loaded by `prefetch`, each entry is served once by `read_data`
+    prefetched: Arc<Mutex<PrefetchCache>>,
+    /// Number of particle data files read from disk
+    disk_reads: Arc<AtomicUsize>,
+    /// Max number of particles whose data is removed at once by `batch_cleanup_data`
+    cleanup_parallelism: usize,
 }
 
 impl ParticleDataStore {
@@ -49,9 +143,40 @@ impl ParticleDataStore {
             particle_data_store,
             vault: ParticleVault::new(vault_dir),
             anomaly_data_store,
+            prefetched: Arc::new(Mutex::new(PrefetchCache::new(
+                DEFAULT_PREFETCH_CAPACITY,
+                DEFAULT_PREFETCH_TTL,
+            ))),
+            disk_reads: <_>::default(),
+            cleanup_parallelism: DEFAULT_CLEANUP_PARALLELISM,
+        }
+    }
+
+    pub fn with_cleanup_parallelism(self, cleanup_parallelism: usize) -> Self {
+        Self {
+            cleanup_parallelism: cleanup_parallelism.max(1),
+            ..self
         }
     }
 
+    /// Keeps at most `capacity` prefetched entries, each for at most `ttl`
+    pub fn with_prefetch_limits(self, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            prefetched: Arc::new(Mutex::new(PrefetchCache::new(capacity, ttl))),
+            ..self
+        }
+    }
+
+    /// Number of particle data files read from disk so far
+    pub fn disk_reads(&self) -> usize {
+        self.disk_reads.load(Ordering::Relaxed)
+    }
+
+    async fn read_file(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        self.disk_reads.fetch_add(1, Ordering::Relaxed);
+        tokio::fs::read(path).await
+    }
+
     pub fn data_file(&self, particle_id: &str, current_peer_id: &str, signature: &[u8]) -> PathBuf {
         let key = store_key_from_components(particle_id, current_peer_id, signature);
         self.particle_data_store.join(key)
@@ -75,16 +200,142 @@ impl ParticleDataStore {
     }
 }
 
+#[async_trait]
+impl DataStore for ParticleDataStore {
+    async fn initialize(&self) -> Result<()> {
+        ParticleDataStore::initialize(self).await
+    }
+
+    async fn store_data(
+        &self,
+        data: &[u8],
+        particle_id: &str,
+        current_peer_id: &str,
+        signature: &[u8],
+    ) -> Result<()> {
+        ParticleDataStore::store_data(self, data, particle_id, current_peer_id, signature).await
+    }
+
+    async fn read_data(
+        &self,
+        particle_id: &str,
+        current_peer_id: &str,
+        signature: &[u8],
+    ) -> Result<Vec<u8>> {
+        ParticleDataStore::read_data(self, particle_id, current_peer_id, signature).await
+    }
+
+    async fn batch_cleanup_data(&self, cleanup_keys: Vec<CleanupKey>) -> CleanupReport {
+        ParticleDataStore::batch_cleanup_data(self, cleanup_keys).await
+    }
+
+    async fn store_mailboxes(&self, particles: &[QueuedParticle]) -> Result<()> {
+        ParticleDataStore::store_mailboxes(self, particles).await
+    }
+
+    async fn take_mailboxes(&self) -> Result<Vec<QueuedParticle>> {
+        ParticleDataStore::take_mailboxes(self).await
+    }
+
+    fn detect_anomaly(
+        &self,
+        execution_time: Duration,
+        memory_delta: usize,
+        outcome: &RawAVMOutcome,
+    ) -> bool {
+        ParticleDataStore::detect_anomaly(self, execution_time, memory_delta, outcome)
+    }
+
+    async fn save_anomaly_data(
+        &self,
+        air_script: &str,
+        current_data: &[u8],
+        call_results: &CallResults,
+        particle_parameters: &ParticleParameters<'_>,
+        particle_signature: &[u8],
+        outcome: &RawAVMOutcome,
+        execution_time: Duration,
+        memory_delta: usize,
+    ) -> Result<()> {
+        ParticleDataStore::save_anomaly_data(
+            self,
+            air_script,
+            current_data,
+            call_results,
+            particle_parameters,
+            particle_signature,
+            outcome,
+            execution_time,
+            memory_delta,
+        )
+        .await
+    }
+}
+
+pub const DEFAULT_CLEANUP_PARALLELISM: usize = 64;
+pub const DEFAULT_PREFETCH_CAPACITY: usize = 1024;
+pub const DEFAULT_PREFETCH_TTL: Duration = Duration::from_secs(60);
 const EXECUTION_TIME_THRESHOLD: Duration = Duration::from_millis(500);
 const MEMORY_DELTA_BYTES_THRESHOLD: usize = 10 * bytesize::MB as usize;
+/// File in the particle data store that holds the mailboxes snapshot
+const MAILBOXES_FILE: &str = "mailboxes.json";
 
 impl ParticleDataStore {
     pub async fn initialize(&self) -> Result<()> {
-        tokio::fs::create_dir_all(&self.particle_data_store)
-            .await
-            .map_err(DataStoreError::CreateDataStore)?;
+        create_store_dir(&self.particle_data_store).await?;
 
-        self.vault.initialize().await?;
+        self.initialize_vault().await?;
+
+        Ok(())
+    }
+
+    async fn initialize_vault(&self) -> Result<()> {
+        self.vault.initialize().await.map_err(|err| match err {
+            VaultError::InitializeVault(err) => io_error(err, self.vault.vault_dir()),
+            err => err.into(),
+        })
+    }
+
+    /// Brings directories of the store back to a state `initialize` can work with:
+    /// moves aside files occupying their paths and creates the missing ones
+    pub async fn repair(&self) -> Result<()> {
+        let dirs = [
+            self.particle_data_store.as_path(),
+            self.anomaly_data_store.as_path(),
+            self.vault.vault_dir(),
+        ];
+        for dir in dirs {
+            if is_dangling_symlink(dir) {
+                // the link is kept, the directory it points to is created
+                let target = tokio::fs::read_link(dir)
+                    .await
+                    .map_err(|err| io_error(err, dir))?;
+                let target = match dir.parent() {
+                    Some(parent) => parent.join(target),
+                    None => target,
+                };
+                tracing::warn!("{dir:?} points to a missing {target:?}, creating it");
+                tokio::fs::create_dir_all(&target)
+                    .await
+                    .map_err(|err| io_error(err, &target))?;
+            }
+
+            let is_corrupted = tokio::fs::metadata(dir)
+                .await
+                .is_ok_and(|metadata| !metadata.is_dir());
+            if is_corrupted {
+                let quarantine = dir.with_extension(format!("corrupted.{}", now_ms()));
+                tracing::warn!("{dir:?} is not a directory, moving it to {quarantine:?}");
+                tokio::fs::rename(dir, &quarantine)
+                    .await
+                    .map_err(|err| io_error(err, dir))?;
+            }
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|err| io_error(err, dir))?;
+        }
+
+        self.initialize_vault().await?;
 
         Ok(())
     }
@@ -99,6 +350,8 @@ impl ParticleDataStore {
     ) -> Result<()> {
         tracing::trace!(target: "particle_reap", particle_id = particle_id, "Storing data for particle");
         let data_path = self.data_file(particle_id, current_peer_id, signature);
+        // prefetched data is outdated from now on
+        self.prefetched.lock().take(&data_path);
         tokio::fs::write(&data_path, data)
             .await
             .map_err(|err| DataStoreError::StoreData(err, data_path))?;
@@ -114,40 +367,109 @@ impl ParticleDataStore {
         signature: &[u8],
     ) -> Result<Vec<u8>> {
         let data_path = self.data_file(particle_id, current_peer_id, signature);
-        let data = tokio::fs::read(&data_path).await.unwrap_or_default();
+        if let Some(data) = self.prefetched.lock().take(&data_path) {
+            return Ok(data);
+        }
+        let data = self.read_file(&data_path).await.unwrap_or_default();
         Ok(data)
     }
 
-    pub async fn batch_cleanup_data(&self, cleanup_keys: Vec<(String, PeerId, Vec<u8>, String)>) {
-        let futures: FuturesUnordered<_> = cleanup_keys
+    /// Loads particle data into memory ahead of execution, e.g. on failover of a deal.
+    /// Returns the number of loaded entries, particles without data are skipped.
+    pub async fn prefetch(&self, keys: Vec<CleanupKey>) -> usize {
+        let futures: FuturesUnordered<_> = keys
             .into_iter()
-            .map(
-                |(particle_id, peer_id, signature, particle_token)| async move {
-                    tracing::debug!(
-                        target: "particle_reap",
-                        particle_id = particle_id, worker_id = peer_id.to_base58(),
-                        "Reaping particle's actor"
-                    );
-
-                    if let Err(err) = self
-                        .cleanup_data(
-                            particle_id.as_str(),
-                            peer_id,
-                            &signature,
-                            particle_token.as_str(),
-                        )
-                        .await
-                    {
-                        tracing::warn!(
-                            particle_id = particle_id,
-                            "Error cleaning up after particle {:?}",
-                            err
-                        );
+            .map(|key| async move {
+                let data_path = self.data_file(
+                    key.particle_id.as_str(),
+                    &key.peer_id.to_base58(),
+                    &key.signature,
+                );
+                match self.read_file(&data_path).await {
+                    Ok(data) => Some((data_path, data)),
+                    Err(err) => {
+                        if err.kind() != ErrorKind::NotFound {
+                            tracing::warn!(
+                                particle_id = key.particle_id,
+                                "Error prefetching particle data {:?}",
+                                err
+                            );
+                        }
+                        None
                     }
-                },
-            )
+                }
+            })
             .collect();
-        let _results: Vec<_> = futures.collect().await;
+        let loaded: Vec<_> = futures.filter_map(|entry| async { entry }).collect().await;
+
+        let count = loaded.len();
+        let mut prefetched = self.prefetched.lock();
+        for (data_path, data) in loaded {
+            prefetched.insert(data_path, data);
+        }
+        count
+    }
+
+    /// Removes data of the particles, at most `cleanup_parallelism` at once.
+    /// Failure to clean up one particle doesn't stop the others.
+    pub async fn batch_cleanup_data(&self, cleanup_keys: Vec<CleanupKey>) -> CleanupReport {
+        cleanup_bounded(cleanup_keys, self.cleanup_parallelism, |key| async move {
+            tracing::debug!(
+                target: "particle_reap",
+                particle_id = key.particle_id, worker_id = key.peer_id.to_base58(),
+                "Reaping particle's actor"
+            );
+
+            let result = self
+                .cleanup_data(
+                    key.particle_id.as_str(),
+                    key.peer_id,
+                    &key.signature,
+                    key.particle_token.as_str(),
+                )
+                .await;
+            if let Err(err) = &result {
+                tracing::warn!(
+                    particle_id = key.particle_id,
+                    "Error cleaning up after particle {:?}",
+                    err
+                );
+            }
+            (key, result)
+        })
+        .await
+    }
+
+    pub async fn store_mailboxes(&self, particles: &[QueuedParticle]) -> Result<()> {
+        let path = self.particle_data_store.join(MAILBOXES_FILE);
+        let data = serde_json::to_vec(particles).map_err(DataStoreError::SerializeMailboxes)?;
+        // Snapshot is replaced only once it's written completely, so a crash can't corrupt it
+        let tmp_path = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, data)
+            .await
+            .map_err(|err| DataStoreError::StoreData(err, tmp_path.clone()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|err| DataStoreError::StoreData(err, path))?;
+
+        Ok(())
+    }
+
+    pub async fn take_mailboxes(&self) -> Result<Vec<QueuedParticle>> {
+        let path = self.particle_data_store.join(MAILBOXES_FILE);
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(DataStoreError::ReadData(err, path)),
+        };
+        // Snapshot that can't be read is left in place for inspection
+        let particles =
+            serde_json::from_slice(&data).map_err(DataStoreError::DeserializeMailboxes)?;
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(DataStoreError::CleanupData)?;
+
+        Ok(particles)
     }
 
     async fn cleanup_data(
@@ -159,6 +481,7 @@ impl ParticleDataStore {
     ) -> Result<()> {
         tracing::debug!(target: "particle_reap", particle_id = particle_id, "Cleaning up particle data for particle");
         let path = self.data_file(particle_id, &current_peer_id.to_base58(), signature);
+        self.prefetched.lock().take(&path);
         match tokio::fs::remove_file(&path).await {
             Ok(_) => Ok(()),
             // ignore NotFound
@@ -275,8 +598,68 @@ pub enum DataStoreError {
     WriteAnomaly(#[source] std::io::Error, PathBuf),
     #[error("error serializing anomaly data")]
     SerializeAnomaly(#[source] serde_json::error::Error),
+    #[error("error serializing mailboxes")]
+    SerializeMailboxes(#[source] serde_json::error::Error),
+    #[error("error deserializing mailboxes")]
+    DeserializeMailboxes(#[source] serde_json::error::Error),
     #[error("error reading data from {1:?}")]
     ReadData(#[source] std::io::Error, PathBuf),
+    #[error("no permission to access {0:?}")]
+    PermissionDenied(PathBuf),
+    #[error("{0:?} is expected to be a directory")]
+    CorruptedDir(PathBuf),
+    #[error("directory {0:?} is missing")]
+    MissingDir(PathBuf),
+}
+
+async fn create_store_dir(dir: &Path) -> Result<()> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(|err| io_error(err, dir))
+}
+
+/// Tells apart the errors `ParticleDataStore::repair` may help with
+fn io_error(err: std::io::Error, dir: &Path) -> DataStoreError {
+    match err.kind() {
+        ErrorKind::PermissionDenied => DataStoreError::PermissionDenied(dir.to_path_buf()),
+        ErrorKind::NotFound => DataStoreError::MissingDir(dir.to_path_buf()),
+        _ if is_dangling_symlink(dir) => DataStoreError::MissingDir(dir.to_path_buf()),
+        _ if dir.exists() && !dir.is_dir() => DataStoreError::CorruptedDir(dir.to_path_buf()),
+        _ => DataStoreError::CreateDataStore(err),
+    }
+}
+
+/// Whether the path is a link to something that doesn't exist, `create_dir_all` can't fix that
+fn is_dangling_symlink(path: &Path) -> bool {
+    let is_symlink = path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink());
+    is_symlink && !path.exists()
+}
+
+async fn cleanup_bounded<C, Fut>(
+    cleanup_keys: Vec<CleanupKey>,
+    parallelism: usize,
+    cleanup: C,
+) -> CleanupReport
+where
+    C: Fn(CleanupKey) -> Fut,
+    Fut: Future<Output = (CleanupKey, Result<()>)>,
+{
+    futures::stream::iter(cleanup_keys)
+        .map(cleanup)
+        .buffer_unordered(parallelism)
+        .fold(
+            CleanupReport::default(),
+            |mut report, (key, result)| async move {
+                match result {
+                    Ok(()) => report.cleaned += 1,
+                    Err(err) => report.failed.push((key, err)),
+                }
+                report
+            },
+        )
+        .await
 }
 
 fn store_key_from_components(particle_id: &str, current_peer_id: &str, signature: &[u8]) -> String {
@@ -292,11 +675,13 @@ fn format_signature(signature: &[u8]) -> String {
 
 #[cfg(test)]
 mod tests {
-    use crate::ParticleDataStore;
+    use crate::particle_data_store::{cleanup_bounded, CleanupKey, MAILBOXES_FILE};
+    use crate::{DataStoreError, ParticleDataStore};
     use avm_server::avm_runner::RawAVMOutcome;
     use avm_server::{CallRequests, SoftLimitsTriggering};
     use fluence_libp2p::PeerId;
     use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::time::Duration;
 
     #[tokio::test]
@@ -316,6 +701,107 @@ mod tests {
         assert!(particle_data_store_clone.exists());
     }
 
+    #[tokio::test]
+    async fn test_repair() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let particle_data_store = temp_dir.path().join("particle_data_store");
+        let vault_dir = temp_dir.path().join("vault");
+        let anomaly_data_store = temp_dir.path().join("anomaly_data_store");
+        let particle_data_store_clone = particle_data_store.clone();
+
+        // a file occupies the path of the store directory
+        std::fs::write(&particle_data_store, b"garbage").expect("Failed to write file");
+
+        let particle_data_store =
+            ParticleDataStore::new(particle_data_store, vault_dir, anomaly_data_store);
+
+        let result = particle_data_store.initialize().await;
+        assert!(
+            matches!(result, Err(DataStoreError::CorruptedDir(ref dir)) if dir == &particle_data_store_clone)
+        );
+
+        particle_data_store
+            .repair()
+            .await
+            .expect("Failed to repair data store");
+        assert!(particle_data_store_clone.is_dir());
+
+        let quarantined = std::fs::read_dir(temp_dir.path())
+            .expect("Failed to read temp dir")
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("particle_data_store.corrupted.")
+            })
+            .count();
+        assert_eq!(quarantined, 1);
+
+        let result = particle_data_store.initialize().await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_repair_missing_dir() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let particle_data_store = temp_dir.path().join("particle_data_store");
+        let vault_dir = temp_dir.path().join("vault");
+        let anomaly_data_store = temp_dir.path().join("anomaly_data_store");
+        let particle_data_store_clone = particle_data_store.clone();
+
+        // the store is a link to a volume that isn't there
+        let volume = temp_dir.path().join("volume").join("particles");
+        std::os::unix::fs::symlink(&volume, &particle_data_store).expect("Failed to create link");
+
+        let particle_data_store =
+            ParticleDataStore::new(particle_data_store, vault_dir, anomaly_data_store);
+
+        let result = particle_data_store.initialize().await;
+        assert!(
+            matches!(result, Err(DataStoreError::MissingDir(ref dir)) if dir == &particle_data_store_clone)
+        );
+
+        particle_data_store
+            .repair()
+            .await
+            .expect("Failed to repair data store");
+        assert!(volume.is_dir());
+        assert!(particle_data_store_clone.is_symlink());
+
+        let result = particle_data_store.initialize().await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_repair_vault() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let particle_data_store = temp_dir.path().join("particle_data_store");
+        let vault_dir = temp_dir.path().join("vault");
+        let anomaly_data_store = temp_dir.path().join("anomaly_data_store");
+        let vault_dir_clone = vault_dir.clone();
+
+        // a file occupies the path of the vault directory
+        std::fs::write(&vault_dir, b"garbage").expect("Failed to write file");
+
+        let particle_data_store =
+            ParticleDataStore::new(particle_data_store, vault_dir, anomaly_data_store);
+
+        let result = particle_data_store.initialize().await;
+        assert!(
+            matches!(result, Err(DataStoreError::CorruptedDir(ref dir)) if dir == &vault_dir_clone)
+        );
+
+        particle_data_store
+            .repair()
+            .await
+            .expect("Failed to repair data store");
+        assert!(vault_dir_clone.is_dir());
+
+        let result = particle_data_store.initialize().await;
+        assert!(result.is_ok());
+    }
+
     #[tokio::test]
     async fn test_store_and_read_data() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
@@ -510,4 +996,237 @@ mod tests {
         assert!(!data_file_path.exists());
         assert!(!vault_path.exists())
     }
+
+    #[tokio::test]
+    async fn test_batch_cleanup_data() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir_path = temp_dir.path();
+        let particle_data_store = ParticleDataStore::new(
+            temp_dir_path.join("particle_data_store"),
+            temp_dir_path.join("vault"),
+            temp_dir_path.join("anomaly_data_store"),
+        );
+        particle_data_store
+            .initialize()
+            .await
+            .expect("Failed to initialize");
+
+        let key = CleanupKey {
+            particle_id: "test_particle".to_string(),
+            peer_id: PeerId::random(),
+            signature: vec![1, 2, 3],
+            particle_token: "test_token".to_string(),
+        };
+        let peer_id_str = key.peer_id.to_base58();
+
+        particle_data_store
+            .store_data(b"test_data", &key.particle_id, &peer_id_str, &key.signature)
+            .await
+            .expect("Failed to store data");
+        let data_file_path =
+            particle_data_store.data_file(&key.particle_id, &peer_id_str, &key.signature);
+        let vault_path = particle_data_store.vault.real_particle_vault(
+            key.peer_id,
+            &key.particle_id,
+            &key.particle_token,
+        );
+        tokio::fs::create_dir_all(&vault_path)
+            .await
+            .expect("Failed to create vault dir");
+
+        particle_data_store.batch_cleanup_data(vec![key]).await;
+
+        assert!(!data_file_path.exists());
+        assert!(!vault_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_bounded() {
+        let keys: Vec<_> = (0..20)
+            .map(|i| CleanupKey {
+                particle_id: i.to_string(),
+                peer_id: PeerId::random(),
+                signature: vec![i],
+                particle_token: "test_token".to_string(),
+            })
+            .collect();
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+
+        let report = cleanup_bounded(keys, 3, |key| {
+            let running = &running;
+            let max_running = &max_running;
+            async move {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+
+                // every fourth deletion fails
+                let result = if key.signature[0] % 4 == 0 {
+                    Err(DataStoreError::CleanupData(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "test failure",
+                    )))
+                } else {
+                    Ok(())
+                };
+                (key, result)
+            }
+        })
+        .await;
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+        assert_eq!(report.cleaned, 15);
+        let mut failed: Vec<_> = report
+            .failed
+            .iter()
+            .map(|(key, _)| key.signature[0])
+            .collect();
+        failed.sort();
+        assert_eq!(failed, vec![0, 4, 8, 12, 16]);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir_path = temp_dir.path();
+        let particle_data_store = ParticleDataStore::new(
+            temp_dir_path.join("particle_data_store"),
+            temp_dir_path.join("vault"),
+            temp_dir_path.join("anomaly_data_store"),
+        );
+        particle_data_store
+            .initialize()
+            .await
+            .expect("Failed to initialize");
+
+        let current_peer_id = PeerId::random();
+        let current_peer_id_str = current_peer_id.to_base58();
+        let signature: &[u8] = &[1];
+        let data = b"test_data";
+        particle_data_store
+            .store_data(data, "test_particle", &current_peer_id_str, signature)
+            .await
+            .expect("Failed to store data");
+
+        let keys = ["test_particle", "missing_particle"]
+            .into_iter()
+            .map(|particle_id| CleanupKey {
+                particle_id: particle_id.to_string(),
+                peer_id: current_peer_id,
+                signature: signature.to_vec(),
+                particle_token: "test_token".to_string(),
+            })
+            .collect();
+        let prefetched = particle_data_store.prefetch(keys).await;
+        assert_eq!(prefetched, 1);
+        let disk_reads = particle_data_store.disk_reads();
+
+        let cached = particle_data_store
+            .read_data("test_particle", &current_peer_id_str, signature)
+            .await
+            .expect("Failed to read data");
+        assert_eq!(cached, data);
+        assert_eq!(particle_data_store.disk_reads(), disk_reads);
+
+        // Prefetched data is served only once, then it's read from disk again
+        let read_again = particle_data_store
+            .read_data("test_particle", &current_peer_id_str, signature)
+            .await
+            .expect("Failed to read data");
+        assert_eq!(read_again, data);
+        assert_eq!(particle_data_store.disk_reads(), disk_reads + 1);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_limits() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir_path = temp_dir.path();
+        let particle_data_store = ParticleDataStore::new(
+            temp_dir_path.join("particle_data_store"),
+            temp_dir_path.join("vault"),
+            temp_dir_path.join("anomaly_data_store"),
+        )
+        .with_prefetch_limits(2, Duration::from_secs(60));
+        particle_data_store
+            .initialize()
+            .await
+            .expect("Failed to initialize");
+
+        let current_peer_id = PeerId::random();
+        let current_peer_id_str = current_peer_id.to_base58();
+        let signature: &[u8] = &[1];
+        let particle_ids = ["first", "second", "third"];
+        for particle_id in particle_ids {
+            particle_data_store
+                .store_data(b"data", particle_id, &current_peer_id_str, signature)
+                .await
+                .expect("Failed to store data");
+        }
+
+        let keys = particle_ids
+            .into_iter()
+            .map(|particle_id| CleanupKey {
+                particle_id: particle_id.to_string(),
+                peer_id: current_peer_id,
+                signature: signature.to_vec(),
+                particle_token: "test_token".to_string(),
+            })
+            .collect();
+        particle_data_store.prefetch(keys).await;
+        let disk_reads = particle_data_store.disk_reads();
+
+        // Only two entries fit, so one of them is read from disk
+        for particle_id in particle_ids {
+            particle_data_store
+                .read_data(particle_id, &current_peer_id_str, signature)
+                .await
+                .expect("Failed to read data");
+        }
+        assert_eq!(particle_data_store.disk_reads(), disk_reads + 1);
+    }
+
+    #[tokio::test]
+    async fn test_take_corrupted_mailboxes() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir_path = temp_dir.path();
+        let particle_data_store = ParticleDataStore::new(
+            temp_dir_path.join("particle_data_store"),
+            temp_dir_path.join("vault"),
+            temp_dir_path.join("anomaly_data_store"),
+        );
+        particle_data_store
+            .initialize()
+            .await
+            .expect("Failed to initialize");
+
+        let path = temp_dir_path
+            .join("particle_data_store")
+            .join(MAILBOXES_FILE);
+        tokio::fs::write(&path, b"[{\"particle\"")
+            .await
+            .expect("Failed to write mailboxes");
+
+        // Snapshot that can't be parsed isn't removed
+        let result = particle_data_store.take_mailboxes().await;
+        assert!(matches!(
+            result,
+            Err(DataStoreError::DeserializeMailboxes(_))
+        ));
+        assert!(path.exists());
+
+        particle_data_store
+            .store_mailboxes(&[])
+            .await
+            .expect("Failed to store mailboxes");
+        assert!(!path.with_extension("json.tmp").exists());
+
+        let particles = particle_data_store
+            .take_mailboxes()
+            .await
+            .expect("Failed to take mailboxes");
+        assert!(particles.is_empty());
+        assert!(!path.exists());
+    }
 }
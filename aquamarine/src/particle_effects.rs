@@ -50,6 +50,10 @@ pub struct InterpretationStats {
     pub memory_delta: usize,
     pub new_data_len: Option<usize>,
     pub success: bool,
+    /// Interpretation succeeded, but produced no data, no next peers and no calls
+    pub empty_effects: bool,
+    /// Nonzero return code and error message of the interpreted script
+    pub avm_error: Option<(i64, String)>,
 }
 
 impl InterpretationStats {
@@ -59,6 +63,8 @@ impl InterpretationStats {
             memory_delta: 0,
             new_data_len: None,
             success: false,
+            empty_effects: false,
+            avm_error: None,
         }
     }
 }
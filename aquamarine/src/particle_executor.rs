@@ -17,11 +17,13 @@
 use async_trait::async_trait;
 use std::borrow::Cow;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use avm_server::avm_runner::RawAVMOutcome;
 use avm_server::{CallResults, ParticleParameters};
 use fluence_keypair::KeyPair;
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use tokio::task::JoinError;
 use tracing::instrument;
 
@@ -30,21 +32,32 @@ use particle_protocol::Particle;
 
 use crate::spawner::SpawnFunctions;
 use crate::spawner::Spawner;
-use crate::{AquaRuntime, InterpretationStats, ParticleDataStore, ParticleEffects};
+use crate::{AquaRuntime, DataStore, InterpretationStats, ParticleEffects};
 
-pub(super) type AVMRes<RT> = FutResult<Option<RT>, ParticleEffects, InterpretationStats>;
+pub(super) type AVMRes<RT> = FutResult<VmOutcome<RT>, ParticleEffects, InterpretationStats>;
+
+/// What became of the VM that executed a particle
+pub enum VmOutcome<RT> {
+    /// VM is free to execute other particles
+    Returned(RT),
+    /// VM was lost due to panic or cancellation, so it has to be recreated
+    Lost,
+    /// VM is busy with a call abandoned on timeout, the future yields it once the call returns
+    Busy(BoxFuture<'static, Option<RT>>),
+}
 
 #[async_trait]
 pub trait ParticleExecutor {
     type Output;
     type Particle;
-    async fn execute(
+    async fn execute<DS: DataStore>(
         mut self,
         spawner: Spawner,
-        data_store: Arc<ParticleDataStore>,
+        data_store: Arc<DS>,
         p: Self::Particle,
         current_peer_id: PeerId,
         key_pair: KeyPair,
+        timeout: Duration,
     ) -> Self::Output;
 }
 
@@ -73,13 +86,14 @@ impl<RT: AquaRuntime> ParticleExecutor for RT {
     type Particle = (Particle, CallResults);
 
     #[instrument(level = tracing::Level::INFO, skip_all)]
-    async fn execute(
+    async fn execute<DS: DataStore>(
         mut self,
         spawner: Spawner,
-        data_store: Arc<ParticleDataStore>,
+        data_store: Arc<DS>,
         p: Self::Particle,
         current_peer_id: PeerId,
         key_pair: KeyPair,
+        timeout: Duration,
     ) -> Self::Output {
         let (particle, call_results) = p;
         let particle_id = particle.id.clone();
@@ -104,11 +118,12 @@ impl<RT: AquaRuntime> ParticleExecutor for RT {
                 particle,
                 call_results,
                 prev_data,
+                timeout,
             )
             .await
         } else {
             FutResult {
-                runtime: Some(self),
+                runtime: VmOutcome::Returned(self),
                 effects: ParticleEffects::empty(),
                 stats: InterpretationStats::failed(),
             }
@@ -117,20 +132,21 @@ impl<RT: AquaRuntime> ParticleExecutor for RT {
 }
 
 #[instrument(level = tracing::Level::INFO, skip_all)]
-async fn execute_with_prev_data<RT: AquaRuntime>(
+async fn execute_with_prev_data<RT: AquaRuntime, DS: DataStore>(
     vm: RT,
     spawner: Spawner,
-    data_store: Arc<ParticleDataStore>,
+    data_store: Arc<DS>,
     current_peer_id: PeerId,
     key_pair: KeyPair,
     particle: Particle,
     call_results: CallResults,
     prev_data: Vec<u8>,
+    timeout: Duration,
 ) -> AVMRes<RT> {
     let particle_id = particle.id.clone();
     let prev_data_len = prev_data.len();
 
-    let avm_result = avm_call(
+    let mut avm_call = Box::pin(avm_call(
         spawner,
         vm,
         current_peer_id,
@@ -138,8 +154,23 @@ async fn execute_with_prev_data<RT: AquaRuntime>(
         particle,
         call_results,
         prev_data,
-    )
-    .await;
+    ));
+    // There's no point in the result once the particle is expired
+    let timed_out = tokio::time::timeout(timeout, &mut avm_call).await;
+    let Ok(avm_result) = timed_out else {
+        tracing::warn!(
+            particle_id,
+            "Particle execution didn't finish in {} before the particle expired",
+            humantime::format_duration(timeout)
+        );
+        // The call can't be interrupted, so its VM is given back to VmPool once it returns
+        let call = async move { avm_call.await.ok().map(|result| result.vm) };
+        return FutResult {
+            runtime: VmOutcome::Busy(call.boxed()),
+            effects: ParticleEffects::empty(),
+            stats: InterpretationStats::failed(),
+        };
+    };
 
     match avm_result {
         Ok(avm_result) => {
@@ -156,7 +187,7 @@ async fn execute_with_prev_data<RT: AquaRuntime>(
             FutResult {
                 // We loose an AVM instance here
                 // But it will be recreated via VmPool
-                runtime: None,
+                runtime: VmOutcome::Lost,
                 effects,
                 stats,
             }
@@ -165,19 +196,32 @@ async fn execute_with_prev_data<RT: AquaRuntime>(
 }
 
 #[instrument(level = tracing::Level::INFO, skip_all)]
-async fn process_avm_result<RT>(
-    data_store: Arc<ParticleDataStore>,
+async fn process_avm_result<RT, DS>(
+    data_store: Arc<DS>,
     current_peer_id: PeerId,
     prev_data_len: usize,
     avm_result: AVMCallResult<'_, RT>,
 ) -> AVMRes<RT>
 where
     RT: AquaRuntime,
+    DS: DataStore,
 {
     let particle_id = avm_result.particle.id;
-    let stats = avm_result.stats;
+    let mut stats = avm_result.stats;
     match &avm_result.avm_outcome {
         Ok(outcome) => {
+            if outcome.data.is_empty()
+                && outcome.next_peer_pks.is_empty()
+                && outcome.call_requests.is_empty()
+            {
+                tracing::debug!(
+                    target: "empty_effects", particle_id = particle_id,
+                    ret_code = outcome.ret_code, error_message = outcome.error_message,
+                    "Particle interpreted without effects"
+                );
+                stats.empty_effects = true;
+            }
+
             let len = outcome.data.len();
             tracing::trace!(
                 target: "execution", particle_id = particle_id,
@@ -222,7 +266,7 @@ where
                     err
                 );
                 return FutResult {
-                    runtime: Some(avm_result.vm),
+                    runtime: VmOutcome::Returned(avm_result.vm),
                     effects: ParticleEffects::empty(),
                     stats: InterpretationStats::failed(),
                 };
@@ -239,7 +283,7 @@ where
     let effects = RT::into_effects(avm_result.avm_outcome, particle_id);
 
     FutResult {
-        runtime: Some(avm_result.vm),
+        runtime: VmOutcome::Returned(avm_result.vm),
         effects,
         stats,
     }
@@ -282,12 +326,19 @@ async fn avm_call<'a, RT: AquaRuntime>(
 
             let interpretation_time = now.elapsed();
             let new_data_len = avm_outcome.as_ref().map(|e| e.data.len()).ok();
+            let avm_error = avm_outcome
+                .as_ref()
+                .ok()
+                .filter(|outcome| outcome.ret_code != 0)
+                .map(|outcome| (outcome.ret_code, outcome.error_message.clone()));
             let memory_delta = memory_size_after - memory_size_before;
             let stats = InterpretationStats {
                 memory_delta,
                 interpretation_time,
                 new_data_len,
                 success: avm_outcome.is_ok(),
+                empty_effects: false,
+                avm_error,
             };
             AVMCallResult {
                 avm_outcome,
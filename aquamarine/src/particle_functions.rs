@@ -122,6 +122,11 @@ impl<F: ParticleFunctionStatic> Functions<F> {
         self.function_calls.extend(futs);
     }
 
+    /// No call requests are in progress and no call results wait to be drained
+    pub fn is_idle(&self) -> bool {
+        self.function_calls.is_empty() && self.call_results.is_empty()
+    }
+
     /// Retrieve all existing call results
     pub fn drain(&mut self) -> (CallResults, Vec<SingleCallStat>, Vec<Arc<Span>>) {
         let call_results = std::mem::take(&mut self.call_results);
@@ -0,0 +1,46 @@
+/*
+ * Copyright 2024 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use eyre::eyre;
+use fluence_keypair::KeyPair;
+
+/// Produces particle tokens by signing particle signatures, e.g. with a key kept in an external KMS
+pub trait ParticleTokenSigner: Send + Sync {
+    fn sign(&self, particle_signature: &[u8]) -> eyre::Result<Vec<u8>>;
+}
+
+/// Signs particle tokens with the in-process root key pair
+pub struct RootKeyPairSigner {
+    key_pair: KeyPair,
+}
+
+impl RootKeyPairSigner {
+    pub fn new(key_pair: KeyPair) -> Self {
+        Self { key_pair }
+    }
+}
+
+impl ParticleTokenSigner for RootKeyPairSigner {
+    fn sign(&self, particle_signature: &[u8]) -> eyre::Result<Vec<u8>> {
+        let signature = self.key_pair.sign(particle_signature).map_err(|err| {
+            eyre!(
+                "Could not produce particle token by signing the particle signature: {}",
+                err
+            )
+        })?;
+        Ok(signature.to_vec())
+    }
+}
@@ -15,12 +15,16 @@
  */
 
 use eyre::eyre;
-use fluence_keypair::KeyPair;
 use futures::future::BoxFuture;
-use futures::FutureExt;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
 use std::collections::hash_map::Entry;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::task::Poll::Ready;
+use std::time::{Duration, Instant};
 use std::{
     collections::{HashMap, VecDeque},
     task::{Context, Poll},
@@ -28,74 +32,345 @@ use std::{
 
 use futures::task::Waker;
 use marine_wasmtime_backend::WasmtimeWasmBackend;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
 use tokio::runtime::Handle;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
 use tokio::task;
-use tracing::instrument;
+use tokio::task::JoinError;
+use tracing::{instrument, Instrument};
 
 use fluence_libp2p::PeerId;
+/// For tests, runtime handles can be hidden to emulate a worker that is still starting
+#[cfg(test)]
+use mock_runtime::get_runtime_handle;
 /// For tests, mocked time is used
 #[cfg(test)]
 use mock_time::now_ms;
 use particle_execution::{ParticleFunctionStatic, ParticleParams, ServiceFunction};
-use particle_protocol::ExtendedParticle;
+use particle_protocol::{ExtendedParticle, Particle, ParticleError};
 use particle_services::PeerScope;
-use peer_metrics::{ParticleExecutorMetrics, WorkerLabel, WorkerType};
+use peer_metrics::{
+    ExecutorCounter, MetricsSink, ParticleOrigin, VmPoolMetrics, WorkerCounter, WorkerGauge,
+    WorkerLabel, WorkerType,
+};
+/// Get worker runtime handle from the worker registry
+#[cfg(not(test))]
+use real_runtime::get_runtime_handle;
 /// Get current time from OS
 #[cfg(not(test))]
 use real_time::now_ms;
 use types::DealId;
 use workers::{KeyStorage, PeerScopes, Workers};
 
-use crate::actor::{Actor, ActorPoll};
+use crate::actor::{particle_memory_estimate, Actor, ActorLoad, ActorPoll};
+use crate::config::{ActorKeying, NoCapacityPolicy, PlumberConfig};
 use crate::deadline::Deadline;
-use crate::error::AquamarineApiError;
+use crate::error::{AquamarineApiError, ServiceTaskError};
+use crate::particle_data_store::{CleanupKey, QueuedParticle};
 use crate::particle_effects::LocalRoutingEffects;
+use crate::particle_executor::VmOutcome;
 use crate::particle_functions::{Functions, SingleCallStat};
+use crate::particle_token::{ParticleTokenSigner, RootKeyPairSigner};
+use crate::signature_cache::SignatureCache;
 use crate::spawner::{RootSpawner, Spawner, WorkerSpawner};
-use crate::vm_pool::VmPool;
-use crate::{AquaRuntime, ParticleDataStore, RemoteRoutingEffects};
+use crate::vm_pool::{VmPool, VmPoolConfigSummary};
+use crate::{
+    AquaRuntime, DataStore, DataStoreError, InterpretationStats, ParticleDataStore,
+    RemoteRoutingEffects,
+};
 use types::peer_scope::WorkerId;
 
-#[derive(PartialEq, Hash, Eq)]
+/// Deferred particles are retried at least that often, as nothing signals
+/// that the runtime of their worker has appeared
+const DEFERRED_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
 struct ActorKey {
     signature: Vec<u8>,
+    /// Init peer id and particle id, set under `ActorKeying::Strict`
+    origin: Option<(PeerId, String)>,
+}
+
+impl ActorKey {
+    fn new(particle: &Particle, keying: ActorKeying) -> Self {
+        let origin = match keying {
+            ActorKeying::Signature => None,
+            ActorKeying::Strict => Some((particle.init_peer_id, particle.id.clone())),
+        };
+        Self {
+            signature: particle.signature.clone(),
+            origin,
+        }
+    }
+}
+
+/// Particle waiting for the runtime of its worker to appear
+struct DeferredParticle {
+    particle: ExtendedParticle,
+    function: Option<ServiceFunction>,
+    worker_id: WorkerId,
+    /// Unix timestamp in milliseconds after which the particle is dropped
+    retry_until: u64,
+}
+
+/// Actors of a worker along with its VM pool
+type WorkerActors<'a, RT, F, DS> = (
+    WorkerId,
+    &'a mut HashMap<ActorKey, Actor<RT, F, DS>>,
+    &'a mut VmPool<RT>,
+);
+
+/// Actors and VM pool of a worker back from polling on the worker's runtime,
+/// along with the poll, `None` if the polling panicked
+type PolledWorker<RT, F, DS> = (
+    HashMap<ActorKey, Actor<RT, F, DS>>,
+    VmPool<RT>,
+    Option<WorkerPoll>,
+);
+
+/// Worker whose actors and VM pool are being polled on the worker's runtime
+#[derive(Default)]
+struct PolledAway {
+    /// Particles arrived meanwhile, they are ingested once the actors are back
+    particles: Vec<(ExtendedParticle, Option<ServiceFunction>)>,
+    /// Pool was removed meanwhile, so it isn't put back
+    pool_removed: bool,
+    /// Worker was reset meanwhile, so the actors are wiped once they are back
+    reset: bool,
+}
+
+/// Everything produced by polling the actors of a single worker
+#[derive(Default)]
+struct WorkerPoll {
+    remote_effects: Vec<RemoteRoutingEffects>,
+    local_effects: Vec<LocalRoutingEffects>,
+    stats: Vec<InterpretationStats>,
+    cleanup_keys: Vec<CleanupKey>,
+    avm_errors: Vec<AquamarineApiError>,
+    /// Last polled actor if the polling stopped on the time budget
+    stopped_at: Option<ActorKey>,
+}
+
+/// Particle waiting for its signature to be verified on the blocking thread pool
+struct PendingVerification {
+    particle: ExtendedParticle,
+    function: Option<ServiceFunction>,
+    peer_scope: PeerScope,
+    /// `None` if the signature is already known to be valid
+    verification: Option<task::JoinHandle<Result<(), ParticleError>>>,
+}
+
+/// Stage of `Plumber::poll` to resume from after yielding on the time budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum PollStage {
+    /// Gather effects of the actors and put VMs back
+    #[default]
+    Actors,
+    Cleanup,
+    /// Execute next messages
+    NextMessages,
+}
+
+/// Position in the actors stage of `Plumber::poll` to resume from after yielding on the time
+/// budget. Actors are polled in the order of their keys, and workers in the order of their ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ActorsCursor {
+    /// Host actors after that one are left, and then all the workers
+    Host(ActorKey),
+    /// Workers starting from that one are left, the actors of the first one after the key if set
+    Worker(WorkerId, Option<ActorKey>),
+}
+
+/// Effect which the networking layer failed to deliver to a remote peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub particle_id: String,
+    pub peer_id: PeerId,
+    /// Unix timestamp in milliseconds when the failure was reported
+    pub reported_at: u64,
+}
+
+/// What `Plumber::ingest` did with the particle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestOutcome {
+    /// A new actor was created for the particle
+    Accepted,
+    /// Particle was put to the mailbox of the existing actor
+    ForwardedToExistingActor,
+    /// Particle waits for its worker runtime to appear, or for the worker's actors
+    /// to be back from polling on that runtime
+    Deferred,
+    /// Particle waits for its signature to be verified on the blocking thread pool
+    PendingVerification,
+    /// Particle was dropped. The error, if any, is still returned from `poll`
+    Rejected(RejectReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    Expired,
+    MaxHopsExceeded,
+    InvalidSignature,
+    Overloaded,
+    WorkerIsNotActive,
+    NoSuchWorker,
+    PeerNotAllowed,
+    FromFuture,
+    NoWorkerPool,
+    NoCapacity,
+    MailboxFull,
 }
 
-const MAX_CLEANUP_KEYS_SIZE: usize = 1024;
+/// How `Plumber::reset_worker` treats interpretations in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Cancel interpretations in progress
+    Immediate,
+    /// Let interpretations in progress finish, but no longer than `timeout`
+    Graceful { timeout: Duration },
+}
+
+/// Summary of the worker execution state wiped by `Plumber::reset_worker`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResetReport {
+    /// Number of removed actors
+    pub actors: usize,
+    /// Number of actors whose interpretation was in progress
+    pub cancelled: usize,
+    /// Number of particles dropped from the actor mailboxes
+    pub dropped_particles: usize,
+    /// Number of actors whose particle data is scheduled for cleanup
+    pub cleanup_keys: usize,
+}
+
+/// Resources the worker spent on particle interpretation, see `Plumber::take_worker_usage`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DealUsage {
+    /// Number of finished interpretations
+    pub interpretations: u64,
+    /// Number of failed interpretations, included in `interpretations`
+    pub failures: u64,
+    pub interpretation_time: Duration,
+}
+
+impl DealUsage {
+    fn add(&mut self, stats: &InterpretationStats) {
+        self.interpretations += 1;
+        if !stats.success {
+            self.failures += 1;
+        }
+        self.interpretation_time += stats.interpretation_time;
+    }
+}
+
+/// State of an actor, see `Plumber::inspect`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ActorInspection {
+    pub peer_scope: PeerScope,
+    pub particle_id: String,
+    /// Number of particles waiting for execution
+    pub mailbox_size: usize,
+    pub is_executing: bool,
+    /// Time left until the particle expires, in milliseconds
+    pub expires_in_ms: u64,
+}
 
-pub struct Plumber<RT: AquaRuntime, F> {
+pub struct Plumber<RT: AquaRuntime, F, DS = ParticleDataStore> {
     config: RT::Config,
+    plumber_config: PlumberConfig,
     events: VecDeque<Result<RemoteRoutingEffects, AquamarineApiError>>,
-    host_actors: HashMap<ActorKey, Actor<RT, F>>,
+    host_actors: HashMap<ActorKey, Actor<RT, F, DS>>,
     host_vm_pool: VmPool<RT>,
-    worker_actors: HashMap<WorkerId, HashMap<ActorKey, Actor<RT, F>>>,
+    worker_actors: HashMap<WorkerId, HashMap<ActorKey, Actor<RT, F, DS>>>,
     worker_vm_pools: HashMap<WorkerId, VmPool<RT>>,
     workers: Arc<Workers>,
-    data_store: Arc<ParticleDataStore>,
+    data_store: Arc<DS>,
     builtins: F,
     waker: Option<Waker>,
-    metrics: Option<ParticleExecutorMetrics>,
+    metrics: Option<Arc<dyn MetricsSink>>,
     key_storage: Arc<KeyStorage>,
     scopes: PeerScopes,
     cleanup_future: Option<BoxFuture<'static, ()>>,
     root_runtime_handle: Handle,
     avm_wasm_backend: WasmtimeWasmBackend,
+    deferred: VecDeque<DeferredParticle>,
+    /// Wakes the plumber to retry the deferred particles
+    deferred_timer: Option<Pin<Box<tokio::time::Sleep>>>,
+    dead_letters: VecDeque<DeadLetter>,
+    /// Keys of the removed actors, their data is removed on the next cleanup
+    pending_cleanup_keys: Vec<CleanupKey>,
+    /// Errors of the worker particles go here instead of the `poll` stream
+    worker_error_subscribers: HashMap<WorkerId, mpsc::UnboundedSender<AquamarineApiError>>,
+    signature_cache: SignatureCache,
+    /// Particles waiting for signature verification, queued by init peer to keep their order
+    pending_verifications: HashMap<PeerId, VecDeque<PendingVerification>>,
+    /// Bounds the number of concurrently running add_service/remove_service tasks
+    service_tasks: Arc<Semaphore>,
+    /// Bounds the number of VMs executing particles at once across all pools
+    vm_budget: Option<Arc<Semaphore>>,
+    /// Usage accumulated since the last `take_worker_usage`
+    worker_usage: HashMap<WorkerId, DealUsage>,
+    /// Worker pools waiting to be created, with their thread count
+    queued_worker_pools: VecDeque<(WorkerId, usize)>,
+    /// Workers to be reset once their interpretations finish or by the timestamp, in milliseconds
+    pending_resets: HashMap<WorkerId, u64>,
+    token_signer: Arc<dyn ParticleTokenSigner>,
+    /// When a particle execution was last started on the worker or its pool was created, in milliseconds
+    worker_last_executed: HashMap<WorkerId, u64>,
+    /// Sizes of the worker pools removed for being idle, they are created again on demand
+    idle_worker_pools: HashMap<WorkerId, usize>,
+    /// Unix timestamp in milliseconds before which expired actors aren't cleaned up
+    next_cleanup_at: u64,
+    cleanup_rng: StdRng,
+    poll_stage: PollStage,
+    actors_cursor: Option<ActorsCursor>,
+    /// Polls of the workers' actors running on the workers' runtimes
+    worker_polls: FuturesUnordered<
+        BoxFuture<'static, (WorkerId, Result<PolledWorker<RT, F, DS>, JoinError>)>,
+    >,
+    /// Workers whose actors and VM pools are away with `worker_polls`
+    polled_away: HashMap<WorkerId, PolledAway>,
+    /// Worker that was the last to be sent for polling on its runtime, the next one goes after it
+    last_polled_away: Option<WorkerId>,
+    /// Host actor that was the last to get a VM, the next poll starts after it
+    host_cursor: Option<ActorKey>,
+    /// Worker actor that was the last to get a VM from its worker pool
+    worker_cursors: HashMap<WorkerId, ActorKey>,
+    /// Mailbox sizes and memory of all the actors, both host and worker ones
+    actor_load: Arc<ActorLoad>,
+    /// Approximate number of bytes held by the buffered events, deferred and unverified particles
+    buffered_memory: usize,
 }
 
-impl<RT: AquaRuntime, F: ParticleFunctionStatic> Plumber<RT, F> {
+impl<RT: AquaRuntime, F: ParticleFunctionStatic, DS: DataStore> Plumber<RT, F, DS> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: RT::Config,
+        plumber_config: PlumberConfig,
         host_vm_pool: VmPool<RT>,
-        data_store: Arc<ParticleDataStore>,
+        data_store: Arc<DS>,
         builtins: F,
-        metrics: Option<ParticleExecutorMetrics>,
+        metrics: Option<Arc<dyn MetricsSink>>,
         workers: Arc<Workers>,
         key_storage: Arc<KeyStorage>,
         scope: PeerScopes,
         avm_wasm_backend: WasmtimeWasmBackend,
     ) -> Self {
-        Self {
+        let signature_cache = SignatureCache::new(plumber_config.signature_cache_size);
+        let service_tasks = Arc::new(Semaphore::new(plumber_config.max_service_tasks.max(1)));
+        let vm_budget = plumber_config
+            .max_executing_vms
+            .map(|vms| Arc::new(Semaphore::new(vms)));
+        let token_signer = Arc::new(RootKeyPairSigner::new(key_storage.root_key_pair.clone()));
+        let cleanup_rng = match plumber_config.cleanup_jitter_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut plumber = Self {
             config,
+            plumber_config,
             host_vm_pool,
             data_store,
             builtins,
@@ -111,7 +386,36 @@ impl<RT: AquaRuntime, F: ParticleFunctionStatic> Plumber<RT, F> {
             cleanup_future: None,
             root_runtime_handle: Handle::current(),
             avm_wasm_backend,
-        }
+            deferred: <_>::default(),
+            deferred_timer: None,
+            dead_letters: <_>::default(),
+            pending_cleanup_keys: vec![],
+            worker_error_subscribers: <_>::default(),
+            signature_cache,
+            pending_verifications: <_>::default(),
+            service_tasks,
+            vm_budget,
+            worker_usage: <_>::default(),
+            queued_worker_pools: <_>::default(),
+            pending_resets: <_>::default(),
+            token_signer,
+            worker_last_executed: <_>::default(),
+            idle_worker_pools: <_>::default(),
+            next_cleanup_at: 0,
+            cleanup_rng,
+            poll_stage: <_>::default(),
+            actors_cursor: None,
+            worker_polls: <_>::default(),
+            polled_away: <_>::default(),
+            last_polled_away: None,
+            host_cursor: None,
+            worker_cursors: <_>::default(),
+            actor_load: <_>::default(),
+            buffered_memory: 0,
+        };
+        plumber.schedule_cleanup(now_ms());
+
+        plumber
     }
 
     /// Receives and ingests incoming particle: creates a new actor or forwards to the existing mailbox
@@ -121,782 +425,5882 @@ impl<RT: AquaRuntime, F: ParticleFunctionStatic> Plumber<RT, F> {
         particle: ExtendedParticle,
         function: Option<ServiceFunction>,
         peer_scope: PeerScope,
-    ) {
-        let deadline = Deadline::from(particle.as_ref());
+        origin: ParticleOrigin,
+    ) -> IngestOutcome {
+        self.meter(|m| m.ingested_particle(origin));
+
+        if let Err(reason) = self.check_admission(&particle, peer_scope) {
+            return IngestOutcome::Rejected(reason);
+        }
+
+        if self.plumber_config.offload_signature_verification {
+            self.verify_offloaded(particle, function, peer_scope);
+            return IngestOutcome::PendingVerification;
+        }
+
+        if let Err(err) = self.verify_signature(&particle.particle) {
+            self.reject_signature(particle, peer_scope, err);
+            return IngestOutcome::Rejected(RejectReason::InvalidSignature);
+        }
+
+        self.ingest_verified(particle, function, peer_scope)
+    }
+
+    /// Ingests particles that arrived together, verifying the signatures that aren't cached
+    /// in a single batch, see `Particle::verify_batch`.
+    /// Outcomes are returned in the order of the particles.
+    pub fn ingest_batch(
+        &mut self,
+        particles: Vec<(ExtendedParticle, Option<ServiceFunction>)>,
+        peer_scope: PeerScope,
+        origin: ParticleOrigin,
+    ) -> Vec<IngestOutcome> {
+        // Offloaded verification doesn't block the plumber anyway
+        if self.plumber_config.offload_signature_verification {
+            return particles
+                .into_iter()
+                .map(|(particle, function)| self.ingest(particle, function, peer_scope, origin))
+                .collect();
+        }
+
+        let mut outcomes = vec![None; particles.len()];
+        let mut admitted = vec![];
+        for (idx, (particle, function)) in particles.into_iter().enumerate() {
+            self.meter(|m| m.ingested_particle(origin));
+            match self.check_admission(&particle, peer_scope) {
+                Ok(()) => admitted.push((idx, particle, function)),
+                Err(reason) => outcomes[idx] = Some(IngestOutcome::Rejected(reason)),
+            }
+        }
+
+        let cached: Vec<bool> = admitted
+            .iter()
+            .map(|(_, particle, _)| self.signature_cache.touch(&particle.particle))
+            .collect();
+        let to_verify: Vec<&Particle> = admitted
+            .iter()
+            .zip(&cached)
+            .filter(|(_, cached)| !**cached)
+            .map(|((_, particle, _), _)| &particle.particle)
+            .collect();
+        self.meter(|m| {
+            m.counter(
+                ExecutorCounter::SignatureVerifications,
+                to_verify.len() as u64,
+            );
+        });
+        let mut verified = Particle::verify_batch(&to_verify).into_iter();
+
+        for ((idx, particle, function), cached) in admitted.into_iter().zip(cached) {
+            let result = if cached {
+                Ok(())
+            } else {
+                verified
+                    .next()
+                    .expect("verification result for each particle")
+            };
+            let outcome = match result {
+                Ok(()) => {
+                    if !cached {
+                        self.signature_cache.insert(&particle.particle);
+                    }
+                    self.ingest_verified(particle, function, peer_scope)
+                }
+                Err(err) => {
+                    self.reject_signature(particle, peer_scope, err);
+                    IngestOutcome::Rejected(RejectReason::InvalidSignature)
+                }
+            };
+            outcomes[idx] = Some(outcome);
+        }
+
+        outcomes.into_iter().flatten().collect()
+    }
+
+    /// Rejects particles that are expired, come from the future or exceeded max local hops
+    fn check_admission(
+        &mut self,
+        particle: &ExtendedParticle,
+        peer_scope: PeerScope,
+    ) -> Result<(), RejectReason> {
+        let deadline = self.deadline(&particle.particle);
         if deadline.is_expired(now_ms()) {
             tracing::info!(target: "expired", particle_id = particle.particle.id, "Particle is expired");
-            self.events
-                .push_back(Err(AquamarineApiError::ParticleExpired {
+            self.push_error(
+                peer_scope,
+                AquamarineApiError::ParticleExpired {
+                    particle_id: particle.particle.id.clone(),
+                },
+            );
+            return Err(RejectReason::Expired);
+        }
+
+        if self.plumber_config.clock_skew_tolerance_ms.is_some()
+            && deadline.is_from_future(now_ms())
+        {
+            tracing::info!(target: "expired", particle_id = particle.particle.id, "Particle is from the future");
+            self.push_error(
+                peer_scope,
+                AquamarineApiError::ParticleFromFuture {
+                    particle_id: particle.particle.id.clone(),
+                    timestamp: particle.particle.timestamp,
+                },
+            );
+            return Err(RejectReason::FromFuture);
+        }
+
+        if particle.hops > self.plumber_config.max_local_hops {
+            tracing::warn!(target: "hops", particle_id = particle.particle.id, "Particle exceeded max local hops {}", self.plumber_config.max_local_hops);
+            self.push_error(
+                peer_scope,
+                AquamarineApiError::MaxHopsExceeded {
+                    particle_id: particle.particle.id.clone(),
+                    hops: particle.hops,
+                },
+            );
+            return Err(RejectReason::MaxHopsExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Ingests particle with a valid signature
+    fn ingest_verified(
+        &mut self,
+        particle: ExtendedParticle,
+        function: Option<ServiceFunction>,
+        peer_scope: PeerScope,
+    ) -> IngestOutcome {
+        let is_manager = self.scopes.is_management(particle.particle.init_peer_id);
+        let is_host = self.scopes.is_host(particle.particle.init_peer_id);
+
+        if !is_manager && !is_host && !self.is_peer_allowed(particle.particle.init_peer_id) {
+            tracing::info!(target: "admission", particle_id = particle.particle.id, init_peer_id = particle.particle.init_peer_id.to_string(), "Init peer is not allowed, particle is rejected");
+            self.push_error(
+                peer_scope,
+                AquamarineApiError::PeerNotAllowed {
                     particle_id: particle.particle.id,
-                }));
-            return;
+                    init_peer_id: particle.particle.init_peer_id.to_string(),
+                },
+            );
+            return IngestOutcome::Rejected(RejectReason::PeerNotAllowed);
         }
 
-        if let Err(err) = particle.particle.verify() {
-            tracing::warn!(target: "signature", particle_id = particle.particle.id, "Particle signature verification failed: {err:?}");
-            self.events
-                .push_back(Err(AquamarineApiError::SignatureVerificationFailed {
+        // Under overload only control-plane particles are admitted
+        if !is_manager && !is_host && (self.is_saturated() || self.is_over_memory_budget()) {
+            tracing::warn!(target: "overload", particle_id = particle.particle.id, "Plumber is saturated, particle is shed");
+            self.push_error(
+                peer_scope,
+                AquamarineApiError::Overloaded {
                     particle_id: particle.particle.id,
-                    err,
-                }));
-            return;
+                },
+            );
+            return IngestOutcome::Rejected(RejectReason::Overloaded);
         }
 
         if let PeerScope::WorkerId(worker_id) = peer_scope {
+            // Unknown workers are never active, so check existence first to report the right error
+            let is_known = self.workers.get_deal_id(worker_id).is_ok();
+            if !is_known {
+                tracing::warn!(target: "worker_unknown", particle_id = particle.particle.id, worker_id = worker_id.to_string(), "No such worker, particle is rejected");
+                return self.reject_unknown_worker(particle.particle.id, peer_scope);
+            }
+
             let is_active = self.workers.is_worker_active(worker_id);
-            let is_manager = self.scopes.is_management(particle.particle.init_peer_id);
-            let is_host = self.scopes.is_host(particle.particle.init_peer_id);
 
             // Only a manager or the host itself is allowed to access deactivated workers
             if !is_active && !is_manager && !is_host {
                 tracing::trace!(target: "worker_inactive", particle_id = particle.particle.id, worker_id = worker_id.to_string(), "Worker is not active");
-                return;
+                return IngestOutcome::Rejected(RejectReason::WorkerIsNotActive);
             }
-        };
 
-        let key = ActorKey {
-            signature: particle.particle.signature.clone(),
+            // Worker runtime may still be starting, so give it some time instead of dropping the particle
+            if get_runtime_handle(&self.workers, worker_id).is_none() {
+                tracing::debug!(target: "worker_runtime", particle_id = particle.particle.id, worker_id = worker_id.to_string(), "Worker runtime not found, deferring particle");
+                let retry_until =
+                    now_ms() + self.plumber_config.worker_runtime_wait.as_millis() as u64;
+                self.defer(DeferredParticle {
+                    particle,
+                    function,
+                    worker_id,
+                    retry_until,
+                });
+                return IngestOutcome::Deferred;
+            }
         };
 
-        let actor = self.get_or_create_actor(peer_scope, key, &particle);
-
-        debug_assert!(actor.is_ok(), "no such worker: {:#?}", actor.err());
+        self.ingest_to_actor(particle, function, peer_scope)
+    }
 
-        match actor {
-            Ok(actor) => {
-                actor.ingest(particle);
-                if let Some(function) = function {
-                    actor.set_function(function);
-                }
-            }
-            Err(err) => tracing::warn!(
-                "No such worker {:?}, rejected particle {particle_id}: {:?}",
-                peer_scope,
-                err,
-                particle_id = particle.particle.id,
-            ),
+    /// Verifies particle signature unless the same signed content was verified recently
+    fn verify_signature(&mut self, particle: &Particle) -> Result<(), ParticleError> {
+        if self.signature_cache.touch(particle) {
+            return Ok(());
         }
-        self.wake();
-    }
 
-    pub fn create_worker_pool(&mut self, worker_id: WorkerId, thread_count: usize) {
-        let vm_pool = VmPool::new(
-            thread_count,
-            self.config.clone(),
-            None,
-            None,
-            self.avm_wasm_backend.clone(),
-        ); // TODO: add metrics
-        self.worker_vm_pools.insert(worker_id, vm_pool);
+        self.meter(|m| m.counter(ExecutorCounter::SignatureVerifications, 1));
+        particle.verify()?;
+        self.signature_cache.insert(particle);
+        Ok(())
     }
 
-    pub fn remove_worker_pool(&mut self, worker_id: WorkerId) {
-        self.worker_vm_pools.remove(&worker_id);
+    fn reject_signature(
+        &mut self,
+        particle: ExtendedParticle,
+        peer_scope: PeerScope,
+        err: ParticleError,
+    ) {
+        tracing::warn!(target: "signature", particle_id = particle.particle.id, "Particle signature verification failed: {err:?}");
+        self.push_error(
+            peer_scope,
+            AquamarineApiError::SignatureVerificationFailed {
+                particle_id: particle.particle.id,
+                err,
+            },
+        );
     }
 
-    fn get_or_create_actor(
+    /// Queues particle until its signature is verified on the blocking thread pool.
+    /// Particles of the same init peer leave the queue in the order they were ingested.
+    fn verify_offloaded(
         &mut self,
+        particle: ExtendedParticle,
+        function: Option<ServiceFunction>,
         peer_scope: PeerScope,
-        key: ActorKey,
-        particle: &ExtendedParticle,
-    ) -> eyre::Result<&mut Actor<RT, F>> {
-        let plumber_params = PlumberParams {
-            builtins: &self.builtins,
-            key_storage: self.key_storage.as_ref(),
-            data_store: self.data_store.clone(),
+    ) {
+        let verification = if self.signature_cache.touch(&particle.particle) {
+            None
+        } else {
+            self.meter(|m| m.counter(ExecutorCounter::SignatureVerifications, 1));
+            let to_verify = particle.particle.clone();
+            Some(
+                self.root_runtime_handle
+                    .spawn_blocking(move || to_verify.verify()),
+            )
         };
-        match peer_scope {
-            PeerScope::Host => {
-                let current_peer_id = self.scopes.get_host_peer_id();
-                let spawner = Spawner::Root(RootSpawner::new(self.root_runtime_handle.clone()));
-                let actor_params = ActorParams {
-                    key,
-                    particle,
-                    peer_scope,
-                    current_peer_id,
-                    deal_id: None,
-                    spawner,
-                };
-                Self::create_actor(&mut self.host_actors, plumber_params, actor_params)
-            }
-            PeerScope::WorkerId(worker_id) => {
-                let worker_actors = match self.worker_actors.entry(worker_id) {
-                    Entry::Occupied(o) => o.into_mut(),
-                    Entry::Vacant(v) => v.insert(HashMap::default()),
-                };
-                let current_peer_id: PeerId = worker_id.into();
-                let deal_id = self
-                    .workers
-                    .get_deal_id(worker_id)
-                    .map_err(|err| eyre!("Not found deal for {:?} : {}", worker_id, err))?;
-                let runtime_handle = self
-                    .workers
-                    .get_runtime_handle(worker_id)
-                    .ok_or(eyre!("Not found runtime handle for {:?}", worker_id))?;
-                let spawner = Spawner::Worker(WorkerSpawner::new(runtime_handle, worker_id));
 
-                let actor_params = ActorParams {
-                    key,
-                    particle,
-                    peer_scope,
-                    current_peer_id,
-                    deal_id: Some(deal_id),
-                    spawner,
-                };
-
-                Self::create_actor(worker_actors, plumber_params, actor_params)
-            }
-        }
+        self.buffered_memory += particle_memory_estimate(&particle.particle);
+        self.pending_verifications
+            .entry(particle.particle.init_peer_id)
+            .or_default()
+            .push_back(PendingVerification {
+                particle,
+                function,
+                peer_scope,
+                verification,
+            });
+        self.wake();
     }
 
-    fn create_actor<'p>(
-        actors: &'p mut HashMap<ActorKey, Actor<RT, F>>,
-        plumber_params: PlumberParams<'p, F>,
-        actor_params: ActorParams<'_>,
-    ) -> eyre::Result<&'p mut Actor<RT, F>> {
-        let entry = actors.entry(actor_params.key);
-        let actor = match entry {
-            Entry::Occupied(actor) => actor.into_mut(),
-            Entry::Vacant(entry) => {
-                let builtins = plumber_params.builtins;
-                let key_pair = plumber_params
-                    .key_storage
-                    .get_keypair(actor_params.peer_scope)
-                    .ok_or(eyre!(
-                        "Cannot create actor, no key pair for {:?}",
-                        actor_params.peer_scope
-                    ))?;
-                let data_store = plumber_params.data_store.clone();
+    /// Ingests or rejects particles whose signature verification is finished
+    fn poll_verifications(&mut self, cx: &mut Context<'_>) {
+        if self.pending_verifications.is_empty() {
+            return;
+        }
 
-                let particle_token = get_particle_token(
-                    &plumber_params.key_storage.root_key_pair,
-                    &actor_params.particle.particle.signature,
-                )?;
-                let params = ParticleParams::clone_from(
-                    &actor_params.particle.particle,
-                    actor_params.peer_scope,
-                    particle_token.clone(),
-                );
-                let functions = Functions::new(params, builtins.clone());
+        let mut verified = vec![];
+        self.pending_verifications.retain(|_, queue| {
+            while let Some(pending) = queue.front_mut() {
+                let result = match pending.verification.as_mut() {
+                    None => Some(Ok(())),
+                    Some(handle) => match handle.poll_unpin(cx) {
+                        Poll::Pending => break,
+                        Poll::Ready(Ok(result)) => Some(result),
+                        Poll::Ready(Err(err)) => {
+                            tracing::error!(
+                                particle_id = pending.particle.particle.id,
+                                "Signature verification task failed: {err}, particle is dropped"
+                            );
+                            None
+                        }
+                    },
+                };
+                if let Some(pending) = queue.pop_front() {
+                    verified.push((pending, result));
+                }
+            }
+            !queue.is_empty()
+        });
 
-                let actor = Actor::new(
-                    &actor_params.particle.particle,
-                    functions,
-                    actor_params.current_peer_id,
-                    particle_token,
-                    key_pair,
-                    data_store,
-                    actor_params.deal_id,
-                    actor_params.spawner,
-                );
-                entry.insert(actor)
+        for (pending, result) in verified {
+            self.buffered_memory -= particle_memory_estimate(&pending.particle.particle);
+            match result {
+                Some(Ok(())) => {
+                    if pending.verification.is_some() {
+                        self.signature_cache.insert(&pending.particle.particle);
+                    }
+                    self.ingest_verified(pending.particle, pending.function, pending.peer_scope);
+                }
+                Some(Err(err)) => self.reject_signature(pending.particle, pending.peer_scope, err),
+                None => {}
             }
-        };
-        Ok(actor)
+        }
     }
 
-    pub fn add_service(
-        &self,
-        service: String,
-        functions: HashMap<String, ServiceFunction>,
-        fallback: Option<ServiceFunction>,
-    ) {
-        let builtins = self.builtins.clone();
-        let task = async move {
-            builtins.extend(service, functions, fallback).await;
+    /// Delivers the error to the worker's subscriber, if there's one, or to the `poll` stream
+    fn push_error(&mut self, peer_scope: PeerScope, err: AquamarineApiError) {
+        let err = match peer_scope {
+            PeerScope::WorkerId(worker_id) => match self.worker_error_subscribers.get(&worker_id) {
+                Some(subscriber) => match subscriber.send(err) {
+                    Ok(()) => return,
+                    Err(mpsc::error::SendError(err)) => {
+                        self.worker_error_subscribers.remove(&worker_id);
+                        err
+                    }
+                },
+                None => err,
+            },
+            PeerScope::Host => err,
         };
-        task::Builder::new()
-            .name("Add service")
-            .spawn(task)
-            .expect("Could not spawn add service task");
+        self.events.push_back(Err(err));
     }
 
-    pub fn remove_service(&self, service: String) {
-        let builtins = self.builtins.clone();
-        let task = async move {
-            builtins.remove(&service).await;
-        };
-        task::Builder::new()
-            .name("Remove service")
-            .spawn(task)
-            .expect("Could not spawn remove service task");
+    /// Subscribes to errors of the particles ingested to the worker.
+    /// These errors are no longer returned from `poll` while the subscription is alive.
+    /// A new subscription replaces the previous one.
+    pub fn subscribe_worker_errors(
+        &mut self,
+        worker_id: WorkerId,
+    ) -> mpsc::UnboundedReceiver<AquamarineApiError> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.worker_error_subscribers.insert(worker_id, sender);
+        receiver
     }
 
-    pub fn poll(
+    /// Creates a new actor or forwards particle to the existing mailbox
+    fn ingest_to_actor(
         &mut self,
-        cx: &mut Context<'_>,
-    ) -> Poll<Result<RemoteRoutingEffects, AquamarineApiError>> {
-        self.waker = Some(cx.waker().clone());
+        particle: ExtendedParticle,
+        function: Option<ServiceFunction>,
+        peer_scope: PeerScope,
+    ) -> IngestOutcome {
+        if let PeerScope::WorkerId(worker_id) = peer_scope {
+            if let Some(away) = self.polled_away.get_mut(&worker_id) {
+                self.buffered_memory += particle_memory_estimate(&particle.particle);
+                away.particles.push((particle, function));
+                return IngestOutcome::Deferred;
+            }
 
-        self.poll_pools(cx);
+            if let Some(thread_count) = self.idle_worker_pools.remove(&worker_id) {
+                tracing::info!(
+                    worker_id = worker_id.to_string(),
+                    "Particle for an idle worker arrived, its VM pool is created again"
+                );
+                self.create_worker_pool(worker_id, thread_count);
+            }
 
-        if let Some(event) = self.events.pop_front() {
-            return Poll::Ready(event);
-        }
+            // Actors of a worker without a pool would never be executed
+            let has_pool = self.worker_vm_pools.contains_key(&worker_id)
+                || self
+                    .queued_worker_pools
+                    .iter()
+                    .any(|(id, _)| *id == worker_id);
+            if !has_pool && self.plumber_config.reject_without_worker_pool {
+                tracing::warn!(
+                    particle_id = particle.particle.id,
+                    worker_id = worker_id.to_string(),
+                    "Worker has no VM pool, particle is rejected"
+                );
+                self.push_error(
+                    peer_scope,
+                    AquamarineApiError::NoWorkerPool {
+                        worker_id: worker_id.to_string(),
+                        particle_id: particle.particle.id,
+                    },
+                );
+                return IngestOutcome::Rejected(RejectReason::NoWorkerPool);
+            }
 
-        let mut remote_effects: Vec<RemoteRoutingEffects> = vec![];
-        let mut local_effects: Vec<LocalRoutingEffects> = vec![];
-        // Gather effects and put VMs back
-        self.poll_host_actors(cx, &mut remote_effects, &mut local_effects);
-        self.poll_workers_actors(cx, &mut remote_effects, &mut local_effects);
+            if !self.has_capacity_for(worker_id) {
+                tracing::warn!(
+                    particle_id = particle.particle.id,
+                    worker_id = worker_id.to_string(),
+                    "Worker VM pool has no VMs, particle is rejected"
+                );
+                self.push_error(
+                    peer_scope,
+                    AquamarineApiError::NoCapacity {
+                        worker_id: worker_id.to_string(),
+                        particle_id: particle.particle.id,
+                    },
+                );
+                return IngestOutcome::Rejected(RejectReason::NoCapacity);
+            }
+        }
 
-        self.cleanup(cx);
+        let key = ActorKey::new(&particle.particle, self.plumber_config.actor_keying);
+        let (mailbox_size, max_mailbox_size) = match peer_scope {
+            PeerScope::Host => (
+                self.host_actors.get(&key).map(|a| a.mailbox_size()),
+                self.plumber_config.max_host_mailbox_size,
+            ),
+            PeerScope::WorkerId(worker_id) => (
+                self.worker_actors
+                    .get(&worker_id)
+                    .and_then(|actors| actors.get(&key))
+                    .map(|a| a.mailbox_size()),
+                self.plumber_config.max_worker_mailbox_size,
+            ),
+        };
+        if max_mailbox_size.is_some_and(|max| mailbox_size.unwrap_or(0) >= max) {
+            tracing::warn!(
+                particle_id = particle.particle.id,
+                "Actor mailbox is full, particle is rejected"
+            );
+            self.push_error(
+                peer_scope,
+                AquamarineApiError::MailboxFull {
+                    particle_id: particle.particle.id,
+                },
+            );
+            return IngestOutcome::Rejected(RejectReason::MailboxFull);
+        }
+        let actor_exists = mailbox_size.is_some();
 
-        // Execute next messages
-        let host_call_stats = self.poll_next_host_messages(cx);
-        let workers_call_stats = self.poll_next_worker_messages(cx);
+        let actor = self.get_or_create_actor(peer_scope, key, &particle);
 
-        // TODO: separate workers and root metrics
-        self.meter(|m| {
-            for stat in &host_call_stats {
-                m.service_call(stat.success, stat.kind, stat.call_time)
-            }
-            for stat in &workers_call_stats {
-                m.service_call(stat.success, stat.kind, stat.call_time)
+        let outcome = match actor {
+            Ok(actor) => {
+                actor.ingest(particle);
+                if let Some(function) = function {
+                    actor.set_function(function);
+                }
+                if actor_exists {
+                    IngestOutcome::ForwardedToExistingActor
+                } else {
+                    IngestOutcome::Accepted
+                }
             }
-        });
-
-        for effect in local_effects {
-            for local_peer in effect.next_peers {
-                let span = tracing::info_span!(parent: effect.particle.span.as_ref(), "Plumber: routing effect ingest");
-                let _guard = span.enter();
-                self.ingest(effect.particle.clone(), None, local_peer);
+            Err(err) => {
+                tracing::warn!(
+                    "No such worker {:?}, rejected particle {particle_id}: {:?}",
+                    peer_scope,
+                    err,
+                    particle_id = particle.particle.id,
+                );
+                self.reject_unknown_worker(particle.particle.id, peer_scope)
             }
-        }
+        };
+        self.wake();
 
-        // Turn effects into events, and buffer them
-        self.events.extend(remote_effects.into_iter().map(Ok));
+        outcome
+    }
 
-        Poll::Pending
+    fn reject_unknown_worker(
+        &mut self,
+        particle_id: String,
+        peer_scope: PeerScope,
+    ) -> IngestOutcome {
+        let worker_id = match peer_scope {
+            PeerScope::WorkerId(worker_id) => worker_id.to_string(),
+            PeerScope::Host => self.scopes.get_host_peer_id().to_string(),
+        };
+        self.push_error(
+            peer_scope,
+            AquamarineApiError::NoSuchWorker {
+                worker_id,
+                particle_id,
+            },
+        );
+        IngestOutcome::Rejected(RejectReason::NoSuchWorker)
     }
 
-    fn poll_pools(&mut self, cx: &mut Context<'_>) {
-        self.host_vm_pool.poll(cx);
-        for (_, vm_pool) in self.worker_vm_pools.iter_mut() {
-            vm_pool.poll(cx);
-        }
+    fn deadline(&self, particle: &Particle) -> Deadline {
+        let tolerance = self.plumber_config.clock_skew_tolerance_ms.unwrap_or(0);
+        Deadline::from(particle).with_clock_skew_tolerance(tolerance)
     }
 
-    fn poll_host_actors(
-        &mut self,
-        cx: &mut Context<'_>,
-        remote_effects: &mut Vec<RemoteRoutingEffects>,
-        local_effects: &mut Vec<LocalRoutingEffects>,
-    ) {
-        let host_label =
-            WorkerLabel::new(WorkerType::Host, self.scopes.get_host_peer_id().to_string());
-        Self::poll_actors(
-            &mut self.host_actors,
-            &mut self.host_vm_pool,
-            &self.scopes,
-            self.metrics.as_ref(),
-            cx,
-            host_label,
-            remote_effects,
-            local_effects,
-        );
+    /// Checks the init peer against the configured allowlist and blocklist
+    fn is_peer_allowed(&self, init_peer_id: PeerId) -> bool {
+        let allowed = self
+            .plumber_config
+            .peer_allowlist
+            .as_ref()
+            .map_or(true, |allowlist| allowlist.contains(&init_peer_id));
+
+        allowed && !self.plumber_config.peer_blocklist.contains(&init_peer_id)
     }
 
-    fn poll_workers_actors(
-        &mut self,
-        cx: &mut Context<'_>,
-        remote_effects: &mut Vec<RemoteRoutingEffects>,
-        local_effects: &mut Vec<LocalRoutingEffects>,
-    ) {
-        for (worker_id, actors) in self.worker_actors.iter_mut() {
-            if let Some(pool) = self.worker_vm_pools.get_mut(worker_id) {
-                let peer_id: PeerId = (*worker_id).into();
-                let host_label = WorkerLabel::new(WorkerType::Worker, peer_id.to_string());
-                Self::poll_actors(
-                    actors,
-                    pool,
-                    &self.scopes,
-                    self.metrics.as_ref(),
-                    cx,
-                    host_label,
-                    remote_effects,
-                    local_effects,
-                );
+    /// Checks whether one more particle may wait for the worker's VM pool to get VMs, if it has none
+    fn has_capacity_for(&self, worker_id: WorkerId) -> bool {
+        let pool_size = self
+            .worker_vm_pools
+            .get(&worker_id)
+            .map(VmPool::pool_size)
+            .or_else(|| {
+                self.queued_worker_pools
+                    .iter()
+                    .find_map(|(id, thread_count)| (*id == worker_id).then_some(*thread_count))
+            });
+        if pool_size != Some(0) {
+            return true;
+        }
+
+        match self.plumber_config.no_capacity_policy {
+            NoCapacityPolicy::Keep => true,
+            NoCapacityPolicy::Reject => false,
+            NoCapacityPolicy::Queue { max_particles } => {
+                let waiting: usize = self
+                    .worker_actors
+                    .get(&worker_id)
+                    .map_or(0, |actors| actors.values().map(|a| a.mailbox_size()).sum());
+                waiting < max_particles
             }
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn poll_actors(
-        actors: &mut HashMap<ActorKey, Actor<RT, F>>,
-        vm_pool: &mut VmPool<RT>,
-        scopes: &PeerScopes,
-        metrics: Option<&ParticleExecutorMetrics>,
-        cx: &mut Context<'_>,
-        label: WorkerLabel,
-        remote_effects: &mut Vec<RemoteRoutingEffects>,
-        local_effects: &mut Vec<LocalRoutingEffects>,
-    ) {
-        let mut mailbox_size = 0;
-        let mut interpretation_stats = vec![];
+    /// All VM pools are busy and particles pile up in the actors' mailboxes
+    pub fn is_saturated(&self) -> bool {
+        let no_free_vms = self.host_vm_pool.free_vms() == 0
+            && self
+                .worker_vm_pools
+                .values()
+                .all(|pool| pool.free_vms() == 0);
 
-        for actor in actors.values_mut() {
-            if let Poll::Ready(result) = actor.poll_completed(cx) {
-                interpretation_stats.push(result.stats);
+        no_free_vms && self.total_mailbox_size() >= self.plumber_config.saturation_mailbox_threshold
+    }
 
-                let mut remote_peers = vec![];
-                let mut local_peers = vec![];
-                for next_peer in result.effects.next_peers {
-                    let scope = scopes.scope(next_peer);
-                    match scope {
-                        Err(_) => {
-                            remote_peers.push(next_peer);
-                        }
-                        Ok(scope) => {
-                            local_peers.push(scope);
-                        }
-                    }
-                }
+    /// Approximate number of bytes held by the buffered events, deferred particles and actors
+    pub fn memory_estimate(&self) -> usize {
+        self.buffered_memory + self.actor_load.memory()
+    }
 
-                if !remote_peers.is_empty() {
-                    remote_effects.push(RemoteRoutingEffects {
-                        particle: result.effects.particle.clone(),
-                        next_peers: remote_peers,
-                    });
-                }
+    fn is_over_memory_budget(&self) -> bool {
+        self.plumber_config
+            .memory_budget
+            .is_some_and(|budget| self.memory_estimate() > budget)
+    }
 
-                if !local_peers.is_empty() {
-                    local_effects.push(LocalRoutingEffects {
-                        particle: result.effects.particle.clone(),
-                        next_peers: local_peers,
-                    });
-                }
+    fn total_mailbox_size(&self) -> usize {
+        self.actor_load.mailbox_size()
+    }
 
-                let (vm_id, vm) = result.runtime;
-                if let Some(vm) = vm {
-                    vm_pool.put_vm(vm_id, vm);
-                } else {
-                    // if `result.vm` is None, then an AVM instance was lost due to
-                    // panic or cancellation, and we must ask VmPool to recreate that AVM
-                    // TODO: add a Count metric to count how often we call `recreate_avm`
-                    vm_pool.recreate_avm(vm_id, cx);
-                }
-            }
-            mailbox_size += actor.mailbox_size();
+    /// Buffers routing effects to be returned from `poll`
+    fn buffer_effects(&mut self, effects: impl IntoIterator<Item = RemoteRoutingEffects>) {
+        for effects in effects {
+            self.buffered_memory += effects_memory_estimate(&effects);
+            self.events.push_back(Ok(effects));
         }
+    }
 
-        if let Some(m) = metrics {
-            for stat in &interpretation_stats {
-                // count particle interpretations
-                if stat.success {
-                    m.interpretation_successes.get_or_create(&label).inc();
-                } else {
-                    m.interpretation_failures.get_or_create(&label).inc();
-                }
+    fn defer(&mut self, deferred: DeferredParticle) {
+        self.buffered_memory += particle_memory_estimate(&deferred.particle.particle);
+        self.deferred.push_back(deferred);
+    }
 
-                let interpretation_time = stat.interpretation_time.as_secs_f64();
-                m.interpretation_time_sec
-                    .get_or_create(&label)
-                    .observe(interpretation_time);
-            }
-            m.total_actors_mailbox
-                .get_or_create(&label)
-                .set(mailbox_size as i64);
-            m.alive_actors
-                .get_or_create(&label)
-                .set(actors.len() as i64);
+    /// Records that effect of the particle couldn't be delivered to the peer
+    pub fn report_delivery_failure(&mut self, particle_id: String, peer_id: PeerId) {
+        tracing::debug!(
+            particle_id = particle_id,
+            "Failed to deliver particle to {}, added to dead letters",
+            peer_id
+        );
+        if self.plumber_config.max_dead_letters == 0 {
+            return;
         }
+        if self.dead_letters.len() >= self.plumber_config.max_dead_letters {
+            self.dead_letters.pop_front();
+        }
+        self.dead_letters.push_back(DeadLetter {
+            particle_id,
+            peer_id,
+            reported_at: now_ms(),
+        });
     }
 
-    fn cleanup(&mut self, cx: &mut Context<'_>) {
-        // do not schedule task if another in progress
-        if let Some(Ready(())) = self.cleanup_future.as_mut().map(|f| f.poll_unpin(cx)) {
-            // we remove clean up future if it is ready
-            self.cleanup_future.take();
+    /// Routes an undelivered effect once again: peers that have become local since then
+    /// are re-ingested, the rest are buffered to be returned from `poll` as remote
+    pub fn requeue_effect(&mut self, effect: RemoteRoutingEffects) {
+        let mut remote_peers = vec![];
+        let mut local_peers = vec![];
+        for next_peer in effect.next_peers {
+            let scope = if self.plumber_config.loopback_peer == Some(next_peer) {
+                Ok(PeerScope::Host)
+            } else {
+                self.scopes.scope(next_peer)
+            };
+            match scope {
+                Ok(scope) => local_peers.push(scope),
+                Err(_) => remote_peers.push(next_peer),
+            }
         }
-        if self.cleanup_future.is_none() {
-            // Remove expired actors
-            let mut cleanup_keys: Vec<(String, PeerId, Vec<u8>, String)> =
-                Vec::with_capacity(MAX_CLEANUP_KEYS_SIZE);
-            let now = now_ms();
-            self.cleanup_host_actors(&mut cleanup_keys, now);
-            self.cleanup_worker_actors(&mut cleanup_keys, now);
 
-            if !cleanup_keys.is_empty() {
-                let data_store = self.data_store.clone();
-                self.cleanup_future =
-                    Some(async move { data_store.batch_cleanup_data(cleanup_keys).await }.boxed())
-            }
+        if !local_peers.is_empty() {
+            self.ingest_local_effects(vec![LocalRoutingEffects {
+                particle: effect.particle.clone(),
+                next_peers: local_peers,
+            }]);
+        }
+        if !remote_peers.is_empty() {
+            self.buffer_effects([RemoteRoutingEffects {
+                particle: effect.particle,
+                next_peers: remote_peers,
+            }]);
+            self.wake();
         }
     }
 
-    fn cleanup_host_actors(
-        &mut self,
-        cleanup_keys: &mut Vec<(String, PeerId, Vec<u8>, String)>,
-        now_ms: u64,
-    ) {
-        Self::cleanup_actors(&mut self.host_actors, cleanup_keys, now_ms)
+    /// Max number of particles whose data is removed in a single cleanup
+    pub fn cleanup_batch_size(&self) -> usize {
+        self.plumber_config.cleanup_batch_size
     }
 
-    fn cleanup_worker_actors(
-        &mut self,
-        cleanup_keys: &mut Vec<(String, PeerId, Vec<u8>, String)>,
-        now_ms: u64,
-    ) {
-        if cleanup_keys.len() >= MAX_CLEANUP_KEYS_SIZE {
+    /// Changes cleanup batch size starting from the next cleanup, the size is at least 1
+    pub fn set_cleanup_batch_size(&mut self, size: usize) {
+        self.plumber_config.cleanup_batch_size = size.max(1);
+    }
+
+    /// Numbers of routing effects and errors buffered to be returned from `poll`
+    pub fn pending_events(&self) -> (usize, usize) {
+        let effects = self.events.iter().filter(|event| event.is_ok()).count();
+        (effects, self.events.len() - effects)
+    }
+
+    /// Undelivered effects, from the oldest to the newest
+    pub fn dead_letters(&self) -> impl Iterator<Item = &DeadLetter> {
+        self.dead_letters.iter()
+    }
+
+    /// Worker pool is created and all its VMs are ready to execute particles
+    pub fn worker_pool_ready(&self, worker_id: WorkerId) -> bool {
+        self.worker_vm_pools
+            .get(&worker_id)
+            .is_some_and(VmPool::warmed_up)
+    }
+
+    /// Creates the worker pool right away or queues it if `worker_pools_per_poll` is set
+    pub fn create_worker_pool(&mut self, worker_id: WorkerId, thread_count: usize) {
+        if self.plumber_config.worker_pools_per_poll.is_none() {
+            self.build_worker_pool(worker_id, thread_count);
             return;
         }
-        self.worker_actors.retain(|worker_id, actors| {
-            Self::cleanup_actors(actors, cleanup_keys, now_ms);
 
-            !actors.is_empty() || self.worker_vm_pools.contains_key(worker_id)
-        });
+        self.queued_worker_pools.retain(|(id, _)| *id != worker_id);
+        self.queued_worker_pools
+            .push_back((worker_id, thread_count));
+        self.wake();
     }
 
-    fn cleanup_actors(
-        map: &mut HashMap<ActorKey, Actor<RT, F>>,
-        cleanup_keys: &mut Vec<(String, PeerId, Vec<u8>, String)>,
-        now_ms: u64,
-    ) {
-        map.retain(|_, actor| {
-            if cleanup_keys.len() >= MAX_CLEANUP_KEYS_SIZE {
-                return true;
-            }
-            // if actor hasn't yet expired or is still executing, keep it
-            if !actor.is_expired(now_ms) || actor.is_executing() {
-                return true; // keep actor
-            }
-            cleanup_keys.push(actor.cleanup_key());
-            false // remove actor
-        });
+    /// Number of worker pools waiting to be created
+    pub fn queued_worker_pools(&self) -> usize {
+        self.queued_worker_pools.len()
     }
 
-    fn poll_next_host_messages(&mut self, cx: &mut Context<'_>) -> Vec<SingleCallStat> {
-        let mut stats = vec![];
-        for actor in self.host_actors.values_mut() {
-            if let Some((vm_id, vm)) = self.host_vm_pool.get_vm() {
-                match actor.poll_next(vm_id, vm, cx) {
-                    ActorPoll::Vm(vm_id, vm) => self.host_vm_pool.put_vm(vm_id, vm),
-                    ActorPoll::Executing(mut s) => stats.append(&mut s),
-                }
-            } else {
-                break;
-            }
+    /// Settings of the host pool or the worker pool, `None` if the worker has no pool right now
+    pub fn vm_pool_config(&self, peer_scope: PeerScope) -> Option<VmPoolConfigSummary<RT::Config>> {
+        match peer_scope {
+            PeerScope::Host => Some(self.host_vm_pool.config_summary()),
+            PeerScope::WorkerId(worker_id) => self
+                .worker_vm_pools
+                .get(&worker_id)
+                .map(VmPool::config_summary),
         }
-        stats
     }
 
-    fn poll_next_worker_messages(&mut self, cx: &mut Context<'_>) -> Vec<SingleCallStat> {
-        let mut stats = vec![];
+    /// State of the host and worker actors, for debugging stuck particles
+    pub fn inspect(&self) -> Vec<ActorInspection> {
+        let now_ms = now_ms();
+        let host = self
+            .host_actors
+            .values()
+            .map(|actor| (PeerScope::Host, actor));
+        let workers = self.worker_actors.iter().flat_map(|(worker_id, actors)| {
+            actors
+                .values()
+                .map(|actor| (PeerScope::WorkerId(*worker_id), actor))
+        });
+        host.chain(workers)
+            .map(|(peer_scope, actor)| ActorInspection {
+                peer_scope,
+                particle_id: actor.particle_id().to_string(),
+                mailbox_size: actor.mailbox_size(),
+                is_executing: actor.is_executing(),
+                expires_in_ms: actor.expires_in(now_ms).as_millis() as u64,
+            })
+            .collect()
+    }
 
-        for (worker_id, actors) in self.worker_actors.iter_mut() {
-            if let Some(pool) = self.worker_vm_pools.get_mut(worker_id) {
-                for actor in actors.values_mut() {
-                    if let Some((vm_id, vm)) = pool.get_vm() {
-                        match actor.poll_next(vm_id, vm, cx) {
-                            ActorPoll::Vm(vm_id, vm) => pool.put_vm(vm_id, vm),
-                            ActorPoll::Executing(mut s) => stats.append(&mut s),
-                        }
-                    } else {
-                        break;
-                    }
-                }
+    fn build_worker_pool(&mut self, worker_id: WorkerId, thread_count: usize) {
+        let peer_id: PeerId = worker_id.into();
+        let label = WorkerLabel::new(WorkerType::Worker, peer_id.to_string());
+        let vm_pool = VmPool::new(
+            thread_count,
+            self.config.clone(),
+            self.host_vm_pool
+                .metrics()
+                .map(VmPoolMetrics::for_another_pool),
+            label,
+            None,
+            self.avm_wasm_backend.clone(),
+            self.host_vm_pool.recreation_policy().clone(),
+        );
+        self.worker_vm_pools.insert(worker_id, vm_pool);
+        self.idle_worker_pools.remove(&worker_id);
+        self.worker_last_executed.insert(worker_id, now_ms());
+    }
+
+    /// Removes pools of the workers that haven't executed particles for `worker_pool_idle_timeout`
+    fn shutdown_idle_pools(&mut self) {
+        let Some(idle_timeout) = self.plumber_config.worker_pool_idle_timeout else {
+            return;
+        };
+
+        let now = now_ms();
+        let idle_timeout = idle_timeout.as_millis() as u64;
+        let idle: Vec<WorkerId> = self
+            .worker_vm_pools
+            .keys()
+            .filter(|worker_id| {
+                let last_executed = self.worker_last_executed.get(worker_id).copied();
+                let expired = last_executed.map_or(true, |t| now.saturating_sub(t) >= idle_timeout);
+                // Actors with pending work need the pool
+                let busy = self.worker_actors.get(worker_id).is_some_and(|actors| {
+                    actors
+                        .values()
+                        .any(|a| a.is_executing() || a.mailbox_size() > 0 || a.has_pending_calls())
+                });
+                expired && !busy
+            })
+            .copied()
+            .collect();
+
+        for worker_id in idle {
+            if let Some(pool) = self.worker_vm_pools.remove(&worker_id) {
+                tracing::info!(
+                    worker_id = worker_id.to_string(),
+                    "Worker is idle, its VM pool is removed"
+                );
+                self.idle_worker_pools.insert(worker_id, pool.pool_size());
+                self.worker_last_executed.remove(&worker_id);
             }
         }
-        stats
     }
 
-    fn wake(&self) {
-        if let Some(waker) = &self.waker {
-            waker.wake_by_ref();
+    pub fn remove_worker_pool(&mut self, worker_id: WorkerId) {
+        self.queued_worker_pools.retain(|(id, _)| *id != worker_id);
+        self.idle_worker_pools.remove(&worker_id);
+        self.worker_last_executed.remove(&worker_id);
+        self.worker_vm_pools.remove(&worker_id);
+        self.worker_error_subscribers.remove(&worker_id);
+        if let Some(away) = self.polled_away.get_mut(&worker_id) {
+            away.pool_removed = true;
         }
     }
 
-    fn meter<U, FF: Fn(&ParticleExecutorMetrics) -> U>(&self, f: FF) {
-        self.metrics.as_ref().map(f);
-    }
-}
+    /// Wipes execution state of the worker: removes all its actors with their mailboxes,
+    /// cancels in-flight interpretations and schedules cleanup of their particle data.
+    /// Unlike `remove_worker_pool`, the worker pool stays available.
+    ///
+    /// Under `ResetMode::Graceful` the worker stops taking new particles for execution
+    /// and is reset on `poll` once interpretations in progress are finished, `None` is returned then.
+    pub fn reset_worker(&mut self, worker_id: WorkerId, mode: ResetMode) -> Option<ResetReport> {
+        let timeout = match mode {
+            ResetMode::Immediate => return Some(self.wipe_worker(worker_id)),
+            ResetMode::Graceful { timeout } => timeout,
+        };
 
-fn get_particle_token(key_pair: &KeyPair, signature: &Vec<u8>) -> eyre::Result<String> {
-    let particle_token = key_pair.sign(signature.as_slice()).map_err(|err| {
-        eyre!(
-            "Could not produce particle token by signing the particle signature: {}",
-            err
-        )
-    })?;
-    Ok(bs58::encode(particle_token.to_vec()).into_string())
-}
+        let executing = self.worker_actors.get(&worker_id).map_or(0, |actors| {
+            actors.values().filter(|a| a.is_executing()).count()
+        });
+        if executing == 0 {
+            return Some(self.wipe_worker(worker_id));
+        }
 
-/// Implements `now` by taking number of non-leap seconds from `Utc::now()`
-mod real_time {
-    #[allow(dead_code)]
-    pub fn now_ms() -> u64 {
-        (chrono::Utc::now().timestamp() * 1000) as u64
+        tracing::info!(
+            worker_id = worker_id.to_string(),
+            "Worker will be reset after {executing} interpretations in progress finish"
+        );
+        let reset_at = now_ms() + timeout.as_millis() as u64;
+        self.pending_resets.insert(worker_id, reset_at);
+        None
     }
+
+    /// Resets workers whose interpretations finished or didn't finish in time
+    fn poll_pending_resets(&mut self) {
+        if self.pending_resets.is_empty() {
+            return;
+        }
+
+        let now = now_ms();
+        let ready: Vec<WorkerId> = self
+            .pending_resets
+            .iter()
+            .filter(|(worker_id, reset_at)| {
+                let executing = self
+                    .worker_actors
+                    .get(worker_id)
+                    .is_some_and(|actors| actors.values().any(|a| a.is_executing()));
+                !executing || now >= **reset_at
+            })
+            .map(|(worker_id, _)| *worker_id)
+            .collect();
+
+        for worker_id in ready {
+            self.pending_resets.remove(&worker_id);
+            self.wipe_worker(worker_id);
+        }
+    }
+
+    fn wipe_worker(&mut self, worker_id: WorkerId) -> ResetReport {
+        self.pending_resets.remove(&worker_id);
+        if let Some(away) = self.polled_away.get_mut(&worker_id) {
+            away.reset = true;
+        }
+        let mut report = ResetReport::default();
+        let actors = match self.worker_actors.get_mut(&worker_id) {
+            Some(actors) => std::mem::take(actors),
+            None => return report,
+        };
+
+        let mut pool = self.worker_vm_pools.get_mut(&worker_id);
+        for actor in actors.into_values() {
+            report.actors += 1;
+            report.dropped_particles += actor.mailbox_size();
+            if actor.is_executing() {
+                report.cancelled += 1;
+            }
+            report.cleanup_keys += 1;
+            Self::cancel_actor(actor, pool.as_deref_mut(), &mut self.pending_cleanup_keys);
+        }
+
+        tracing::info!(
+            worker_id = worker_id.to_string(),
+            "Worker was reset: {:?}",
+            report
+        );
+        self.wake();
+
+        report
+    }
+
+    /// Returns usage accumulated by the worker since the previous call and starts counting from zero
+    pub fn take_worker_usage(&mut self, worker_id: WorkerId) -> DealUsage {
+        self.worker_usage.remove(&worker_id).unwrap_or_default()
+    }
+
+    /// Replaces the signer of particle tokens, the root key pair is used by default
+    pub fn set_token_signer(&mut self, token_signer: Arc<dyn ParticleTokenSigner>) {
+        self.token_signer = token_signer;
+    }
+
+    /// Cleans up the worker of the expired deal without waiting for its particles to expire:
+    /// removes its actors and pool and schedules cleanup of their particle data.
+    /// Returns `None` if there's no worker for the deal.
+    pub fn on_deal_expired(&mut self, deal_id: DealId) -> Option<ResetReport> {
+        let worker_id = match self.workers.get_worker_id(deal_id.clone()) {
+            Ok(worker_id) => worker_id,
+            Err(err) => {
+                tracing::warn!(
+                    deal_id = deal_id.to_string(),
+                    "Deal expired, but its worker wasn't found: {err}"
+                );
+                return None;
+            }
+        };
+
+        let report = self.wipe_worker(worker_id);
+        self.remove_worker_pool(worker_id);
+        tracing::info!(
+            deal_id = deal_id.to_string(),
+            worker_id = worker_id.to_string(),
+            "Deal expired, worker actors are removed"
+        );
+
+        Some(report)
+    }
+
+    /// Cancels and removes all actors of particles initiated by the peer, both on host and on workers.
+    /// Returns the number of removed actors.
+    pub fn cancel_by_init_peer(&mut self, init_peer_id: PeerId) -> usize {
+        let mut cancelled = Self::cancel_actors_of(
+            &mut self.host_actors,
+            Some(&mut self.host_vm_pool),
+            &mut self.pending_cleanup_keys,
+            init_peer_id,
+        );
+        for (worker_id, actors) in self.worker_actors.iter_mut() {
+            cancelled += Self::cancel_actors_of(
+                actors,
+                self.worker_vm_pools.get_mut(worker_id),
+                &mut self.pending_cleanup_keys,
+                init_peer_id,
+            );
+        }
+
+        tracing::info!(
+            "Cancelled {} actors of particles initiated by {}",
+            cancelled,
+            init_peer_id
+        );
+        self.wake();
+
+        cancelled
+    }
+
+    fn cancel_actors_of(
+        actors: &mut HashMap<ActorKey, Actor<RT, F, DS>>,
+        mut pool: Option<&mut VmPool<RT>>,
+        cleanup_keys: &mut Vec<CleanupKey>,
+        init_peer_id: PeerId,
+    ) -> usize {
+        let keys: Vec<ActorKey> = actors
+            .iter()
+            .filter(|(_, actor)| actor.init_peer_id() == init_peer_id)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &keys {
+            if let Some(actor) = actors.remove(key) {
+                Self::cancel_actor(actor, pool.as_deref_mut(), cleanup_keys);
+            }
+        }
+
+        keys.len()
+    }
+
+    /// Drops the actor along with its in-flight AVM call and schedules cleanup of its data
+    fn cancel_actor(
+        actor: Actor<RT, F, DS>,
+        pool: Option<&mut VmPool<RT>>,
+        cleanup_keys: &mut Vec<CleanupKey>,
+    ) {
+        // VM is owned by the dropped AVM call future, so it has to be recreated
+        if let (Some(vm_id), Some(pool)) = (actor.executing_vm_id(), pool) {
+            pool.release_lost_vm(vm_id);
+        }
+        cleanup_keys.push(actor.cleanup_key());
+    }
+
+    fn get_or_create_actor(
+        &mut self,
+        peer_scope: PeerScope,
+        key: ActorKey,
+        particle: &ExtendedParticle,
+    ) -> eyre::Result<&mut Actor<RT, F, DS>> {
+        let plumber_params = PlumberParams {
+            builtins: &self.builtins,
+            key_storage: self.key_storage.as_ref(),
+            data_store: self.data_store.clone(),
+            token_signer: self.token_signer.as_ref(),
+            actor_load: self.actor_load.clone(),
+        };
+        let init_peer_id = particle.particle.init_peer_id;
+        let priority = self.scopes.is_management(init_peer_id) || self.scopes.is_host(init_peer_id);
+        let mut deadline = self.deadline(&particle.particle);
+        if let Some(saturated_ttl) = self.plumber_config.saturated_ttl {
+            // Don't commit to long-lived work under load
+            if self.is_saturated() {
+                deadline = deadline.clamp(now_ms(), saturated_ttl.as_millis() as u64);
+            }
+        }
+        match peer_scope {
+            PeerScope::Host => {
+                let current_peer_id = self.scopes.get_host_peer_id();
+                let spawner = Spawner::Root(RootSpawner::new(self.root_runtime_handle.clone()));
+                let actor_params = ActorParams {
+                    key,
+                    particle,
+                    deadline,
+                    peer_scope,
+                    current_peer_id,
+                    deal_id: None,
+                    spawner,
+                    priority,
+                };
+                Self::create_actor(&mut self.host_actors, plumber_params, actor_params)
+            }
+            PeerScope::WorkerId(worker_id) => {
+                let worker_actors = match self.worker_actors.entry(worker_id) {
+                    Entry::Occupied(o) => o.into_mut(),
+                    Entry::Vacant(v) => v.insert(HashMap::default()),
+                };
+                let current_peer_id: PeerId = worker_id.into();
+                let deal_id = self
+                    .workers
+                    .get_deal_id(worker_id)
+                    .map_err(|err| eyre!("Not found deal for {:?} : {}", worker_id, err))?;
+                let runtime_handle = get_runtime_handle(&self.workers, worker_id)
+                    .ok_or(eyre!("Not found runtime handle for {:?}", worker_id))?;
+                let spawner = Spawner::Worker(WorkerSpawner::new(runtime_handle, worker_id));
+
+                let actor_params = ActorParams {
+                    key,
+                    particle,
+                    deadline,
+                    peer_scope,
+                    current_peer_id,
+                    deal_id: Some(deal_id),
+                    spawner,
+                    priority,
+                };
+
+                Self::create_actor(worker_actors, plumber_params, actor_params)
+            }
+        }
+    }
+
+    fn create_actor<'p>(
+        actors: &'p mut HashMap<ActorKey, Actor<RT, F, DS>>,
+        plumber_params: PlumberParams<'p, F, DS>,
+        actor_params: ActorParams<'_>,
+    ) -> eyre::Result<&'p mut Actor<RT, F, DS>> {
+        let entry = actors.entry(actor_params.key);
+        let actor = match entry {
+            Entry::Occupied(actor) => actor.into_mut(),
+            Entry::Vacant(entry) => {
+                let builtins = plumber_params.builtins;
+                let key_pair = plumber_params
+                    .key_storage
+                    .get_keypair(actor_params.peer_scope)
+                    .ok_or(eyre!(
+                        "Cannot create actor, no key pair for {:?}",
+                        actor_params.peer_scope
+                    ))?;
+                let data_store = plumber_params.data_store.clone();
+
+                let particle_token = get_particle_token(
+                    plumber_params.token_signer,
+                    &actor_params.particle.particle.signature,
+                )?;
+                let params = ParticleParams::clone_from(
+                    &actor_params.particle.particle,
+                    actor_params.peer_scope,
+                    particle_token.clone(),
+                );
+                let functions = Functions::new(params, builtins.clone());
+
+                let actor = Actor::new(
+                    &actor_params.particle.particle,
+                    actor_params.deadline,
+                    functions,
+                    actor_params.current_peer_id,
+                    particle_token,
+                    key_pair,
+                    data_store,
+                    actor_params.deal_id,
+                    actor_params.spawner,
+                    actor_params.priority,
+                    plumber_params.actor_load,
+                );
+                entry.insert(actor)
+            }
+        };
+        Ok(actor)
+    }
+
+    pub fn add_service(
+        &self,
+        service: String,
+        functions: HashMap<String, ServiceFunction>,
+        fallback: Option<ServiceFunction>,
+    ) -> Result<(), ServiceTaskError> {
+        let builtins = self.builtins.clone();
+        let service_tasks = self.service_tasks.clone();
+        let task = async move {
+            let _permit = Self::acquire_service_task(service_tasks).await;
+            builtins.extend(service, functions, fallback).await;
+        };
+        task::Builder::new().name("Add service").spawn(task)?;
+        Ok(())
+    }
+
+    pub fn remove_service(&self, service: String) -> Result<(), ServiceTaskError> {
+        let builtins = self.builtins.clone();
+        let service_tasks = self.service_tasks.clone();
+        let task = async move {
+            let _permit = Self::acquire_service_task(service_tasks).await;
+            builtins.remove(&service).await;
+        };
+        task::Builder::new().name("Remove service").spawn(task)?;
+        Ok(())
+    }
+
+    /// Waits for a slot for a service task, so tasks over the limit are queued
+    async fn acquire_service_task(service_tasks: Arc<Semaphore>) -> OwnedSemaphorePermit {
+        service_tasks
+            .acquire_owned()
+            .await
+            .expect("Service tasks semaphore is never closed")
+    }
+
+    pub fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<RemoteRoutingEffects, AquamarineApiError>> {
+        self.waker = Some(cx.waker().clone());
+
+        self.create_queued_pools(cx);
+        self.poll_pools(cx);
+        self.poll_verifications(cx);
+        self.poll_deferred(cx);
+
+        if let Some(event) = self.events.pop_front() {
+            if let Ok(effects) = &event {
+                self.buffered_memory -= effects_memory_estimate(effects);
+            }
+            return Poll::Ready(event);
+        }
+
+        let started = Instant::now();
+        let mut remote_effects: Vec<RemoteRoutingEffects> = vec![];
+        let mut local_effects: Vec<LocalRoutingEffects> = vec![];
+        if self.poll_stage == PollStage::Actors {
+            // Gather effects and put VMs back, the time budget is checked after each actor
+            let deadline = self
+                .plumber_config
+                .poll_time_budget
+                .map(|budget| started + budget);
+            let host_polled =
+                self.poll_host_actors(cx, deadline, &mut remote_effects, &mut local_effects);
+            if self.actors_cursor.is_some() {
+                return self.yield_poll(cx, remote_effects, local_effects);
+            }
+            self.poll_workers_actors(
+                cx,
+                deadline,
+                host_polled,
+                true,
+                &mut remote_effects,
+                &mut local_effects,
+            );
+            if self.actors_cursor.is_some() {
+                return self.yield_poll(cx, remote_effects, local_effects);
+            }
+            self.poll_pending_resets();
+            self.shutdown_idle_pools();
+
+            if self.is_over_poll_budget(started, PollStage::Cleanup) {
+                return self.yield_poll(cx, remote_effects, local_effects);
+            }
+        }
+
+        if self.poll_stage == PollStage::Cleanup {
+            self.evict_over_budget();
+            self.cleanup(cx);
+
+            if self.is_over_poll_budget(started, PollStage::NextMessages) {
+                return self.yield_poll(cx, remote_effects, local_effects);
+            }
+        }
+
+        // Execute next messages
+        self.poll_stage = PollStage::Actors;
+        let host_call_stats = self.poll_next_host_messages(cx);
+        let workers_call_stats = self.poll_next_worker_messages(cx);
+
+        self.meter(|m| {
+            let host_label =
+                WorkerLabel::new(WorkerType::Host, self.scopes.get_host_peer_id().to_string());
+            for stat in &host_call_stats {
+                m.service_call(&host_label, stat.success, stat.kind, stat.call_time)
+            }
+            for (worker_id, stats) in &workers_call_stats {
+                let peer_id: PeerId = (*worker_id).into();
+                let worker_label = WorkerLabel::new(WorkerType::Worker, peer_id.to_string());
+                for stat in stats {
+                    m.service_call(&worker_label, stat.success, stat.kind, stat.call_time)
+                }
+            }
+        });
+
+        self.ingest_local_effects(local_effects);
+
+        // Turn effects into events, and buffer them
+        self.buffer_effects(remote_effects);
+
+        Poll::Pending
+    }
+
+    /// Moves poll to the `next` stage, returns true if it should yield before running it
+    fn is_over_poll_budget(&mut self, started: Instant, next: PollStage) -> bool {
+        self.poll_stage = next;
+        self.plumber_config
+            .poll_time_budget
+            .is_some_and(|budget| started.elapsed() >= budget)
+    }
+
+    /// Buffers the gathered effects and returns control to the runtime, asking to be polled again
+    fn yield_poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        remote_effects: Vec<RemoteRoutingEffects>,
+        local_effects: Vec<LocalRoutingEffects>,
+    ) -> Poll<Result<RemoteRoutingEffects, AquamarineApiError>> {
+        self.ingest_local_effects(local_effects);
+        self.buffer_effects(remote_effects);
+        cx.waker().wake_by_ref();
+
+        Poll::Pending
+    }
+
+    /// Spreads VM instantiation of the queued worker pools over several polls
+    fn create_queued_pools(&mut self, cx: &mut Context<'_>) {
+        let Some(per_poll) = self.plumber_config.worker_pools_per_poll else {
+            return;
+        };
+
+        for _ in 0..per_poll.max(1) {
+            match self.queued_worker_pools.pop_front() {
+                Some((worker_id, thread_count)) => self.build_worker_pool(worker_id, thread_count),
+                None => return,
+            }
+        }
+        if !self.queued_worker_pools.is_empty() {
+            cx.waker().wake_by_ref();
+        }
+    }
+
+    fn poll_pools(&mut self, cx: &mut Context<'_>) {
+        self.host_vm_pool.poll(cx);
+        for (_, vm_pool) in self.worker_vm_pools.iter_mut() {
+            vm_pool.poll(cx);
+        }
+    }
+
+    /// Re-ingests particle to the local peers, counting it as one more hop
+    fn ingest_local_effects(&mut self, local_effects: Vec<LocalRoutingEffects>) {
+        self.meter(|m| {
+            let mut reingests = 0;
+            for effect in &local_effects {
+                m.local_effect_fanout(effect.next_peers.len());
+                reingests += effect.next_peers.len() as u64;
+            }
+            m.counter(ExecutorCounter::LocalReingests, reingests);
+        });
+        for effect in local_effects {
+            self.ingest_local_effect(effect);
+        }
+    }
+
+    fn ingest_local_effect(&mut self, effect: LocalRoutingEffects) {
+        let hops = effect.particle.hops + 1;
+        for local_peer in effect.next_peers {
+            let span = tracing::info_span!(parent: effect.particle.span.as_ref(), "Plumber: routing effect ingest");
+            let _guard = span.enter();
+            self.ingest(
+                effect.particle.clone().with_hops(hops),
+                None,
+                local_peer,
+                ParticleOrigin::LocalEffect,
+            );
+        }
+    }
+
+    /// Ingests deferred particles whose worker runtime has appeared,
+    /// drops the expired ones and the ones waited for too long.
+    /// Schedules a wake-up to retry the ones that keep waiting.
+    fn poll_deferred(&mut self, cx: &mut Context<'_>) {
+        if self.deferred.is_empty() {
+            self.deferred_timer = None;
+            return;
+        }
+
+        let now = now_ms();
+        for deferred in std::mem::take(&mut self.deferred) {
+            let worker_id = deferred.worker_id;
+            let peer_scope = PeerScope::WorkerId(worker_id);
+            let particle_id = deferred.particle.particle.id.clone();
+            self.buffered_memory -= particle_memory_estimate(&deferred.particle.particle);
+            if self.deadline(&deferred.particle.particle).is_expired(now) {
+                tracing::info!(target: "expired", particle_id, "Deferred particle is expired");
+                self.push_error(
+                    peer_scope,
+                    AquamarineApiError::ParticleExpired { particle_id },
+                );
+            } else if get_runtime_handle(&self.workers, worker_id).is_some() {
+                self.ingest_to_actor(deferred.particle, deferred.function, peer_scope);
+            } else if now > deferred.retry_until {
+                tracing::warn!(
+                    particle_id,
+                    worker_id = worker_id.to_string(),
+                    "Worker runtime didn't appear in time, rejected particle"
+                );
+                self.push_error(
+                    peer_scope,
+                    AquamarineApiError::WorkerRuntimeNotFound {
+                        worker_id: worker_id.to_string(),
+                        particle_id,
+                    },
+                );
+            } else {
+                self.defer(deferred);
+            }
+        }
+
+        self.schedule_deferred_retry(cx, now);
+    }
+
+    /// Wakes the plumber once the first deferred particle is to be dropped,
+    /// but no later than in `DEFERRED_RETRY_INTERVAL`
+    fn schedule_deferred_retry(&mut self, cx: &mut Context<'_>, now: u64) {
+        let Some(retry_until) = self.deferred.iter().map(|d| d.retry_until).min() else {
+            self.deferred_timer = None;
+            return;
+        };
+
+        // the particle is dropped once the time is past `retry_until`
+        let delay = Duration::from_millis(retry_until.saturating_sub(now) + 1);
+        let deadline = tokio::time::Instant::now() + delay.min(DEFERRED_RETRY_INTERVAL);
+        let timer = match &mut self.deferred_timer {
+            Some(timer) if timer.deadline() <= deadline && !timer.is_elapsed() => timer,
+            timer => timer.insert(Box::pin(tokio::time::sleep_until(deadline))),
+        };
+        if timer.as_mut().poll(cx).is_ready() {
+            self.deferred_timer = None;
+            cx.waker().wake_by_ref();
+        }
+    }
+
+    /// Returns true if any actor was polled
+    fn poll_host_actors(
+        &mut self,
+        cx: &mut Context<'_>,
+        deadline: Option<Instant>,
+        remote_effects: &mut Vec<RemoteRoutingEffects>,
+        local_effects: &mut Vec<LocalRoutingEffects>,
+    ) -> bool {
+        let mut cursor = match self.actors_cursor.take() {
+            Some(ActorsCursor::Host(key)) => Some(key),
+            // Host actors were polled before yielding
+            worker @ Some(ActorsCursor::Worker(..)) => {
+                self.actors_cursor = worker;
+                return false;
+            }
+            None => None,
+        };
+        let polled = self
+            .host_actors
+            .keys()
+            .any(|key| cursor.as_ref().map_or(true, |cursor| key > cursor));
+
+        let host_label =
+            WorkerLabel::new(WorkerType::Host, self.scopes.get_host_peer_id().to_string());
+        let mut avm_errors = vec![];
+        Self::poll_actors(
+            &mut self.host_actors,
+            &mut self.host_vm_pool,
+            &self.scopes,
+            self.metrics.as_deref(),
+            cx,
+            host_label,
+            remote_effects,
+            local_effects,
+            &self.plumber_config,
+            &mut self.pending_cleanup_keys,
+            &mut avm_errors,
+            now_ms(),
+            &mut cursor,
+            deadline,
+        );
+        self.actors_cursor = cursor.map(ActorsCursor::Host);
+        for err in avm_errors {
+            self.push_error(PeerScope::Host, err);
+        }
+        polled
+    }
+
+    /// Polls the workers' actors, `polled_before` tells that some actors were polled before them.
+    /// `parallel` allows sending them to be polled on the workers' runtimes
+    fn poll_workers_actors(
+        &mut self,
+        cx: &mut Context<'_>,
+        deadline: Option<Instant>,
+        polled_before: bool,
+        parallel: bool,
+        remote_effects: &mut Vec<RemoteRoutingEffects>,
+        local_effects: &mut Vec<LocalRoutingEffects>,
+    ) {
+        let now = now_ms();
+        let parallelism = self
+            .plumber_config
+            .worker_polling_threads
+            .filter(|threads| parallel && *threads > 1 && self.worker_actors.len() > 1);
+        let mut polled = match parallelism {
+            Some(parallelism) => {
+                self.poll_workers_on_runtimes(parallelism, now, cx);
+                vec![]
+            }
+            None => self.poll_workers_in_turn(cx, now, deadline, polled_before),
+        };
+        // Workers sent for polling earlier are collected whichever way the workers are polled now
+        polled.extend(self.collect_worker_polls(cx));
+
+        for (worker_id, poll) in polled {
+            remote_effects.extend(poll.remote_effects);
+            local_effects.extend(poll.local_effects);
+            self.pending_cleanup_keys.extend(poll.cleanup_keys);
+            if !poll.stats.is_empty() {
+                let usage = self.worker_usage.entry(worker_id).or_default();
+                poll.stats.iter().for_each(|stat| usage.add(stat));
+            }
+            for err in poll.avm_errors {
+                self.push_error(PeerScope::WorkerId(worker_id), err);
+            }
+        }
+    }
+
+    /// Polls the workers' actors one worker after another until the time is over
+    fn poll_workers_in_turn(
+        &mut self,
+        cx: &mut Context<'_>,
+        now: u64,
+        deadline: Option<Instant>,
+        polled_before: bool,
+    ) -> Vec<(WorkerId, WorkerPoll)> {
+        let cursor = match self.actors_cursor.take() {
+            Some(ActorsCursor::Worker(worker_id, key)) => Some((worker_id, key)),
+            _ => None,
+        };
+        let mut pools: HashMap<WorkerId, &mut VmPool<RT>> = self
+            .worker_vm_pools
+            .iter_mut()
+            .map(|(worker_id, pool)| (*worker_id, pool))
+            .collect();
+        let mut groups: Vec<_> = self
+            .worker_actors
+            .iter_mut()
+            .filter(|(worker_id, _)| cursor.as_ref().map_or(true, |(w, _)| *worker_id >= w))
+            .filter_map(|(worker_id, actors)| {
+                let pool = pools.remove(worker_id)?;
+                Some((*worker_id, actors, pool))
+            })
+            .collect();
+        if deadline.is_some() {
+            groups.sort_by_key(|(worker_id, _, _)| *worker_id);
+        }
+
+        let mut polled_groups = vec![];
+        let mut next_cursor = None;
+        let mut polled = polled_before;
+        let mut resume_after = cursor.and_then(|(_, key)| key);
+        for (worker_id, actors, pool) in groups {
+            if polled && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                next_cursor = Some(ActorsCursor::Worker(worker_id, None));
+                break;
+            }
+            polled |= !actors.is_empty();
+            let poll = Self::poll_worker_group(
+                (worker_id, actors, pool),
+                &self.scopes,
+                self.metrics.as_deref(),
+                &self.plumber_config,
+                now,
+                resume_after.take(),
+                deadline,
+                cx,
+            );
+            let stopped_at = poll.stopped_at.clone();
+            polled_groups.push((worker_id, poll));
+            if let Some(key) = stopped_at {
+                next_cursor = Some(ActorsCursor::Worker(worker_id, Some(key)));
+                break;
+            }
+        }
+        self.actors_cursor = next_cursor;
+
+        polled_groups
+    }
+
+    /// Sends actors of the workers to be polled on the workers' own runtimes, so that at most
+    /// `parallelism` workers are away at once. Actors and the pool of a worker are moved
+    /// to the polling task and put back by `collect_worker_polls`, the plumber isn't blocked
+    /// meanwhile. Workers go in turn, starting after the one sent the last.
+    fn poll_workers_on_runtimes(&mut self, parallelism: usize, now: u64, cx: &mut Context<'_>) {
+        // Cursor is only kept by the polling in turn
+        self.actors_cursor = None;
+
+        let available = parallelism.saturating_sub(self.worker_polls.len());
+        if available == 0 {
+            return;
+        }
+        let mut worker_ids: Vec<WorkerId> = self
+            .worker_actors
+            .iter()
+            .filter(|(worker_id, actors)| {
+                !actors.is_empty()
+                    && !self.polled_away.contains_key(worker_id)
+                    && self.worker_vm_pools.contains_key(worker_id)
+            })
+            .map(|(worker_id, _)| *worker_id)
+            .collect();
+        worker_ids.sort();
+        if let Some(last) = self.last_polled_away {
+            let after_last = worker_ids.partition_point(|worker_id| *worker_id <= last);
+            worker_ids.rotate_left(after_last);
+        }
+
+        for worker_id in worker_ids.into_iter().take(available) {
+            let Some(mut pool) = self.worker_vm_pools.remove(&worker_id) else {
+                continue;
+            };
+            let mut actors = self
+                .worker_actors
+                .get_mut(&worker_id)
+                .map(std::mem::take)
+                .unwrap_or_default();
+            let handle = get_runtime_handle(&self.workers, worker_id)
+                .unwrap_or_else(|| self.root_runtime_handle.clone());
+            let scopes = self.scopes.clone();
+            let metrics = self.metrics.clone();
+            let config = self.plumber_config.clone();
+            let waker = cx.waker().clone();
+            let polling = handle.spawn(async move {
+                // Actors wake the plumber, not the polling task
+                let mut cx = Context::from_waker(&waker);
+                let poll = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    Self::poll_worker_group(
+                        (worker_id, &mut actors, &mut pool),
+                        &scopes,
+                        metrics.as_deref(),
+                        &config,
+                        now,
+                        None,
+                        None,
+                        &mut cx,
+                    )
+                }))
+                .ok();
+                (actors, pool, poll)
+            });
+            self.worker_polls
+                .push(polling.map(move |result| (worker_id, result)).boxed());
+            self.polled_away.insert(worker_id, PolledAway::default());
+            self.last_polled_away = Some(worker_id);
+        }
+    }
+
+    /// Puts back actors and pools of the workers whose polling on their runtimes has finished,
+    /// then ingests the particles arrived for these workers meanwhile
+    fn collect_worker_polls(&mut self, cx: &mut Context<'_>) -> Vec<(WorkerId, WorkerPoll)> {
+        let mut polled = vec![];
+        while let Poll::Ready(Some((worker_id, result))) = self.worker_polls.poll_next_unpin(cx) {
+            let away = self.polled_away.remove(&worker_id).unwrap_or_default();
+            match result {
+                Ok((actors, pool, poll)) => {
+                    self.worker_actors
+                        .entry(worker_id)
+                        .or_default()
+                        .extend(actors);
+                    if !away.pool_removed {
+                        self.worker_vm_pools.entry(worker_id).or_insert(pool);
+                    }
+                    match poll {
+                        Some(poll) => polled.push((worker_id, poll)),
+                        None => tracing::error!(
+                            worker_id = worker_id.to_string(),
+                            "Polling of the worker actors panicked, they are put back as they are"
+                        ),
+                    }
+                }
+                Err(err) => tracing::error!(
+                    worker_id = worker_id.to_string(),
+                    "Polling of the worker actors failed, they are lost: {err}"
+                ),
+            }
+
+            if away.reset {
+                self.wipe_worker(worker_id);
+            }
+            for (particle, function) in away.particles {
+                self.buffered_memory -= particle_memory_estimate(&particle.particle);
+                self.ingest_to_actor(particle, function, PeerScope::WorkerId(worker_id));
+            }
+        }
+
+        polled
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn poll_worker_group(
+        (worker_id, actors, pool): WorkerActors<'_, RT, F, DS>,
+        scopes: &PeerScopes,
+        metrics: Option<&dyn MetricsSink>,
+        config: &PlumberConfig,
+        now: u64,
+        mut resume_after: Option<ActorKey>,
+        deadline: Option<Instant>,
+        cx: &mut Context<'_>,
+    ) -> WorkerPoll {
+        let peer_id: PeerId = worker_id.into();
+        let label = WorkerLabel::new(WorkerType::Worker, peer_id.to_string());
+        let mut poll = WorkerPoll::default();
+        poll.stats = Self::poll_actors(
+            actors,
+            pool,
+            scopes,
+            metrics,
+            cx,
+            label,
+            &mut poll.remote_effects,
+            &mut poll.local_effects,
+            config,
+            &mut poll.cleanup_keys,
+            &mut poll.avm_errors,
+            now,
+            &mut resume_after,
+            deadline,
+        );
+        poll.stopped_at = resume_after;
+        poll
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn poll_actors(
+        actors: &mut HashMap<ActorKey, Actor<RT, F, DS>>,
+        vm_pool: &mut VmPool<RT>,
+        scopes: &PeerScopes,
+        metrics: Option<&dyn MetricsSink>,
+        cx: &mut Context<'_>,
+        label: WorkerLabel,
+        remote_effects: &mut Vec<RemoteRoutingEffects>,
+        local_effects: &mut Vec<LocalRoutingEffects>,
+        config: &PlumberConfig,
+        cleanup_keys: &mut Vec<CleanupKey>,
+        avm_errors: &mut Vec<AquamarineApiError>,
+        now: u64,
+        cursor: &mut Option<ActorKey>,
+        deadline: Option<Instant>,
+    ) -> Vec<InterpretationStats> {
+        let mut interpretation_stats = vec![];
+        let mut finished = vec![];
+
+        // Only the actors after the cursor are left to poll, it's set again if the time is over
+        let resume_after = cursor.take();
+        let mut to_poll: Vec<_> = actors
+            .iter_mut()
+            .filter(|(key, _)| resume_after.as_ref().map_or(true, |after| *key > after))
+            .collect();
+        if deadline.is_some() {
+            // Keys order lets the next poll resume where this one stopped
+            to_poll.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        let mut to_poll = to_poll.into_iter().peekable();
+
+        while let Some((key, actor)) = to_poll.next() {
+            if let Some(factor) = config.execution_grace_factor {
+                let timeout = Duration::try_from_secs_f64(actor.ttl().as_secs_f64() * factor)
+                    .unwrap_or(Duration::MAX);
+                if actor.execution_elapsed(now).is_some_and(|e| e > timeout) {
+                    let timeout = humantime::format_duration(timeout);
+                    tracing::warn!(
+                        particle_id = actor.particle_id(),
+                        "Particle execution didn't finish in {timeout}, cancelled"
+                    );
+                    if let Some(vm_id) = actor.cancel_execution() {
+                        if let Some(m) = metrics {
+                            m.worker_counter(WorkerCounter::AvmRecreated, &label, 1);
+                        }
+                        vm_pool.recreate_avm(vm_id, cx);
+                    }
+                    avm_errors.push(AquamarineApiError::ExecutionTimeout {
+                        particle_id: actor.particle_id().to_string(),
+                        timeout,
+                    });
+                }
+            }
+
+            if let Poll::Ready(result) = actor.poll_completed(cx) {
+                if config.report_avm_errors {
+                    if let Some((ret_code, message)) = result.stats.avm_error.clone() {
+                        avm_errors.push(AquamarineApiError::AvmError {
+                            particle_id: result.effects.particle.particle.id.clone(),
+                            ret_code,
+                            message,
+                        });
+                    }
+                }
+                interpretation_stats.push(result.stats);
+
+                let mut remote_peers = vec![];
+                let mut local_peers = vec![];
+                for next_peer in result.effects.next_peers {
+                    let scope = if config.loopback_peer == Some(next_peer) {
+                        Ok(PeerScope::Host)
+                    } else {
+                        scopes.scope(next_peer)
+                    };
+                    match scope {
+                        Err(err) if config.strict_peer_scopes => {
+                            tracing::error!(
+                                particle_id = result.effects.particle.particle.id,
+                                "Particle is routed to a peer that isn't local: {err}"
+                            );
+                            if let Some(m) = metrics {
+                                m.worker_counter(WorkerCounter::UnknownPeerScopes, &label, 1);
+                            }
+                        }
+                        Err(_) => {
+                            remote_peers.push(next_peer);
+                        }
+                        Ok(scope) => {
+                            local_peers.push(scope);
+                        }
+                    }
+                }
+                let remote_only = !remote_peers.is_empty() && local_peers.is_empty();
+
+                if !remote_peers.is_empty() {
+                    remote_effects.push(RemoteRoutingEffects {
+                        particle: result.effects.particle.clone(),
+                        next_peers: remote_peers,
+                    });
+                }
+
+                if !local_peers.is_empty() {
+                    local_effects.push(LocalRoutingEffects {
+                        particle: result.effects.particle.clone(),
+                        next_peers: local_peers,
+                    });
+                }
+
+                let (vm_id, vm) = result.runtime;
+                match vm {
+                    VmOutcome::Returned(vm) => {
+                        let particle_id = result.effects.particle.particle.id.as_str();
+                        Self::report_vm_memory(&vm, particle_id, metrics, &label, config);
+                        vm_pool.put_vm(vm_id, vm);
+                    }
+                    VmOutcome::Lost => {
+                        // an AVM instance was lost due to panic or cancellation,
+                        // and we must ask VmPool to recreate that AVM
+                        if let Some(m) = metrics {
+                            m.worker_counter(WorkerCounter::AvmRecreated, &label, 1);
+                        }
+                        vm_pool.recreate_avm(vm_id, cx);
+                    }
+                    VmOutcome::Busy(call) => vm_pool.reclaim_vm(vm_id, call),
+                }
+
+                // Particle has left the peer and the actor has nothing else to do
+                if config.evict_remote_only_actors
+                    && remote_only
+                    && actor.mailbox_size() == 0
+                    && !actor.has_pending_calls()
+                {
+                    finished.push(key.clone());
+                }
+            }
+
+            if to_poll.peek().is_some()
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                *cursor = Some(key.clone());
+                break;
+            }
+        }
+
+        for key in finished {
+            if let Some(actor) = actors.remove(&key) {
+                tracing::debug!(
+                    particle_id = actor.particle_id(),
+                    "Particle was routed only to remote peers, actor is evicted"
+                );
+                Self::cancel_actor(actor, Some(&mut *vm_pool), cleanup_keys);
+            }
+        }
+
+        if let Some(m) = metrics {
+            for stat in &interpretation_stats {
+                // count particle interpretations
+                if stat.success {
+                    m.worker_counter(WorkerCounter::InterpretationSuccesses, &label, 1);
+                } else {
+                    m.worker_counter(WorkerCounter::InterpretationFailures, &label, 1);
+                }
+                if stat.empty_effects {
+                    m.worker_counter(WorkerCounter::EmptyEffects, &label, 1);
+                }
+
+                m.interpretation_time(&label, stat.interpretation_time);
+            }
+            let report_actors = |label: &WorkerLabel, actors: &[&Actor<RT, F, DS>]| {
+                let mailbox_size: usize = actors.iter().map(|actor| actor.mailbox_size()).sum();
+                m.worker_gauge(WorkerGauge::TotalActorsMailbox, label, mailbox_size as i64);
+                m.worker_gauge(WorkerGauge::AliveActors, label, actors.len() as i64);
+            };
+            report_actors(&label, &actors.values().collect::<Vec<_>>());
+            if *label.worker_type() == WorkerType::Host {
+                // Host peer's own particles are also reported apart from the ones it relays
+                let root: Vec<_> = actors
+                    .values()
+                    .filter(|actor| scopes.is_host(actor.init_peer_id()))
+                    .collect();
+                let root_label = WorkerLabel::new(WorkerType::Root, label.peer_id().to_string());
+                report_actors(&root_label, &root);
+            }
+        }
+
+        interpretation_stats
+    }
+
+    fn cleanup(&mut self, cx: &mut Context<'_>) {
+        // do not schedule task if another in progress
+        if let Some(Ready(())) = self.cleanup_future.as_mut().map(|f| f.poll_unpin(cx)) {
+            // we remove clean up future if it is ready
+            self.cleanup_future.take();
+        }
+        let now = now_ms();
+        if now < self.next_cleanup_at {
+            return;
+        }
+        if self.cleanup_future.is_some() {
+            // cleanup can't keep up if it's often still in progress when the next one is due,
+            // without an interval it's due on every poll
+            self.meter(|m| m.counter(ExecutorCounter::CleanupsSkipped, 1));
+            self.schedule_cleanup(now);
+        } else {
+            self.schedule_cleanup(now);
+            // Remove expired actors
+            let batch_size = self.plumber_config.cleanup_batch_size;
+            let mut cleanup_keys: Vec<CleanupKey> = Vec::with_capacity(batch_size);
+            let pending = self.pending_cleanup_keys.len().min(batch_size);
+            cleanup_keys.extend(self.pending_cleanup_keys.drain(..pending));
+            self.cleanup_host_actors(&mut cleanup_keys, now);
+            self.cleanup_worker_actors(&mut cleanup_keys, now);
+
+            if !cleanup_keys.is_empty() {
+                let data_store = self.data_store.clone();
+                let metrics = self.metrics.clone();
+                let keys = cleanup_keys.len();
+                let span = tracing::info_span!("Plumber: cleanup", keys);
+                self.cleanup_future = Some(
+                    async move {
+                        let started = Instant::now();
+                        let report = data_store.batch_cleanup_data(cleanup_keys).await;
+                        let elapsed = started.elapsed();
+                        tracing::debug!(target: "particle_reap", "Cleaned up {} particles in {elapsed:?}, {} failed", report.cleaned, report.failed.len());
+                        if let Some(m) = metrics {
+                            m.cleanup_finished(keys, elapsed)
+                        }
+                    }
+                    .instrument(span)
+                    .boxed(),
+                )
+            }
+        }
+    }
+
+    /// Stops the plumber, so no particles are accepted anymore. Waits up to `shutdown_timeout`
+    /// for the interpretations in progress to finish, and then for the cleanup in progress.
+    /// Finally removes data of the removed and expired actors and saves the particles
+    /// left in the mailboxes along with the ones the finished interpretations routed
+    /// to the local peers, so they can be brought back by `restore`.
+    pub fn shutdown(mut self) -> BoxFuture<'static, ()> {
+        async move {
+            let timeout = self.plumber_config.shutdown_timeout;
+            let mut drained = vec![];
+            let drain = futures::future::poll_fn(|cx| self.poll_drain(cx, &mut drained));
+            if tokio::time::timeout(timeout, drain).await.is_err() {
+                tracing::warn!(
+                    "Interpretations didn't finish in {} on shutdown, abandoned",
+                    humantime::format_duration(timeout)
+                );
+            }
+
+            if let Some(cleanup) = self.cleanup_future.take() {
+                cleanup.await;
+            }
+
+            let now = now_ms();
+            let mut cleanup_keys = std::mem::take(&mut self.pending_cleanup_keys);
+            Self::cleanup_actors(&mut self.host_actors, &mut cleanup_keys, usize::MAX, now);
+            for actors in self.worker_actors.values_mut() {
+                Self::cleanup_actors(actors, &mut cleanup_keys, usize::MAX, now);
+            }
+            if !cleanup_keys.is_empty() {
+                let report = self.data_store.batch_cleanup_data(cleanup_keys).await;
+                tracing::info!(
+                    "Cleaned up {} particles on shutdown, {} failed",
+                    report.cleaned,
+                    report.failed.len()
+                );
+            }
+
+            let mut queued = self.queued_particles();
+            queued.extend(drained);
+            if !queued.is_empty() {
+                match self.data_store.store_mailboxes(&queued).await {
+                    Ok(()) => tracing::info!("Saved {} queued particles on shutdown", queued.len()),
+                    Err(err) => {
+                        tracing::error!("Could not save queued particles on shutdown: {err}")
+                    }
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Re-ingests the particles saved on the last `shutdown`, skipping the expired ones.
+    /// Returns the number of re-ingested particles.
+    pub async fn restore(&mut self) -> Result<usize, DataStoreError> {
+        let queued = self.data_store.take_mailboxes().await?;
+
+        let now = now_ms();
+        let total = queued.len();
+        let mut restored = 0;
+        for queued in queued {
+            if self.deadline(&queued.particle).is_expired(now) {
+                continue;
+            }
+            let span = tracing::info_span!("Plumber: restore", particle_id = queued.particle.id);
+            let particle = ExtendedParticle::new(queued.particle, span).with_hops(queued.hops);
+            self.ingest(particle, None, queued.peer_scope, ParticleOrigin::Network);
+            restored += 1;
+        }
+        tracing::info!(
+            "Restored {restored} queued particles, {} expired",
+            total - restored
+        );
+
+        Ok(restored)
+    }
+
+    /// Particles waiting in the mailboxes of all actors
+    fn queued_particles(&self) -> Vec<QueuedParticle> {
+        let host = self
+            .host_actors
+            .values()
+            .map(|actor| (PeerScope::Host, actor));
+        let workers = self.worker_actors.iter().flat_map(|(worker_id, actors)| {
+            actors
+                .values()
+                .map(|actor| (PeerScope::WorkerId(*worker_id), actor))
+        });
+        host.chain(workers)
+            .flat_map(|(peer_scope, actor)| {
+                actor.mailbox().map(move |particle| QueuedParticle {
+                    particle: particle.particle.clone(),
+                    peer_scope,
+                    hops: particle.hops,
+                })
+            })
+            .collect()
+    }
+
+    /// Puts VMs of the finished interpretations back to the pools without starting new ones.
+    /// Particles they routed to the local peers are collected to `drained`, counting one more hop,
+    /// the ones routed to the remote peers are dropped.
+    /// Ready once nothing is executing and all the workers' actors are back from polling.
+    fn poll_drain(&mut self, cx: &mut Context<'_>, drained: &mut Vec<QueuedParticle>) -> Poll<()> {
+        let mut remote_effects = vec![];
+        let mut local_effects = vec![];
+        // All the actors are polled regardless of where the last poll stopped
+        self.actors_cursor = None;
+        let polled = self.poll_host_actors(cx, None, &mut remote_effects, &mut local_effects);
+        // Workers aren't sent away anymore, so the drain isn't held by the new polls
+        self.poll_workers_actors(
+            cx,
+            None,
+            polled,
+            false,
+            &mut remote_effects,
+            &mut local_effects,
+        );
+        if !remote_effects.is_empty() {
+            tracing::debug!(
+                "Dropped {} remote effects produced during shutdown",
+                remote_effects.len()
+            );
+        }
+        for effect in local_effects {
+            let hops = effect.particle.hops + 1;
+            drained.extend(
+                effect
+                    .next_peers
+                    .into_iter()
+                    .map(|peer_scope| QueuedParticle {
+                        particle: effect.particle.particle.clone(),
+                        peer_scope,
+                        hops,
+                    }),
+            );
+        }
+
+        let executing = self
+            .host_actors
+            .values()
+            .chain(
+                self.worker_actors
+                    .values()
+                    .flat_map(|actors| actors.values()),
+            )
+            .any(|actor| actor.is_executing());
+        if executing || !self.worker_polls.is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    /// Sets the time of the next cleanup according to `cleanup_interval` and `cleanup_jitter`
+    fn schedule_cleanup(&mut self, now_ms: u64) {
+        let Some(interval) = self.plumber_config.cleanup_interval else {
+            return;
+        };
+
+        let jitter = self.plumber_config.cleanup_jitter.max(0.0) * self.cleanup_rng.gen::<f64>();
+        let delay = interval.mul_f64(1.0 + jitter);
+        self.next_cleanup_at = now_ms + delay.as_millis() as u64;
+    }
+
+    /// Evicts actors whose executions took longer than `max_actor_execution_time` in total
+    fn evict_over_budget(&mut self) {
+        let Some(budget) = self.plumber_config.max_actor_execution_time else {
+            return;
+        };
+
+        let mut evicted = Self::evict_actors_over(
+            &mut self.host_actors,
+            Some(&mut self.host_vm_pool),
+            &mut self.pending_cleanup_keys,
+            budget,
+        )
+        .into_iter()
+        .map(|particle_id| (PeerScope::Host, particle_id))
+        .collect::<Vec<_>>();
+        for (worker_id, actors) in self.worker_actors.iter_mut() {
+            let worker_evicted = Self::evict_actors_over(
+                actors,
+                self.worker_vm_pools.get_mut(worker_id),
+                &mut self.pending_cleanup_keys,
+                budget,
+            );
+            evicted.extend(
+                worker_evicted
+                    .into_iter()
+                    .map(|particle_id| (PeerScope::WorkerId(*worker_id), particle_id)),
+            );
+        }
+
+        for (peer_scope, particle_id) in evicted {
+            tracing::warn!(target: "budget", particle_id = particle_id, "Actor exceeded execution budget {}, evicted", humantime::format_duration(budget));
+            self.push_error(
+                peer_scope,
+                AquamarineApiError::BudgetExceeded {
+                    particle_id,
+                    budget: humantime::format_duration(budget),
+                },
+            );
+        }
+    }
+
+    /// Returns particle ids of the evicted actors
+    fn evict_actors_over(
+        actors: &mut HashMap<ActorKey, Actor<RT, F, DS>>,
+        mut pool: Option<&mut VmPool<RT>>,
+        cleanup_keys: &mut Vec<CleanupKey>,
+        budget: Duration,
+    ) -> Vec<String> {
+        let keys: Vec<ActorKey> = actors
+            .iter()
+            .filter(|(_, actor)| !actor.is_executing() && actor.execution_time() > budget)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        keys.iter()
+            .filter_map(|key| actors.remove(key))
+            .map(|actor| {
+                let particle_id = actor.particle_id().to_string();
+                Self::cancel_actor(actor, pool.as_deref_mut(), cleanup_keys);
+                particle_id
+            })
+            .collect()
+    }
+
+    fn cleanup_host_actors(&mut self, cleanup_keys: &mut Vec<CleanupKey>, now_ms: u64) {
+        let batch_size = self.plumber_config.cleanup_batch_size;
+        Self::cleanup_actors(&mut self.host_actors, cleanup_keys, batch_size, now_ms)
+    }
+
+    fn cleanup_worker_actors(&mut self, cleanup_keys: &mut Vec<CleanupKey>, now_ms: u64) {
+        let batch_size = self.plumber_config.cleanup_batch_size;
+        if cleanup_keys.len() >= batch_size {
+            return;
+        }
+        self.worker_actors.retain(|worker_id, actors| {
+            Self::cleanup_actors(actors, cleanup_keys, batch_size, now_ms);
+
+            !actors.is_empty() || self.worker_vm_pools.contains_key(worker_id)
+        });
+    }
+
+    fn cleanup_actors(
+        map: &mut HashMap<ActorKey, Actor<RT, F, DS>>,
+        cleanup_keys: &mut Vec<CleanupKey>,
+        batch_size: usize,
+        now_ms: u64,
+    ) {
+        map.retain(|_, actor| {
+            if cleanup_keys.len() >= batch_size {
+                return true;
+            }
+            // if actor hasn't yet expired or is still executing, keep it
+            if !actor.is_expired(now_ms) || actor.is_executing() {
+                return true; // keep actor
+            }
+            cleanup_keys.push(actor.cleanup_key());
+            false // remove actor
+        });
+    }
+
+    /// Meters memory of the VM that has just finished an interpretation
+    /// and warns if it's close to the memory limit
+    fn report_vm_memory(
+        vm: &RT,
+        particle_id: &str,
+        metrics: Option<&dyn MetricsSink>,
+        label: &WorkerLabel,
+        config: &PlumberConfig,
+    ) {
+        let memory = vm.memory_stats();
+        if let Some(m) = metrics {
+            let rejects = memory.allocation_rejects.unwrap_or_default();
+            m.worker_gauge(WorkerGauge::AvmMemorySize, label, memory.memory_size as i64);
+            m.worker_gauge(WorkerGauge::AvmAllocationRejects, label, rejects as i64);
+        }
+
+        let Some(limit) = memory.total_memory_limit else {
+            return;
+        };
+        if memory.memory_size as f64 >= limit as f64 * config.memory_limit_warning_ratio {
+            tracing::warn!(
+                particle_id,
+                "AquaVM memory {} is close to its limit {limit}, allocation rejects: {:?}",
+                memory.memory_size,
+                memory.allocation_rejects
+            );
+        }
+    }
+
+    fn poll_next_host_messages(&mut self, cx: &mut Context<'_>) -> Vec<SingleCallStat> {
+        let now = now_ms();
+        let mut stats = vec![];
+        if let Some(last) = Self::poll_next_messages(
+            &mut self.host_actors,
+            &mut self.host_vm_pool,
+            self.vm_budget.as_ref(),
+            self.host_cursor.as_ref(),
+            now,
+            cx,
+            &mut stats,
+        ) {
+            self.host_cursor = Some(last);
+        }
+        stats
+    }
+
+    /// Returns stats of the calls made by the actors of each worker
+    fn poll_next_worker_messages(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Vec<(WorkerId, Vec<SingleCallStat>)> {
+        let mut stats = vec![];
+        let now = now_ms();
+
+        let worker_actors = &self.worker_actors;
+        self.worker_cursors
+            .retain(|worker_id, _| worker_actors.contains_key(worker_id));
+        for (worker_id, actors) in self.worker_actors.iter_mut() {
+            // Worker waits for the interpretations in progress to be reset
+            if self.pending_resets.contains_key(worker_id) {
+                continue;
+            }
+            if let Some(pool) = self.worker_vm_pools.get_mut(worker_id) {
+                let mut worker_stats = vec![];
+                let last = Self::poll_next_messages(
+                    actors,
+                    pool,
+                    self.vm_budget.as_ref(),
+                    self.worker_cursors.get(worker_id),
+                    now,
+                    cx,
+                    &mut worker_stats,
+                );
+                if let Some(last) = last {
+                    self.worker_last_executed.insert(*worker_id, now);
+                    self.worker_cursors.insert(*worker_id, last);
+                }
+                if !worker_stats.is_empty() {
+                    stats.push((*worker_id, worker_stats));
+                }
+            }
+        }
+        stats
+    }
+
+    /// Offers free VMs of the pool to the actors, starting right after the `cursor` actor,
+    /// so that actors beyond the pool capacity take turns instead of starving.
+    /// Returns the key of the last actor that started an execution
+    fn poll_next_messages(
+        actors: &mut HashMap<ActorKey, Actor<RT, F, DS>>,
+        pool: &mut VmPool<RT>,
+        vm_budget: Option<&Arc<Semaphore>>,
+        cursor: Option<&ActorKey>,
+        now: u64,
+        cx: &mut Context<'_>,
+        stats: &mut Vec<SingleCallStat>,
+    ) -> Option<ActorKey> {
+        let mut actors: Vec<_> = actors.iter_mut().collect();
+        if let Some(position) = cursor.and_then(|c| actors.iter().position(|(key, _)| *key == c)) {
+            actors.rotate_left(position + 1);
+        }
+        // Management and host particles go first, the rest keep their turns
+        actors.sort_by_key(|(_, actor)| !actor.is_priority());
+
+        let mut last = None;
+        for (key, actor) in actors {
+            let permit = match vm_budget.map(|budget| budget.clone().try_acquire_owned()) {
+                Some(Ok(permit)) => Some(permit),
+                // All VMs allowed by the global budget are busy
+                Some(Err(_)) => break,
+                None => None,
+            };
+            let Some((vm_id, vm)) = pool.get_vm() else {
+                break;
+            };
+            match actor.poll_next(vm_id, vm, now, cx) {
+                ActorPoll::Vm(vm_id, vm) => pool.put_vm(vm_id, vm),
+                ActorPoll::Executing(mut s) => {
+                    if let Some(permit) = permit {
+                        actor.hold_vm_permit(permit);
+                    }
+                    stats.append(&mut s);
+                    last = Some(key.clone());
+                }
+            }
+        }
+        last
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = &self.waker {
+            waker.wake_by_ref();
+        }
+    }
+
+    fn meter<U, FF: Fn(&dyn MetricsSink) -> U>(&self, f: FF) {
+        self.metrics.as_deref().map(f);
+    }
+}
+
+fn get_particle_token(signer: &dyn ParticleTokenSigner, signature: &[u8]) -> eyre::Result<String> {
+    let particle_token = signer.sign(signature)?;
+    Ok(bs58::encode(particle_token).into_string())
+}
+
+/// Approximate number of bytes held by the buffered routing effects
+fn effects_memory_estimate(effects: &RemoteRoutingEffects) -> usize {
+    particle_memory_estimate(&effects.particle.particle)
+        + effects.next_peers.len() * std::mem::size_of::<PeerId>()
+}
+
+/// Implements `now` by taking number of non-leap seconds from `Utc::now()`
+mod real_time {
+    #[allow(dead_code)]
+    pub fn now_ms() -> u64 {
+        (chrono::Utc::now().timestamp() * 1000) as u64
+    }
+}
+
+mod real_runtime {
+    use tokio::runtime::Handle;
+    use types::peer_scope::WorkerId;
+    use workers::Workers;
+
+    #[allow(dead_code)]
+    pub fn get_runtime_handle(workers: &Workers, worker_id: WorkerId) -> Option<Handle> {
+        workers.get_runtime_handle(worker_id)
+    }
+}
+
+struct ActorParams<'a> {
+    key: ActorKey,
+    particle: &'a ExtendedParticle,
+    deadline: Deadline,
+    peer_scope: PeerScope,
+    current_peer_id: PeerId,
+    deal_id: Option<DealId>,
+    spawner: Spawner,
+    priority: bool,
+}
+
+struct PlumberParams<'p, F, DS>
+where
+    F: Clone,
+{
+    builtins: &'p F,
+    key_storage: &'p KeyStorage,
+    data_store: Arc<DS>,
+    token_signer: &'p dyn ParticleTokenSigner,
+    actor_load: Arc<ActorLoad>,
 }
 
-struct ActorParams<'a> {
-    key: ActorKey,
-    particle: &'a ExtendedParticle,
-    peer_scope: PeerScope,
-    current_peer_id: PeerId,
-    deal_id: Option<DealId>,
-    spawner: Spawner,
-}
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::convert::Infallible;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Waker;
+    use std::time::Duration;
+    use std::{sync::Arc, task::Context};
+
+    use avm_server::{AVMMemoryStats, CallResults, ParticleParameters};
+    use fluence_keypair::KeyPair;
+    use fluence_libp2p::{PeerId, RandomPeerId};
+    use futures::task::noop_waker_ref;
+    use futures::FutureExt;
+    use workers::{
+        DummyCoreManager, Event, KeyStorage, PeerScopes, Receiver, WorkerId, WorkerParams, Workers,
+        CUID,
+    };
+
+    use particle_args::Args;
+    use particle_execution::{FunctionOutcome, ParticleFunction, ParticleParams, ServiceFunction};
+    use particle_protocol::{ExtendedParticle, Particle};
+
+    use crate::deadline::Deadline;
+    use crate::particle_effects::LocalRoutingEffects;
+    use crate::plumber::mock_runtime::{hide_runtime_handle, reveal_runtime_handle};
+    use crate::plumber::mock_time::set_mock_time;
+    use crate::plumber::{now_ms, real_time, ActorsCursor, DeadLetter, PollStage};
+    use crate::vm_pool::VmPool;
+    use crate::AquamarineApiError::{
+        AvmError, BudgetExceeded, ExecutionTimeout, MailboxFull, MaxHopsExceeded, NoCapacity,
+        NoSuchWorker, NoWorkerPool, Overloaded, ParticleExpired, PeerNotAllowed,
+        SignatureVerificationFailed, WorkerRuntimeNotFound,
+    };
+    use crate::{
+        ActorKeying, AquaRuntime, CleanupKey, CleanupReport, DataStore, DataStoreError, DealUsage,
+        IngestOutcome, NoCapacityPolicy, ParticleDataStore, ParticleEffects, ParticleTokenSigner,
+        Plumber, PlumberConfig, QueuedParticle, RejectReason, RemoteRoutingEffects, ResetMode,
+    };
+    use async_trait::async_trait;
+    use avm_server::avm_runner::RawAVMOutcome;
+    use marine_wasmtime_backend::{WasmtimeConfig, WasmtimeWasmBackend};
+    use parking_lot::Mutex;
+    use particle_services::{PeerScope, WasmBackendConfig};
+    use peer_metrics::{
+        ExecutorCounter, FunctionKind, MetricsSink, ParticleExecutorMetrics, ParticleOrigin,
+        ParticleOriginLabel, VmPoolMetrics, WorkerCounter, WorkerGauge, WorkerLabel, WorkerType,
+    };
+    use prometheus_client::encoding::text::encode;
+    use prometheus_client::registry::Registry;
+    use tracing::Span;
+    use types::DealId;
+
+    /// Tracks how many add_service/remove_service calls run at once
+    #[derive(Default)]
+    struct MockF {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        finished: AtomicUsize,
+    }
+
+    impl MockF {
+        async fn track(&self) {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            self.finished.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl ParticleFunction for MockF {
+        async fn call(&self, _args: Args, _particle: ParticleParams) -> FunctionOutcome {
+            panic!("no builtins in plumber tests!")
+        }
+
+        async fn extend(
+            &self,
+            _service: String,
+            _functions: HashMap<String, ServiceFunction>,
+            _fallback: Option<ServiceFunction>,
+        ) {
+            self.track().await
+        }
+
+        async fn remove(&self, _service: &str) {
+            self.track().await
+        }
+    }
+
+    /// Keeps nothing, only records what is cleaned up
+    #[derive(Default)]
+    struct MockDataStore {
+        cleaned: Mutex<Vec<CleanupKey>>,
+        /// Sizes of the cleaned batches
+        batches: Mutex<Vec<usize>>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        mailboxes: Mutex<Vec<QueuedParticle>>,
+    }
+
+    #[async_trait]
+    impl DataStore for MockDataStore {
+        async fn initialize(&self) -> Result<(), DataStoreError> {
+            Ok(())
+        }
+
+        async fn store_data(
+            &self,
+            _data: &[u8],
+            _particle_id: &str,
+            _current_peer_id: &str,
+            _signature: &[u8],
+        ) -> Result<(), DataStoreError> {
+            Ok(())
+        }
+
+        async fn read_data(
+            &self,
+            _particle_id: &str,
+            _current_peer_id: &str,
+            _signature: &[u8],
+        ) -> Result<Vec<u8>, DataStoreError> {
+            Ok(vec![])
+        }
+
+        async fn batch_cleanup_data(&self, cleanup_keys: Vec<CleanupKey>) -> CleanupReport {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let cleaned = cleanup_keys.len();
+            self.batches.lock().push(cleaned);
+            self.cleaned.lock().extend(cleanup_keys);
+            CleanupReport {
+                cleaned,
+                failed: vec![],
+            }
+        }
+
+        async fn store_mailboxes(
+            &self,
+            particles: &[QueuedParticle],
+        ) -> Result<(), DataStoreError> {
+            *self.mailboxes.lock() = particles.to_vec();
+            Ok(())
+        }
+
+        async fn take_mailboxes(&self) -> Result<Vec<QueuedParticle>, DataStoreError> {
+            Ok(std::mem::take(&mut *self.mailboxes.lock()))
+        }
+
+        fn detect_anomaly(
+            &self,
+            _execution_time: Duration,
+            _memory_delta: usize,
+            _outcome: &RawAVMOutcome,
+        ) -> bool {
+            false
+        }
+
+        async fn save_anomaly_data(
+            &self,
+            _air_script: &str,
+            _current_data: &[u8],
+            _call_results: &CallResults,
+            _particle_parameters: &ParticleParameters<'_>,
+            _particle_signature: &[u8],
+            _outcome: &RawAVMOutcome,
+            _execution_time: Duration,
+            _memory_delta: usize,
+        ) -> Result<(), DataStoreError> {
+            Ok(())
+        }
+    }
+
+    /// Routes particle to the peer whose id is the particle script, if any.
+    /// Particle data `sleep <ms>` makes the interpretation take that long
+    #[derive(Default)]
+    struct VMMock {
+        memory_size: usize,
+        allocation_rejects: Option<usize>,
+    }
+
+    #[async_trait]
+    impl AquaRuntime for VMMock {
+        type Config = ();
+        type Error = Infallible;
+
+        fn create_runtime(
+            _config: Self::Config,
+            _backend: WasmtimeWasmBackend,
+            _waker: Waker,
+        ) -> Result<Self, Self::Error> {
+            Ok(VMMock::default())
+        }
+
+        fn into_effects(
+            outcome: Result<RawAVMOutcome, Self::Error>,
+            _particle_id: String,
+        ) -> ParticleEffects {
+            let next_peers = outcome
+                .map(|o| {
+                    o.next_peer_pks
+                        .iter()
+                        .filter_map(|pk| pk.parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default();
+            ParticleEffects {
+                new_data: vec![],
+                next_peers,
+                call_requests: Default::default(),
+            }
+        }
+
+        async fn call(
+            &mut self,
+            air: impl Into<String> + Send,
+            _prev_data: impl Into<Vec<u8>> + Send,
+            current_data: impl Into<Vec<u8>> + Send,
+            _particle_params: ParticleParameters<'_>,
+            _call_results: CallResults,
+            _key_pair: &KeyPair,
+        ) -> Result<RawAVMOutcome, Self::Error> {
+            let soft_limits_triggering = <_>::default();
+            let current_data = current_data.into();
+            let delay = std::str::from_utf8(&current_data)
+                .ok()
+                .and_then(|data| data.strip_prefix("sleep "))
+                .and_then(|ms| ms.parse().ok());
+            if let Some(delay) = delay {
+                std::thread::sleep(Duration::from_millis(delay));
+            }
+            // "fail <ret_code> <error_message>" makes the script fail
+            let (ret_code, error_message) = std::str::from_utf8(&current_data)
+                .ok()
+                .and_then(|data| data.strip_prefix("fail "))
+                .and_then(|failure| failure.split_once(' '))
+                .and_then(|(code, message)| Some((code.parse().ok()?, message.to_string())))
+                .unwrap_or_default();
+            // "memory <size> <rejects>" leaves the VM with that memory size and allocation rejects
+            let memory = std::str::from_utf8(&current_data)
+                .ok()
+                .and_then(|data| data.strip_prefix("memory "))
+                .and_then(|memory| memory.split_once(' '))
+                .and_then(|(size, rejects)| Some((size.parse().ok()?, rejects.parse().ok()?)));
+            if let Some((memory_size, allocation_rejects)) = memory {
+                self.memory_size = memory_size;
+                self.allocation_rejects = Some(allocation_rejects);
+            }
+            let air = air.into();
+            let next_peer_pks = air
+                .parse::<PeerId>()
+                .map(|peer_id| vec![peer_id.to_base58()])
+                .unwrap_or_default();
+            Ok(RawAVMOutcome {
+                ret_code,
+                error_message,
+                data: vec![],
+                call_requests: Default::default(),
+                next_peer_pks,
+                soft_limits_triggering,
+            })
+        }
+
+        fn memory_stats(&self) -> AVMMemoryStats {
+            AVMMemoryStats {
+                memory_size: self.memory_size,
+                total_memory_limit: None,
+                allocation_rejects: self.allocation_rejects.map(|rejects| rejects as _),
+            }
+        }
+    }
+
+    async fn plumber() -> Plumber<VMMock, Arc<MockF>> {
+        let (plumber, _env) = plumber_with_env(PlumberConfig::default()).await;
+        plumber
+    }
+
+    /// Everything plumber depends on that tests need to access or keep alive
+    struct TestEnv {
+        workers: Arc<Workers>,
+        management_key_pair: KeyPair,
+        // worker registry fails to create workers when receiver is dropped
+        _receiver: Receiver<Event>,
+        _tmp_dir: tempfile::TempDir,
+    }
+
+    impl TestEnv {
+        async fn create_worker(&self, creator: &KeyPair) -> WorkerId {
+            let deal_id = format!("deal_{}", creator.get_peer_id());
+            self.workers
+                .create_worker(WorkerParams::new(
+                    deal_id.into(),
+                    creator.get_peer_id(),
+                    vec![CUID::new([1; 32])],
+                ))
+                .await
+                .expect("Could not create worker")
+        }
+    }
+
+    impl Drop for TestEnv {
+        fn drop(&mut self) {
+            // tokio doesn't allow to drop runtimes in async context
+            self.workers.shutdown();
+        }
+    }
+
+    async fn plumber_with_env(
+        plumber_config: PlumberConfig,
+    ) -> (Plumber<VMMock, Arc<MockF>>, TestEnv) {
+        plumber_with_store(plumber_config, |path| {
+            ParticleDataStore::new(
+                path.join("particles"),
+                path.join("vault"),
+                path.join("anomaly"),
+            )
+        })
+        .await
+    }
+
+    async fn plumber_with_store<DS: DataStore>(
+        plumber_config: PlumberConfig,
+        data_store: impl FnOnce(&Path) -> DS,
+    ) -> (Plumber<VMMock, Arc<MockF>, DS>, TestEnv) {
+        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
+        let avm_wasm_backend =
+            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
+        // Pool is of size 1 so it's easier to control tests
+        let vm_pool = VmPool::new(
+            1,
+            (),
+            None,
+            WorkerLabel::new(WorkerType::Host, "host".to_string()),
+            None,
+            avm_wasm_backend.clone(),
+            <_>::default(),
+        );
+        let builtin_mock = Arc::new(MockF::default());
+
+        let tmp_dir = tempfile::tempdir().expect("Could not create temp dir");
+        let tmp_path = tmp_dir.path();
+
+        let root_key_pair: KeyPair = KeyPair::generate_ed25519();
+        let management_key_pair: KeyPair = KeyPair::generate_ed25519();
+        let key_pair_path: PathBuf = tmp_path.join("keypair");
+        let workers_path: PathBuf = tmp_path.join("workers");
+        let key_storage = KeyStorage::from_path(key_pair_path.clone(), root_key_pair.clone())
+            .await
+            .expect("Could not load key storage");
+
+        let key_storage = Arc::new(key_storage);
+
+        let core_manager = Arc::new(DummyCoreManager::default().into());
+
+        let scope = PeerScopes::new(
+            root_key_pair.get_peer_id(),
+            management_key_pair.get_peer_id(),
+            RandomPeerId::random(),
+            key_storage.clone(),
+        );
+
+        let (workers, receiver) =
+            Workers::from_path(workers_path.clone(), key_storage.clone(), core_manager, 128)
+                .await
+                .expect("Could not load worker registry");
+
+        let workers = Arc::new(workers);
+
+        let data_store = data_store(tmp_path);
+        data_store
+            .initialize()
+            .await
+            .expect("Could not initialize datastore");
+        let data_store = Arc::new(data_store);
+
+        let plumber = Plumber::new(
+            (),
+            plumber_config,
+            vm_pool,
+            data_store,
+            builtin_mock,
+            None,
+            workers.clone(),
+            key_storage.clone(),
+            scope.clone(),
+            avm_wasm_backend,
+        );
+
+        let env = TestEnv {
+            workers,
+            management_key_pair,
+            _receiver: receiver,
+            _tmp_dir: tmp_dir,
+        };
+
+        (plumber, env)
+    }
+
+    fn particle(ts: u64, ttl: u32) -> Particle {
+        let mut particle = Particle::default();
+        particle.timestamp = ts;
+        particle.ttl = ttl;
+
+        particle
+    }
+
+    fn signed_particle(key_pair: &KeyPair, ts: u64, ttl: u32) -> Particle {
+        let mut particle = particle(ts, ttl);
+        particle.init_peer_id = key_pair.get_peer_id();
+        particle.sign(key_pair).expect("Could not sign particle");
+
+        particle
+    }
+
+    fn context() -> Context<'static> {
+        Context::from_waker(noop_waker_ref())
+    }
+
+    /// Checks that expired actor will be removed
+    #[ignore]
+    #[tokio::test]
+    async fn remove_expired() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+
+        let particle = particle(now_ms(), 1);
+        let deadline = Deadline::from(&particle);
+        assert!(!deadline.is_expired(now_ms()));
+
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        assert_eq!(plumber.host_actors.len(), 1);
+        let mut cx = context();
+        assert!(plumber.poll(&mut cx).is_pending());
+        assert_eq!(plumber.host_actors.len(), 1);
+
+        assert_eq!(plumber.host_vm_pool.free_vms(), 0);
+        // pool is single VM, wait until VM is free
+        loop {
+            if plumber.host_vm_pool.free_vms() == 1 {
+                break;
+            };
+            // 'is_pending' is used to suppress "must use" warning
+            plumber.poll(&mut cx).is_pending();
+        }
+
+        set_mock_time(now_ms() + 2);
+        assert!(plumber.poll(&mut cx).is_pending());
+        assert_eq!(plumber.host_actors.len(), 0);
+    }
+
+    /// Checks that clock skew tolerance admits slightly expired particles and rejects the ones from the future
+    #[tokio::test]
+    async fn clock_skew_tolerance() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            clock_skew_tolerance_ms: Some(1000),
+            ..<_>::default()
+        })
+        .await;
+        let key_pair = KeyPair::generate_ed25519();
+        let mut ingest = |ts, ttl| {
+            let particle = signed_particle(&key_pair, ts, ttl);
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            )
+        };
+
+        // expired 500ms ago, within tolerance
+        assert_eq!(ingest(now_ms() - 1500, 1000), IngestOutcome::Accepted);
+        // expired 5s ago
+        assert_eq!(
+            ingest(now_ms() - 6000, 1000),
+            IngestOutcome::Rejected(RejectReason::Expired)
+        );
+        // created 500ms ahead of us, within tolerance
+        assert_eq!(ingest(now_ms() + 500, 10000), IngestOutcome::Accepted);
+        // created 5s ahead of us
+        assert_eq!(
+            ingest(now_ms() + 5000, 10000),
+            IngestOutcome::Rejected(RejectReason::FromFuture)
+        );
+    }
+
+    /// Checks that expired particle won't create an actor
+    #[tokio::test]
+    async fn ignore_expired() {
+        set_mock_time(real_time::now_ms());
+        // set_mock_time(1000);
+
+        let mut plumber = plumber().await;
+        let particle = particle(now_ms() - 100, 99);
+        let deadline = Deadline::from(&particle);
+        assert!(deadline.is_expired(now_ms()));
+
+        plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        assert_eq!(plumber.host_actors.len(), 0);
+
+        // Check actor doesn't appear after poll somehow
+        set_mock_time(now_ms() + 1000);
+        let poll = plumber.poll(&mut context());
+        assert!(poll.is_ready());
+        match poll {
+            std::task::Poll::Ready(Err(ParticleExpired { particle_id })) => {
+                assert_eq!(particle_id, particle.id)
+            }
+            unexpected => panic!(
+                "Expected Poll::Ready(Err(AquamarineApiError::ParticleExpired)), got {:?}",
+                unexpected
+            ),
+        }
+        assert_eq!(plumber.host_actors.len(), 0);
+    }
+
+    /// Checks that worker errors go only to the subscriber of that worker
+    #[tokio::test]
+    async fn subscribe_worker_errors() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        let other_worker_id = WorkerId::from(RandomPeerId::random());
+
+        let mut errors = plumber.subscribe_worker_errors(worker_id);
+        let mut other_errors = plumber.subscribe_worker_errors(other_worker_id);
+
+        let expired = particle(now_ms() - 100, 99);
+        plumber.ingest(
+            ExtendedParticle::new(expired.clone(), Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+
+        match errors.try_recv() {
+            Ok(ParticleExpired { particle_id }) => assert_eq!(particle_id, expired.id),
+            unexpected => panic!("Expected ParticleExpired, got {:?}", unexpected),
+        }
+        assert!(other_errors.try_recv().is_err());
+        assert!(plumber.poll(&mut context()).is_pending());
+
+        // host errors are still returned from poll
+        let expired = particle(now_ms() - 100, 98);
+        plumber.ingest(
+            ExtendedParticle::new(expired, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        assert!(matches!(
+            plumber.poll(&mut context()),
+            std::task::Poll::Ready(Err(ParticleExpired { .. }))
+        ));
+        assert!(errors.try_recv().is_err());
+        assert!(other_errors.try_recv().is_err());
+    }
+
+    /// Checks that the signature of a re-sent particle isn't verified again
+    #[tokio::test]
+    async fn skip_verified_signature() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let key_pair = KeyPair::generate_ed25519();
+
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        for _ in 0..2 {
+            plumber.ingest(
+                ExtendedParticle::new(particle.clone(), Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+
+        assert_eq!(metrics.signature_verifications.get(), 1);
+        assert_eq!(plumber.host_actors.len(), 1);
+    }
+
+    /// Checks that cleanup cycles are metered as skipped while the previous cleanup is in progress
+    #[tokio::test]
+    async fn meter_skipped_cleanups() {
+        let now = real_time::now_ms();
+        set_mock_time(now);
+
+        let mut plumber = plumber().await;
+        plumber.plumber_config.cleanup_interval = Some(Duration::from_secs(1));
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert_eq!(metrics.cleanups_skipped.get(), 0);
+
+        // emulate a data store that takes forever to clean up
+        plumber.cleanup_future = Some(futures::future::pending().boxed());
+        set_mock_time(now + 1000);
+        for _ in 0..3 {
+            assert!(plumber.poll(&mut context()).is_pending());
+        }
+        assert_eq!(metrics.cleanups_skipped.get(), 1);
+
+        // next interval elapses while the same cleanup is still in progress
+        set_mock_time(now + 2000);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert_eq!(metrics.cleanups_skipped.get(), 2);
+    }
+
+    /// Checks that without a cleanup interval each poll during the cleanup in progress
+    /// is metered as a skipped cleanup
+    #[tokio::test]
+    async fn meter_skipped_cleanups_without_interval() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        assert!(plumber.plumber_config.cleanup_interval.is_none());
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert_eq!(metrics.cleanups_skipped.get(), 0);
+
+        // emulate a data store that takes forever to clean up
+        plumber.cleanup_future = Some(futures::future::pending().boxed());
+        for _ in 0..3 {
+            assert!(plumber.poll(&mut context()).is_pending());
+        }
+        assert_eq!(metrics.cleanups_skipped.get(), 3);
+    }
+
+    /// Checks that a new cleanup batch size is applied on the next cleanup
+    #[tokio::test]
+    async fn set_cleanup_batch_size() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        let key_pair = KeyPair::generate_ed25519();
+        let now = now_ms();
+        for ts in [now, now + 1, now + 2] {
+            plumber.ingest(
+                ExtendedParticle::new(signed_particle(&key_pair, ts, 10000), Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+        assert_eq!(plumber.host_actors.len(), 3);
+
+        set_mock_time(now + 20000);
+        plumber.set_cleanup_batch_size(1);
+        assert_eq!(plumber.cleanup_batch_size(), 1);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert_eq!(plumber.host_actors.len(), 2);
+
+        // pretend the previous cleanup is finished
+        plumber.cleanup_future = None;
+        plumber.set_cleanup_batch_size(2);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert_eq!(plumber.host_actors.len(), 0);
+    }
+
+    /// Checks that offloaded verification still rejects particles with invalid signatures
+    #[tokio::test]
+    async fn offload_signature_verification() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            offload_signature_verification: true,
+            ..<_>::default()
+        })
+        .await;
+        let key_pair = KeyPair::generate_ed25519();
+
+        let valid = signed_particle(&key_pair, now_ms(), 10000);
+        let mut forged = signed_particle(&key_pair, now_ms() + 1, 10000);
+        forged.script = "(null)".to_string();
+        for particle in [valid, forged.clone()] {
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+        // nothing is routed until verification is done
+        assert_eq!(plumber.host_actors.len(), 0);
+
+        let mut rejected = None;
+        for _ in 0..100 {
+            if let std::task::Poll::Ready(Err(err)) = plumber.poll(&mut context()) {
+                rejected = Some(err);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        match rejected {
+            Some(SignatureVerificationFailed { particle_id, .. }) => {
+                assert_eq!(particle_id, forged.id)
+            }
+            unexpected => panic!(
+                "Expected AquamarineApiError::SignatureVerificationFailed, got {:?}",
+                unexpected
+            ),
+        }
+        assert_eq!(plumber.host_actors.len(), 1);
+    }
+
+    /// Checks that both host and worker actors are inspected
+    #[tokio::test]
+    async fn inspect_actors() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+
+        let signed = |id: &str, ttl| {
+            let mut particle = particle(now_ms(), ttl);
+            particle.id = id.to_string();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(&key_pair).expect("Could not sign particle");
+            particle
+        };
+        let host_particle = signed("host", 10000);
+        for _ in 0..2 {
+            plumber.ingest(
+                ExtendedParticle::new(host_particle.clone(), Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+        let worker_particle = signed("worker", 20000);
+        plumber.ingest(
+            ExtendedParticle::new(worker_particle.clone(), Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+
+        let mut inspections = plumber.inspect();
+        inspections.sort_by_key(|inspection| inspection.mailbox_size);
+        let summary: Vec<_> = inspections
+            .iter()
+            .map(|i| {
+                (
+                    i.peer_scope,
+                    i.particle_id.clone(),
+                    i.mailbox_size,
+                    i.is_executing,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (PeerScope::WorkerId(worker_id), worker_particle.id, 1, false),
+                (PeerScope::Host, host_particle.id, 2, false),
+            ]
+        );
+        assert!(inspections[0].expires_in_ms > 10000);
+        assert!(inspections[1].expires_in_ms <= 10000);
+    }
+
+    /// Checks that poll yields once its time budget is spent and resumes on the next call
+    #[tokio::test]
+    async fn poll_time_budget() {
+        set_mock_time(real_time::now_ms());
+
+        struct CountingWaker(AtomicUsize);
+        impl futures::task::ArcWake for CountingWaker {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let mut plumber = plumber().await;
+        // wait until the VM is created
+        for _ in 0..100 {
+            if plumber.host_vm_pool.free_vms() == 1 {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(plumber.host_vm_pool.free_vms(), 1);
+
+        plumber.plumber_config.poll_time_budget = Some(Duration::ZERO);
+        for _ in 0..10 {
+            let key_pair = KeyPair::generate_ed25519();
+            ingest_host(&mut plumber, &key_pair);
+        }
+        let executing = |plumber: &Plumber<VMMock, Arc<MockF>>| {
+            plumber
+                .host_actors
+                .values()
+                .filter(|a| a.is_executing())
+                .count()
+        };
+
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = futures::task::waker(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        // the time is over after the first actor, the rest are left for the next poll
+        assert!(plumber.poll(&mut cx).is_pending());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(plumber.poll_stage, PollStage::Actors);
+        let mut keys: Vec<_> = plumber.host_actors.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            plumber.actors_cursor,
+            Some(ActorsCursor::Host(keys[0].clone()))
+        );
+
+        // each poll resumes after the actor the previous one stopped at
+        for polls in 2..10 {
+            assert!(plumber.poll(&mut cx).is_pending());
+            assert_eq!(counter.0.load(Ordering::SeqCst), polls);
+            assert_eq!(plumber.poll_stage, PollStage::Actors);
+            assert_eq!(
+                plumber.actors_cursor,
+                Some(ActorsCursor::Host(keys[polls - 1].clone()))
+            );
+        }
+
+        // the last actor is polled, cleanup and execution are left for the next polls
+        assert!(plumber.poll(&mut cx).is_pending());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 10);
+        assert_eq!(plumber.poll_stage, PollStage::Cleanup);
+        assert_eq!(plumber.actors_cursor, None);
+        assert_eq!(executing(&plumber), 0);
+
+        assert!(plumber.poll(&mut cx).is_pending());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 11);
+        assert_eq!(plumber.poll_stage, PollStage::NextMessages);
+        assert_eq!(executing(&plumber), 0);
+
+        assert!(plumber.poll(&mut cx).is_pending());
+        assert_eq!(plumber.poll_stage, PollStage::Actors);
+        assert_eq!(executing(&plumber), 1);
+    }
+
+    /// Checks that actors take turns when there are more of them than VMs
+    #[tokio::test]
+    async fn actors_take_turns() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let host_label = WorkerLabel::new(
+            WorkerType::Host,
+            plumber.scopes.get_host_peer_id().to_string(),
+        );
+
+        // 3 actors with a backlog of 5 particles each compete for a single VM
+        for _ in 0..3 {
+            let key_pair = KeyPair::generate_ed25519();
+            let particle = signed_particle(&key_pair, now_ms(), 10000);
+            for _ in 0..5 {
+                plumber.ingest(
+                    ExtendedParticle::new(particle.clone(), Span::none()),
+                    None,
+                    PeerScope::Host,
+                    ParticleOrigin::Network,
+                );
+            }
+        }
+        assert_eq!(plumber.host_actors.len(), 3);
+
+        let interpreted = || {
+            metrics
+                .interpretation_successes
+                .get_or_create(&host_label)
+                .get()
+        };
+        for _ in 0..100 {
+            if interpreted() >= 3 {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(interpreted() >= 3);
+
+        // every actor has executed something before any actor got a third turn
+        for actor in plumber.host_actors.values() {
+            assert!(actor.mailbox_size() < 5);
+            assert!(actor.mailbox_size() > 2);
+        }
+    }
+
+    /// Checks that management particles jump ahead of the backlog when VMs are scarce
+    #[tokio::test]
+    async fn management_particles_first() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+
+        // the only host VM is wanted by 3 ordinary actors before the management one
+        for _ in 0..3 {
+            let key_pair = KeyPair::generate_ed25519();
+            let particle = signed_particle(&key_pair, now_ms(), 10000);
+            for _ in 0..3 {
+                plumber.ingest(
+                    ExtendedParticle::new(particle.clone(), Span::none()),
+                    None,
+                    PeerScope::Host,
+                    ParticleOrigin::Network,
+                );
+            }
+        }
+        let management = signed_particle(&env.management_key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(management, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        assert_eq!(plumber.host_actors.len(), 4);
+
+        let executing = |plumber: &Plumber<VMMock, Arc<MockF>>| {
+            plumber
+                .host_actors
+                .values()
+                .filter(|actor| actor.is_executing())
+                .map(|actor| actor.is_priority())
+                .collect::<Vec<_>>()
+        };
+        for _ in 0..100 {
+            if !executing(&plumber).is_empty() {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(executing(&plumber), vec![true]);
+    }
+
+    /// Checks that requeued effect is re-ingested for the peers that have become local
+    #[tokio::test]
+    async fn requeue_effect() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let key_pair = KeyPair::generate_ed25519();
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+
+        // worker is created after the effect was routed to the network
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+        let remote_peer = RandomPeerId::random();
+        plumber.requeue_effect(RemoteRoutingEffects {
+            particle: ExtendedParticle::new(particle.clone(), Span::none()),
+            next_peers: vec![worker_id.into(), remote_peer],
+        });
+
+        let reingested = metrics
+            .ingested_particles
+            .get_or_create(&ParticleOriginLabel::new(ParticleOrigin::LocalEffect))
+            .get();
+        assert_eq!(reingested, 1);
+        let worker_actors = plumber.worker_actors.get(&worker_id).map(HashMap::len);
+        assert_eq!(worker_actors, Some(1));
+
+        match plumber.poll(&mut context()) {
+            std::task::Poll::Ready(Ok(effect)) => {
+                assert_eq!(effect.particle.particle.id, particle.id);
+                assert_eq!(effect.next_peers, vec![remote_peer]);
+            }
+            unexpected => panic!("Expected remote effect, got {:?}", unexpected),
+        }
+    }
+
+    /// Checks that metrics can be emitted to a custom sink instead of the prometheus registry
+    #[tokio::test]
+    async fn custom_metrics_sink() {
+        set_mock_time(real_time::now_ms());
+
+        #[derive(Default)]
+        struct RecordingSink {
+            ops: Mutex<Vec<(&'static str, &'static str, Option<WorkerLabel>)>>,
+        }
+        impl RecordingSink {
+            fn record(&self, op: &'static str, name: &'static str, worker: Option<&WorkerLabel>) {
+                self.ops.lock().push((op, name, worker.cloned()));
+            }
+        }
+        impl MetricsSink for RecordingSink {
+            fn counter(&self, counter: ExecutorCounter, _value: u64) {
+                self.record("counter", counter.as_str(), None)
+            }
+            fn worker_counter(&self, counter: WorkerCounter, worker: &WorkerLabel, _value: u64) {
+                self.record("counter", counter.as_str(), Some(worker))
+            }
+            fn worker_gauge(&self, gauge: WorkerGauge, worker: &WorkerLabel, _value: i64) {
+                self.record("gauge", gauge.as_str(), Some(worker))
+            }
+            fn interpretation_time(&self, worker: &WorkerLabel, _time: Duration) {
+                self.record("histogram", "interpretation_time_sec", Some(worker))
+            }
+            fn ingested_particle(&self, origin: ParticleOrigin) {
+                self.record("counter", origin.as_str(), None)
+            }
+            fn local_effect_fanout(&self, _peers: usize) {
+                self.record("histogram", "local_effect_fanout", None)
+            }
+            fn cleanup_finished(&self, _keys: usize, _time: Duration) {
+                self.record("counter", "cleanups", None)
+            }
+            fn service_call(
+                &self,
+                worker: &WorkerLabel,
+                _success: bool,
+                kind: FunctionKind,
+                _run_time: Option<Duration>,
+            ) {
+                self.record("counter", kind.as_str(), Some(worker))
+            }
+        }
+
+        let mut plumber = plumber().await;
+        let sink = Arc::new(RecordingSink::default());
+        plumber.metrics = Some(sink.clone());
+        let key_pair = KeyPair::generate_ed25519();
+        ingest_host(&mut plumber, &key_pair);
+
+        let recorded = |op: &str, name: &str| {
+            sink.ops
+                .lock()
+                .iter()
+                .filter(|(o, n, _)| *o == op && *n == name)
+                .map(|(_, _, labels)| labels.clone())
+                .collect::<Vec<_>>()
+        };
+        for _ in 0..100 {
+            if !recorded("counter", "interpretation_successes").is_empty() {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let host_label = Some(WorkerLabel::new(
+            WorkerType::Host,
+            plumber.scopes.get_host_peer_id().to_string(),
+        ));
+        assert_eq!(recorded("counter", "Network"), vec![None]);
+        assert_eq!(recorded("counter", "signature_verifications").len(), 1);
+        assert_eq!(
+            recorded("counter", "interpretation_successes"),
+            vec![host_label.clone()]
+        );
+        assert_eq!(
+            recorded("histogram", "interpretation_time_sec"),
+            vec![host_label.clone()]
+        );
+        assert!(recorded("gauge", "alive_actors").contains(&host_label));
+        assert!(recorded("gauge", "total_actors_mailbox").contains(&host_label));
+    }
+
+    /// Checks that host peer's own actors are also counted under a separate label
+    #[tokio::test]
+    async fn root_actors_label() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+
+        let root_key_pair = plumber.key_storage.root_key_pair.clone();
+        let root = signed_particle(&root_key_pair, now_ms(), 10000);
+        for particle in [&root, &root] {
+            plumber.ingest(
+                ExtendedParticle::new(particle.clone(), Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+        for _ in 0..3 {
+            let particle = signed_particle(&KeyPair::generate_ed25519(), now_ms(), 10000);
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+
+        // actors are reported before any of them is given a VM
+        assert!(plumber.poll(&mut context()).is_pending());
+
+        let host_peer_id = plumber.scopes.get_host_peer_id().to_string();
+        let worker_peer_id: PeerId = worker_id.into();
+        let labels = [
+            WorkerLabel::new(WorkerType::Root, host_peer_id.clone()),
+            WorkerLabel::new(WorkerType::Host, host_peer_id),
+            WorkerLabel::new(WorkerType::Worker, worker_peer_id.to_string()),
+        ];
+        let gauges: Vec<_> = labels
+            .iter()
+            .map(|label| {
+                (
+                    metrics.alive_actors.get_or_create(label).get(),
+                    metrics.total_actors_mailbox.get_or_create(label).get(),
+                )
+            })
+            .collect();
+        // host series still counts all host actors, root ones included
+        assert_eq!(gauges, vec![(1, 2), (4, 5), (1, 1)]);
+    }
+
+    /// Checks that memory stats of the VM are metered after the interpretation
+    #[tokio::test]
+    async fn meter_vm_memory() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let host_label = WorkerLabel::new(
+            WorkerType::Host,
+            plumber.scopes.get_host_peer_id().to_string(),
+        );
+
+        let particle = Particle {
+            data: b"memory 4096 3".to_vec(),
+            ..signed_particle(&KeyPair::generate_ed25519(), now_ms(), 10000)
+        };
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        let rejects = || {
+            metrics
+                .avm_allocation_rejects
+                .get_or_create(&host_label)
+                .get()
+        };
+        for _ in 0..100 {
+            if rejects() > 0 {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(rejects(), 3);
+        assert_eq!(
+            metrics.avm_memory_size.get_or_create(&host_label).get(),
+            4096
+        );
+    }
+
+    /// Checks that host and worker pools together never execute more VMs than the global budget
+    #[tokio::test]
+    async fn global_vm_budget() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig {
+            max_executing_vms: Some(2),
+            ..<_>::default()
+        })
+        .await;
+        let key_pair = KeyPair::generate_ed25519();
+        let mut scopes = vec![PeerScope::Host];
+        for _ in 0..2 {
+            let worker_id = env.create_worker(&key_pair).await;
+            plumber.create_worker_pool(worker_id, 1);
+            scopes.push(PeerScope::WorkerId(worker_id));
+        }
+
+        for peer_scope in scopes {
+            let mut particle = particle(now_ms(), 10000);
+            particle.data = b"sleep 100".to_vec();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(&key_pair).expect("Could not sign particle");
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                peer_scope,
+                ParticleOrigin::Network,
+            );
+        }
+
+        let mut executed = HashSet::new();
+        let mut max_executing = 0;
+        for _ in 0..100 {
+            assert!(plumber.poll(&mut context()).is_pending());
+            let executing: Vec<_> = plumber
+                .inspect()
+                .into_iter()
+                .filter(|actor| actor.is_executing)
+                .map(|actor| actor.peer_scope)
+                .collect();
+            max_executing = max_executing.max(executing.len());
+            executed.extend(executing.iter().copied());
+            if executed.len() == 3 && executing.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(executed.len(), 3);
+        assert_eq!(max_executing, 2);
+    }
+
+    /// Checks that ingested particles are metered by origin
+    #[tokio::test]
+    async fn meter_particle_origin() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let key_pair = KeyPair::generate_ed25519();
+
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::LocalEffect,
+        );
+
+        let ingested = |origin| {
+            metrics
+                .ingested_particles
+                .get_or_create(&ParticleOriginLabel::new(origin))
+                .get()
+        };
+        assert_eq!(ingested(ParticleOrigin::Network), 1);
+        assert_eq!(ingested(ParticleOrigin::LocalEffect), 1);
+    }
+
+    /// Checks that the fan-out of local effects is metered
+    #[tokio::test]
+    async fn meter_local_effect_fanout() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let mut registry = Registry::default();
+        let metrics = ParticleExecutorMetrics::new(&mut registry);
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        let effect = |next_peers| LocalRoutingEffects {
+            particle: ExtendedParticle::new(particle.clone(), Span::none()),
+            next_peers,
+        };
+        plumber.ingest_local_effects(vec![
+            effect(vec![
+                PeerScope::Host,
+                PeerScope::WorkerId(worker_id),
+                PeerScope::WorkerId(worker_id),
+            ]),
+            effect(vec![PeerScope::Host]),
+        ]);
+
+        assert_eq!(metrics.local_reingests.get(), 4);
+        let ingested = metrics
+            .ingested_particles
+            .get_or_create(&ParticleOriginLabel::new(ParticleOrigin::LocalEffect))
+            .get();
+        assert_eq!(ingested, 4);
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry).expect("Could not encode metrics");
+        let fanout: Vec<_> = encoded
+            .lines()
+            .filter(|line| {
+                line.starts_with("particle_executor_local_effect_fanout_sum")
+                    || line.starts_with("particle_executor_local_effect_fanout_count")
+            })
+            .collect();
+        assert_eq!(
+            fanout,
+            vec![
+                "particle_executor_local_effect_fanout_sum 4.0",
+                "particle_executor_local_effect_fanout_count 2",
+            ]
+        );
+    }
+
+    /// Checks that interpretations without effects are counted
+    #[tokio::test]
+    async fn meter_empty_effects() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let key_pair = KeyPair::generate_ed25519();
+        let host_label = WorkerLabel::new(
+            WorkerType::Host,
+            plumber.scopes.get_host_peer_id().to_string(),
+        );
+
+        // VMMock always returns empty outcome
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        let empty_effects = || metrics.empty_effects.get_or_create(&host_label).get();
+        for _ in 0..100 {
+            if empty_effects() > 0 {
+                break;
+            }
+            let _ = plumber.poll(&mut context());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(empty_effects(), 1);
+    }
+
+    /// Checks that taken worker usage is reset and counted from zero again
+    #[tokio::test]
+    async fn take_worker_usage() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+
+        async fn execute(
+            plumber: &mut Plumber<VMMock, Arc<MockF>>,
+            key_pair: &KeyPair,
+            worker_id: WorkerId,
+            id: &str,
+        ) {
+            let mut particle = particle(now_ms(), 10000);
+            particle.id = id.to_string();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(key_pair).expect("Could not sign particle");
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::WorkerId(worker_id),
+                ParticleOrigin::Network,
+            );
+            for _ in 0..100 {
+                if plumber.worker_usage.contains_key(&worker_id) {
+                    break;
+                }
+                let _ = plumber.poll(&mut context());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        execute(&mut plumber, &key_pair, worker_id, "first").await;
+        let usage = plumber.take_worker_usage(worker_id);
+        assert_eq!(usage.interpretations, 1);
+        assert_eq!(plumber.take_worker_usage(worker_id), DealUsage::default());
+
+        execute(&mut plumber, &key_pair, worker_id, "second").await;
+        assert_eq!(plumber.take_worker_usage(worker_id).interpretations, 1);
+    }
+
+    /// Checks that an actor is evicted right after its particle is routed only to remote peers
+    #[tokio::test]
+    async fn evict_remote_only_actors() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            evict_remote_only_actors: true,
+            ..<_>::default()
+        })
+        .await;
+        let key_pair = KeyPair::generate_ed25519();
+        let remote_peer = RandomPeerId::random();
+
+        let mut forwarded = particle(now_ms(), 10000);
+        forwarded.id = "forwarded".to_string();
+        forwarded.script = remote_peer.to_base58();
+        forwarded.init_peer_id = key_pair.get_peer_id();
+        forwarded.sign(&key_pair).expect("Could not sign particle");
+        // VMMock routes this one nowhere
+        let idle = signed_particle(&key_pair, now_ms(), 10000);
+        for particle in [forwarded, idle] {
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+        assert_eq!(plumber.host_actors.len(), 2);
+
+        let mut effects = None;
+        for _ in 0..100 {
+            if let std::task::Poll::Ready(Ok(e)) = plumber.poll(&mut context()) {
+                effects = Some(e);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let effects = effects.expect("Particle wasn't routed to the remote peer");
+        assert_eq!(effects.next_peers, vec![remote_peer]);
+        let remaining: Vec<_> = plumber
+            .host_actors
+            .values()
+            .map(|a| a.particle_id().to_string())
+            .collect();
+        assert_eq!(remaining, vec![String::new()]);
+    }
+
+    /// Checks that plumbers with different jitter seeds clean up at different times
+    #[tokio::test]
+    async fn cleanup_jitter() {
+        set_mock_time(real_time::now_ms());
+
+        let interval = Duration::from_secs(60);
+        let config = |seed| PlumberConfig {
+            cleanup_interval: Some(interval),
+            cleanup_jitter: 0.5,
+            cleanup_jitter_seed: Some(seed),
+            ..<_>::default()
+        };
+        let (first, _first_env) = plumber_with_env(config(1)).await;
+        let (second, _second_env) = plumber_with_env(config(2)).await;
+
+        assert_ne!(first.next_cleanup_at, second.next_cleanup_at);
+        let earliest = now_ms() + interval.as_millis() as u64;
+        let latest = now_ms() + interval.mul_f64(1.5).as_millis() as u64;
+        for plumber in [&first, &second] {
+            assert!((earliest..=latest).contains(&plumber.next_cleanup_at));
+        }
+    }
+
+    /// Checks that interpretation is abandoned once the particle expires,
+    /// and its VM is reused once the abandoned call returns instead of being recreated
+    #[tokio::test]
+    async fn timeout_at_deadline() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let key_pair = KeyPair::generate_ed25519();
+        let host_label = WorkerLabel::new(
+            WorkerType::Host,
+            plumber.scopes.get_host_peer_id().to_string(),
+        );
+
+        let mut particle = particle(now_ms(), 200);
+        particle.data = b"sleep 1000".to_vec();
+        particle.init_peer_id = key_pair.get_peer_id();
+        particle.sign(&key_pair).expect("Could not sign particle");
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        let started = std::time::Instant::now();
+        let failures = || {
+            metrics
+                .interpretation_failures
+                .get_or_create(&host_label)
+                .get()
+        };
+        for _ in 0..100 {
+            if failures() > 0 {
+                break;
+            }
+            let _ = plumber.poll(&mut context());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(failures(), 1);
+        assert!(started.elapsed() < Duration::from_millis(800));
+        // VM is still busy with the abandoned call, so its slot stays taken
+        let pool_size = plumber.host_vm_pool.pool_size();
+        assert_eq!(plumber.host_vm_pool.free_vms(), pool_size - 1);
+
+        for _ in 0..200 {
+            if plumber.host_vm_pool.free_vms() == pool_size {
+                break;
+            }
+            let _ = plumber.poll(&mut context());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(plumber.host_vm_pool.free_vms(), pool_size);
+        assert_eq!(metrics.avm_recreated.get_or_create(&host_label).get(), 0);
+    }
+
+    /// Checks that interpretation running past the TTL multiplied by the grace factor is cancelled
+    #[tokio::test]
+    async fn cancel_overdue_execution() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            execution_grace_factor: Some(2.0),
+            ..<_>::default()
+        })
+        .await;
+        let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+        plumber.metrics = Some(Arc::new(metrics.clone()));
+        let key_pair = KeyPair::generate_ed25519();
+        let host_label = WorkerLabel::new(
+            WorkerType::Host,
+            plumber.scopes.get_host_peer_id().to_string(),
+        );
+
+        let mut particle = particle(now_ms(), 10000);
+        particle.data = b"sleep 60000".to_vec();
+        particle.init_peer_id = key_pair.get_peer_id();
+        particle.sign(&key_pair).expect("Could not sign particle");
+        plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        let executing = |plumber: &Plumber<VMMock, Arc<MockF>>| {
+            plumber.host_actors.values().any(|a| a.is_executing())
+        };
+        for _ in 0..100 {
+            if executing(&plumber) {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(executing(&plumber));
+
+        // still within the grace period
+        set_mock_time(now_ms() + 15000);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert!(executing(&plumber));
+
+        set_mock_time(now_ms() + 10000);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert!(!executing(&plumber));
+        match plumber.poll(&mut context()) {
+            std::task::Poll::Ready(Err(ExecutionTimeout { particle_id, .. })) => {
+                assert_eq!(particle_id, particle.id)
+            }
+            unexpected => panic!("Expected ExecutionTimeout, got {:?}", unexpected),
+        }
+        assert_eq!(metrics.avm_recreated.get_or_create(&host_label).get(), 1);
+    }
+
+    /// Checks that actors of all workers produce their effects when polled in parallel
+    #[tokio::test]
+    async fn parallel_worker_polling() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig {
+            worker_polling_threads: Some(2),
+            ..<_>::default()
+        })
+        .await;
+
+        let mut expected = HashMap::new();
+        for i in 0..3 {
+            let key_pair = KeyPair::generate_ed25519();
+            let worker_id = env.create_worker(&key_pair).await;
+            plumber.create_worker_pool(worker_id, 1);
+
+            // VMMock routes particle to the peer in its script
+            let remote_peer = RandomPeerId::random();
+            let mut particle = particle(now_ms(), 10000);
+            particle.id = format!("particle_{i}");
+            particle.script = remote_peer.to_base58();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(&key_pair).expect("Could not sign particle");
+            plumber.ingest(
+                ExtendedParticle::new(particle.clone(), Span::none()),
+                None,
+                PeerScope::WorkerId(worker_id),
+                ParticleOrigin::Network,
+            );
+            expected.insert(particle.id, vec![remote_peer]);
+        }
+
+        let mut effects = HashMap::new();
+        for _ in 0..100 {
+            while let std::task::Poll::Ready(Ok(e)) = plumber.poll(&mut context()) {
+                effects.insert(e.particle.particle.id, e.next_peers);
+            }
+            if effects.len() == expected.len() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(effects, expected);
+    }
+
+    /// Checks that a particle arrived while its worker's actors are polled on the worker's runtime
+    /// waits for them to be back and is executed then
+    #[tokio::test]
+    async fn ingest_while_polled_away() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig {
+            worker_polling_threads: Some(2),
+            ..<_>::default()
+        })
+        .await;
+
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+        // VMMock routes particle to the peer in its script
+        let remote_peer = RandomPeerId::random();
+        let worker_particle = |id: &str, ts| {
+            let mut particle = particle(ts, 10000);
+            particle.id = id.to_string();
+            particle.script = remote_peer.to_base58();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(&key_pair).expect("Could not sign particle");
+            ExtendedParticle::new(particle, Span::none())
+        };
+        plumber.ingest(
+            worker_particle("first", now_ms()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+
+        plumber.poll_workers_on_runtimes(2, now_ms(), &mut context());
+        assert!(plumber.polled_away.contains_key(&worker_id));
+        assert!(plumber.worker_vm_pools.get(&worker_id).is_none());
+        let outcome = plumber.ingest(
+            worker_particle("second", now_ms() + 1),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+        assert_eq!(outcome, IngestOutcome::Deferred);
+
+        let mut executed = vec![];
+        for _ in 0..100 {
+            while let std::task::Poll::Ready(Ok(effects)) = plumber.poll(&mut context()) {
+                assert_eq!(effects.next_peers, vec![remote_peer]);
+                executed.push(effects.particle.particle.id);
+            }
+            if executed.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        executed.sort();
+        assert_eq!(executed, vec!["first", "second"]);
+        assert!(plumber.polled_away.is_empty());
+        assert!(plumber.worker_vm_pools.contains_key(&worker_id));
+    }
+
+    /// Checks that a nonzero return code of the script is reported only if configured
+    #[tokio::test]
+    async fn report_avm_errors() {
+        set_mock_time(real_time::now_ms());
+
+        let key_pair = KeyPair::generate_ed25519();
+        for report_avm_errors in [false, true] {
+            let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+                report_avm_errors,
+                ..<_>::default()
+            })
+            .await;
+            let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+            plumber.metrics = Some(Arc::new(metrics.clone()));
+            let host_label = WorkerLabel::new(
+                WorkerType::Host,
+                plumber.scopes.get_host_peer_id().to_string(),
+            );
+
+            let mut particle = particle(now_ms(), 10000);
+            particle.data = b"fail 42 boom".to_vec();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(&key_pair).expect("Could not sign particle");
+            plumber.ingest(
+                ExtendedParticle::new(particle.clone(), Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+
+            let interpretations = || {
+                metrics
+                    .interpretation_successes
+                    .get_or_create(&host_label)
+                    .get()
+            };
+            let mut errors = vec![];
+            for _ in 0..100 {
+                if let std::task::Poll::Ready(Err(err)) = plumber.poll(&mut context()) {
+                    errors.push(err);
+                }
+                if interpretations() > 0 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(interpretations(), 1);
+            while let std::task::Poll::Ready(Err(err)) = plumber.poll(&mut context()) {
+                errors.push(err);
+            }
+
+            if report_avm_errors {
+                match errors.as_slice() {
+                    [AvmError {
+                        particle_id,
+                        ret_code,
+                        message,
+                    }] => {
+                        assert_eq!(particle_id, &particle.id);
+                        assert_eq!(*ret_code, 42);
+                        assert_eq!(message, "boom");
+                    }
+                    unexpected => panic!(
+                        "Expected Err(AquamarineApiError::AvmError), got {:?}",
+                        unexpected
+                    ),
+                }
+            } else {
+                assert!(errors.is_empty(), "Unexpected errors {:?}", errors);
+            }
+        }
+    }
+
+    /// Checks that effects addressed to the host or the loopback peer are re-ingested locally
+    #[tokio::test]
+    async fn loopback_effects() {
+        set_mock_time(real_time::now_ms());
+
+        let loopback_peer = RandomPeerId::random();
+        let key_pair = KeyPair::generate_ed25519();
+        for host_alias in [None, Some(loopback_peer)] {
+            let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+                loopback_peer: Some(loopback_peer),
+                ..<_>::default()
+            })
+            .await;
+            let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+            plumber.metrics = Some(Arc::new(metrics.clone()));
+            let next_peer = host_alias.unwrap_or(plumber.scopes.get_host_peer_id());
+
+            // VMMock routes particle to the peer in its script
+            let mut particle = particle(now_ms(), 10000);
+            particle.script = next_peer.to_base58();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(&key_pair).expect("Could not sign particle");
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+
+            let reingested = || {
+                metrics
+                    .ingested_particles
+                    .get_or_create(&ParticleOriginLabel::new(ParticleOrigin::LocalEffect))
+                    .get()
+            };
+            for _ in 0..100 {
+                if reingested() > 0 {
+                    break;
+                }
+                assert!(plumber.poll(&mut context()).is_pending());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(reingested() > 0);
+            assert!(plumber.events.is_empty());
+        }
+    }
+
+    /// Checks that in strict mode effects to unknown peers are reported instead of being sent
+    #[tokio::test]
+    async fn strict_peer_scopes() {
+        set_mock_time(real_time::now_ms());
+
+        let key_pair = KeyPair::generate_ed25519();
+        for strict_peer_scopes in [false, true] {
+            let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+                strict_peer_scopes,
+                ..<_>::default()
+            })
+            .await;
+            let metrics = ParticleExecutorMetrics::new(&mut Registry::default());
+            plumber.metrics = Some(Arc::new(metrics.clone()));
+            let host_label = WorkerLabel::new(
+                WorkerType::Host,
+                plumber.scopes.get_host_peer_id().to_string(),
+            );
+
+            // VMMock routes particle to the peer in its script
+            let mut particle = particle(now_ms(), 10000);
+            particle.script = RandomPeerId::random().to_base58();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(&key_pair).expect("Could not sign particle");
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+
+            let interpreted = || {
+                metrics
+                    .interpretation_successes
+                    .get_or_create(&host_label)
+                    .get()
+            };
+            for _ in 0..100 {
+                if interpreted() > 0 {
+                    break;
+                }
+                assert!(plumber.poll(&mut context()).is_pending());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(interpreted(), 1);
+
+            let unknown = metrics.unknown_peer_scopes.get_or_create(&host_label).get();
+            if strict_peer_scopes {
+                assert_eq!(unknown, 1);
+                assert!(plumber.events.is_empty());
+            } else {
+                assert_eq!(unknown, 0);
+                assert_eq!(plumber.events.len(), 1);
+            }
+        }
+    }
+
+    /// Checks that a particle reproducing itself via local effects is cut off
+    #[tokio::test]
+    async fn cut_off_local_hops() {
+        set_mock_time(real_time::now_ms());
+
+        let config = PlumberConfig {
+            max_local_hops: 3,
+            ..<_>::default()
+        };
+        let (mut plumber, _env) = plumber_with_env(config).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        let mut effect = LocalRoutingEffects {
+            particle: ExtendedParticle::new(particle.clone(), Span::none()),
+            next_peers: vec![PeerScope::Host],
+        };
+
+        for hops in 1..=4 {
+            plumber.ingest_local_effect(effect.clone());
+            // emulate actor producing the same local effect again
+            effect.particle = effect.particle.with_hops(hops);
+        }
+
+        assert_eq!(plumber.host_actors.len(), 1);
+        match plumber.poll(&mut context()) {
+            std::task::Poll::Ready(Err(MaxHopsExceeded { particle_id, hops })) => {
+                assert_eq!(particle_id, particle.id);
+                assert_eq!(hops, 4);
+            }
+            unexpected => panic!(
+                "Expected Poll::Ready(Err(AquamarineApiError::MaxHopsExceeded)), got {:?}",
+                unexpected
+            ),
+        }
+    }
+
+    /// Checks that under saturation ordinary particles are shed and management ones are admitted
+    #[tokio::test]
+    async fn shed_on_saturation() {
+        set_mock_time(real_time::now_ms());
+
+        let config = PlumberConfig {
+            saturation_mailbox_threshold: 1,
+            ..<_>::default()
+        };
+        let (mut plumber, env) = plumber_with_env(config).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+        assert!(!plumber.is_saturated());
+
+        // VMs aren't created before the first poll, so the only particle in a mailbox saturates the plumber
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        assert!(plumber.is_saturated());
+
+        let particle = signed_particle(&key_pair, now_ms() + 1, 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+        assert!(plumber.worker_actors.get(&worker_id).is_none());
+        match plumber.events.pop_front() {
+            Some(Err(Overloaded { particle_id })) => assert_eq!(particle_id, particle.id),
+            unexpected => panic!(
+                "Expected Err(AquamarineApiError::Overloaded), got {:?}",
+                unexpected
+            ),
+        }
+
+        let particle = signed_particle(&env.management_key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(|a| a.len()),
+            Some(1)
+        );
+        assert!(plumber.events.is_empty());
+    }
+
+    /// Checks that particles are shed while the buffered state exceeds the memory budget
+    #[tokio::test]
+    async fn shed_over_memory_budget() {
+        set_mock_time(real_time::now_ms());
+
+        let budget = 64 * 1024;
+        let config = PlumberConfig {
+            memory_budget: Some(budget),
+            ..<_>::default()
+        };
+        let (mut plumber, env) = plumber_with_env(config).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+        assert!(plumber.memory_estimate() < budget);
+
+        // effect with large data is waiting to be taken from `poll`
+        let large = Particle {
+            data: vec![0; 2 * budget],
+            ..particle(now_ms(), 10000)
+        };
+        plumber.buffer_effects([RemoteRoutingEffects {
+            particle: ExtendedParticle::new(large, Span::none()),
+            next_peers: vec![RandomPeerId::random()],
+        }]);
+        assert!(plumber.memory_estimate() > budget);
+
+        let ingest = |plumber: &mut Plumber<_, _, _>, ts| {
+            plumber.ingest(
+                ExtendedParticle::new(signed_particle(&key_pair, ts, 10000), Span::none()),
+                None,
+                PeerScope::WorkerId(worker_id),
+                ParticleOrigin::Network,
+            )
+        };
+        assert_eq!(
+            ingest(&mut plumber, now_ms()),
+            IngestOutcome::Rejected(RejectReason::Overloaded)
+        );
+        assert_eq!(
+            ingest(&mut plumber, now_ms() + 1),
+            IngestOutcome::Rejected(RejectReason::Overloaded)
+        );
+
+        // the effect is taken, and only the errors about shed particles are left
+        assert!(matches!(
+            plumber.poll(&mut context()),
+            std::task::Poll::Ready(Ok(_))
+        ));
+        assert!(plumber.memory_estimate() < budget);
+        assert_eq!(ingest(&mut plumber, now_ms() + 2), IngestOutcome::Accepted);
+    }
+
+    /// Checks that the memory and mailbox counters follow particles through execution and removal
+    #[tokio::test]
+    async fn running_load() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        assert_eq!(plumber.memory_estimate(), 0);
+
+        // VM mock routes the particle to the peer from its script
+        let key_pair = KeyPair::generate_ed25519();
+        let remote_peer = RandomPeerId::random();
+        let mut particle = particle(now_ms(), 10000);
+        particle.init_peer_id = key_pair.get_peer_id();
+        particle.script = remote_peer.to_base58();
+        particle.sign(&key_pair).expect("Could not sign particle");
+        plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        assert_eq!(plumber.total_mailbox_size(), 1);
+        let ingested = plumber.memory_estimate();
+        assert!(ingested > 0);
+
+        let mut effect = None;
+        for _ in 0..100 {
+            if let std::task::Poll::Ready(Ok(ready)) = plumber.poll(&mut context()) {
+                effect = Some(ready);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let effect = effect.expect("Particle wasn't executed");
+        assert_eq!(effect.next_peers, vec![remote_peer]);
+
+        // only the idle actor is left
+        assert_eq!(plumber.total_mailbox_size(), 0);
+        assert!(plumber.memory_estimate() > 0);
+        assert!(plumber.memory_estimate() < ingested);
+
+        assert_eq!(plumber.cancel_by_init_peer(key_pair.get_peer_id()), 1);
+        assert_eq!(plumber.memory_estimate(), 0);
+    }
+
+    /// Checks that actors created under saturation get a shortened deadline
+    #[tokio::test]
+    async fn clamp_ttl_on_saturation() {
+        set_mock_time(real_time::now_ms());
+
+        let config = PlumberConfig {
+            saturation_mailbox_threshold: 1,
+            saturated_ttl: Some(Duration::from_secs(1)),
+            ..<_>::default()
+        };
+        let (mut plumber, env) = plumber_with_env(config).await;
+        let key_pair = KeyPair::generate_ed25519();
+
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        assert!(plumber.is_saturated());
+
+        let particle = signed_particle(&env.management_key_pair, now_ms(), 60000);
+        let outcome = plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        assert_eq!(outcome, IngestOutcome::Accepted);
+
+        let is_expired = |init_peer_id: PeerId| {
+            plumber
+                .host_actors
+                .values()
+                .find(|a| a.init_peer_id() == init_peer_id)
+                .map(|a| a.is_expired(now_ms() + 2000))
+        };
+        // created before the saturation, keeps its own ttl
+        assert_eq!(is_expired(key_pair.get_peer_id()), Some(false));
+        assert_eq!(
+            is_expired(env.management_key_pair.get_peer_id()),
+            Some(true)
+        );
+    }
+
+    /// Checks that buffered events are counted without being consumed
+    #[tokio::test]
+    async fn pending_events() {
+        set_mock_time(real_time::now_ms());
+
+        let mut plumber = plumber().await;
+        let key_pair = KeyPair::generate_ed25519();
+
+        // VMMock routes particle to the peer in its script
+        let mut particle = particle(now_ms(), 10000);
+        particle.script = RandomPeerId::random().to_base58();
+        particle.init_peer_id = key_pair.get_peer_id();
+        particle.sign(&key_pair).expect("Could not sign particle");
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        for _ in 0..100 {
+            if plumber.pending_events() != (0, 0) {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(plumber.pending_events(), (1, 0));
+
+        for ts in [now_ms() - 200, now_ms() - 100] {
+            let expired = signed_particle(&key_pair, ts, 50);
+            plumber.ingest(
+                ExtendedParticle::new(expired, Span::none()),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+        assert_eq!(plumber.pending_events(), (1, 2));
+        assert_eq!(plumber.pending_events(), (1, 2));
+
+        assert!(matches!(
+            plumber.poll(&mut context()),
+            std::task::Poll::Ready(Ok(_))
+        ));
+        assert_eq!(plumber.pending_events(), (0, 2));
+    }
+
+    /// Checks that reported delivery failures are kept in a bounded buffer
+    #[tokio::test]
+    async fn dead_letters() {
+        set_mock_time(real_time::now_ms());
+
+        let config = PlumberConfig {
+            max_dead_letters: 2,
+            ..<_>::default()
+        };
+        let (mut plumber, _env) = plumber_with_env(config).await;
+        let peer_id = RandomPeerId::random();
+
+        plumber.report_delivery_failure("1".to_string(), peer_id);
+        let expected = DeadLetter {
+            particle_id: "1".to_string(),
+            peer_id,
+            reported_at: now_ms(),
+        };
+        assert_eq!(plumber.dead_letters().collect::<Vec<_>>(), vec![&expected]);
+
+        plumber.report_delivery_failure("2".to_string(), peer_id);
+        plumber.report_delivery_failure("3".to_string(), peer_id);
+        let particle_ids: Vec<_> = plumber
+            .dead_letters()
+            .map(|letter| letter.particle_id.as_str())
+            .collect();
+        assert_eq!(particle_ids, vec!["2", "3"]);
+    }
+
+    /// Checks that particle waits for the worker runtime instead of being dropped
+    #[tokio::test]
+    async fn wait_for_worker_runtime() {
+        set_mock_time(real_time::now_ms());
+
+        let config = PlumberConfig {
+            worker_runtime_wait: Duration::from_secs(1),
+            ..<_>::default()
+        };
+        let (mut plumber, env) = plumber_with_env(config).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+        hide_runtime_handle(worker_id);
+
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+        assert!(plumber.worker_actors.get(&worker_id).is_none());
+        assert_eq!(plumber.deferred.len(), 1);
+
+        // Runtime is still missing, particle keeps waiting
+        set_mock_time(now_ms() + 500);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert_eq!(plumber.deferred.len(), 1);
+
+        reveal_runtime_handle(worker_id);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert_eq!(plumber.deferred.len(), 0);
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(|a| a.len()),
+            Some(1)
+        );
+    }
+
+    /// Checks that worker reset removes actors and schedules their cleanup, keeping the pool
+    #[tokio::test]
+    async fn reset_worker() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+
+        for ts in [now_ms(), now_ms() + 1] {
+            let particle = signed_particle(&key_pair, ts, 10000);
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::WorkerId(worker_id),
+                ParticleOrigin::Network,
+            );
+        }
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(|a| a.len()),
+            Some(2)
+        );
+
+        let report = plumber
+            .reset_worker(worker_id, ResetMode::Immediate)
+            .expect("Immediate reset is done right away");
+
+        assert_eq!(report.actors, 2);
+        assert_eq!(report.cleanup_keys, 2);
+        assert_eq!(report.dropped_particles, 2);
+        assert_eq!(plumber.pending_cleanup_keys.len(), 2);
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(|a| a.len()),
+            Some(0)
+        );
+        assert!(plumber.worker_vm_pools.contains_key(&worker_id));
+    }
+
+    /// Checks that graceful reset waits for the interpretation in progress, while immediate one cancels it
+    #[tokio::test]
+    async fn graceful_reset_worker() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+
+        async fn start_long_execution(
+            plumber: &mut Plumber<VMMock, Arc<MockF>>,
+            key_pair: &KeyPair,
+            worker_id: WorkerId,
+        ) {
+            let mut particle = particle(now_ms(), 10000);
+            particle.data = b"sleep 300".to_vec();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(key_pair).expect("Could not sign particle");
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::WorkerId(worker_id),
+                ParticleOrigin::Network,
+            );
+            for _ in 0..100 {
+                let actors = plumber.worker_actors.get(&worker_id);
+                if actors.is_some_and(|actors| actors.values().any(|a| a.is_executing())) {
+                    return;
+                }
+                let _ = plumber.poll(&mut context());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("Particle execution hasn't started");
+        }
+
+        start_long_execution(&mut plumber, &key_pair, worker_id).await;
+        let graceful = ResetMode::Graceful {
+            timeout: Duration::from_secs(5),
+        };
+        assert!(plumber.reset_worker(worker_id, graceful).is_none());
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(|a| a.len()),
+            Some(1)
+        );
+        for _ in 0..100 {
+            if plumber.worker_actors.get(&worker_id).map(|a| a.len()) == Some(0) {
+                break;
+            }
+            let _ = plumber.poll(&mut context());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(|a| a.len()),
+            Some(0)
+        );
+        let usage = plumber.take_worker_usage(worker_id);
+        assert_eq!((usage.interpretations, usage.failures), (1, 0));
+
+        start_long_execution(&mut plumber, &key_pair, worker_id).await;
+        let report = plumber
+            .reset_worker(worker_id, ResetMode::Immediate)
+            .expect("Immediate reset is done right away");
+        assert_eq!(report.cancelled, 1);
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(|a| a.len()),
+            Some(0)
+        );
+    }
+
+    /// Checks that particle tokens are produced by the configured signer
+    #[tokio::test]
+    async fn custom_token_signer() {
+        set_mock_time(real_time::now_ms());
+
+        struct MockSigner;
+        impl ParticleTokenSigner for MockSigner {
+            fn sign(&self, particle_signature: &[u8]) -> eyre::Result<Vec<u8>> {
+                Ok(particle_signature.iter().rev().copied().collect())
+            }
+        }
+
+        let mut plumber = plumber().await;
+        plumber.set_token_signer(Arc::new(MockSigner));
+        let key_pair = KeyPair::generate_ed25519();
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        ingest_host(&mut plumber, &key_pair);
+
+        let token = plumber
+            .host_actors
+            .values()
+            .next()
+            .map(|actor| actor.cleanup_key().particle_token);
+        let reversed: Vec<u8> = particle.signature.iter().rev().copied().collect();
+        assert_eq!(token, Some(bs58::encode(reversed).into_string()));
+    }
+
+    fn ingest_host(plumber: &mut Plumber<VMMock, Arc<MockF>>, key_pair: &KeyPair) -> IngestOutcome {
+        let particle = signed_particle(key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        )
+    }
+
+    /// Checks that only peers from the allowlist are admitted
+    #[tokio::test]
+    async fn peer_allowlist() {
+        set_mock_time(real_time::now_ms());
+
+        let allowed = KeyPair::generate_ed25519();
+        let stranger = KeyPair::generate_ed25519();
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            peer_allowlist: Some(HashSet::from([allowed.get_peer_id()])),
+            ..<_>::default()
+        })
+        .await;
+
+        assert_eq!(ingest_host(&mut plumber, &allowed), IngestOutcome::Accepted);
+        assert_eq!(
+            ingest_host(&mut plumber, &stranger),
+            IngestOutcome::Rejected(RejectReason::PeerNotAllowed)
+        );
+        match plumber.events.pop_front() {
+            Some(Err(PeerNotAllowed { init_peer_id, .. })) => {
+                assert_eq!(init_peer_id, stranger.get_peer_id().to_string())
+            }
+            unexpected => panic!(
+                "Expected Err(AquamarineApiError::PeerNotAllowed), got {:?}",
+                unexpected
+            ),
+        }
+    }
+
+    /// Checks that peers from the blocklist are rejected while the rest are admitted
+    #[tokio::test]
+    async fn peer_blocklist() {
+        set_mock_time(real_time::now_ms());
 
-struct PlumberParams<'p, F>
-where
-    F: Clone,
-{
-    builtins: &'p F,
-    key_storage: &'p KeyStorage,
-    data_store: Arc<ParticleDataStore>,
-}
+        let blocked = KeyPair::generate_ed25519();
+        let client = KeyPair::generate_ed25519();
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            peer_blocklist: HashSet::from([blocked.get_peer_id()]),
+            ..<_>::default()
+        })
+        .await;
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
-    use std::convert::Infallible;
-    use std::path::PathBuf;
-    use std::task::Waker;
-    use std::{sync::Arc, task::Context};
+        assert_eq!(
+            ingest_host(&mut plumber, &blocked),
+            IngestOutcome::Rejected(RejectReason::PeerNotAllowed)
+        );
+        assert_eq!(ingest_host(&mut plumber, &client), IngestOutcome::Accepted);
+        assert_eq!(plumber.host_actors.len(), 1);
+    }
 
-    use avm_server::{AVMMemoryStats, CallResults, ParticleParameters};
-    use fluence_keypair::KeyPair;
-    use fluence_libp2p::RandomPeerId;
-    use futures::task::noop_waker_ref;
-    use workers::{DummyCoreManager, KeyStorage, PeerScopes, Workers};
+    /// Checks that the management peer bypasses both lists
+    #[tokio::test]
+    async fn management_peer_bypasses_lists() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let management_peer_id = env.management_key_pair.get_peer_id();
+        plumber.plumber_config.peer_allowlist = Some(HashSet::new());
+        plumber.plumber_config.peer_blocklist = HashSet::from([management_peer_id]);
+
+        assert_eq!(
+            ingest_host(&mut plumber, &env.management_key_pair),
+            IngestOutcome::Accepted
+        );
+        assert_eq!(
+            ingest_host(&mut plumber, &KeyPair::generate_ed25519()),
+            IngestOutcome::Rejected(RejectReason::PeerNotAllowed)
+        );
+    }
+
+    /// Checks that an actor is evicted once it exceeds its execution budget, despite its TTL
+    #[tokio::test]
+    async fn evict_over_budget() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            max_actor_execution_time: Some(Duration::ZERO),
+            ..<_>::default()
+        })
+        .await;
+        let key_pair = KeyPair::generate_ed25519();
+        let particle = signed_particle(&key_pair, now_ms(), 100_000);
+        plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        assert_eq!(plumber.host_actors.len(), 1);
+
+        let mut evicted = None;
+        for _ in 0..100 {
+            if let std::task::Poll::Ready(Err(err)) = plumber.poll(&mut context()) {
+                evicted = Some(err);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        match evicted {
+            Some(BudgetExceeded { particle_id, .. }) => assert_eq!(particle_id, particle.id),
+            unexpected => panic!(
+                "Expected AquamarineApiError::BudgetExceeded, got {:?}",
+                unexpected
+            ),
+        }
+        assert!(plumber.host_actors.is_empty());
+        // data of the evicted actor is being cleaned up
+        assert!(plumber.cleanup_future.is_some());
+    }
+
+    /// Checks that data of the evicted actors is passed to the data store for cleanup
+    #[tokio::test]
+    async fn cleanup_evicted_actors() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) =
+            plumber_with_store(PlumberConfig::default(), |_| MockDataStore::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        assert_eq!(plumber.cancel_by_init_peer(key_pair.get_peer_id()), 1);
+        // the first poll schedules the cleanup, the second one runs it
+        let _ = plumber.poll(&mut context());
+        let _ = plumber.poll(&mut context());
+
+        let cleaned = plumber.data_store.cleaned.lock();
+        assert_eq!(cleaned.len(), 1);
+        assert_eq!(cleaned[0].particle_id, particle.id);
+        assert_eq!(cleaned[0].signature, particle.signature);
+    }
+
+    /// Checks that host and workers share a single in-flight cleanup batch
+    #[tokio::test]
+    async fn shared_cleanup_ceiling() {
+        set_mock_time(real_time::now_ms());
+
+        let config = PlumberConfig {
+            cleanup_batch_size: 3,
+            ..<_>::default()
+        };
+        let (mut plumber, env) = plumber_with_store(config, |_| MockDataStore::default()).await;
+        let mut scopes = vec![PeerScope::Host];
+        for _ in 0..3 {
+            let key_pair = KeyPair::generate_ed25519();
+            let worker_id = env.create_worker(&key_pair).await;
+            plumber.create_worker_pool(worker_id, 1);
+            scopes.push(PeerScope::WorkerId(worker_id));
+        }
+
+        let key_pair = KeyPair::generate_ed25519();
+        for (i, peer_scope) in scopes.into_iter().enumerate() {
+            for ts in [now_ms(), now_ms() + 1] {
+                let mut particle = particle(ts, 100);
+                particle.id = format!("particle_{i}");
+                particle.init_peer_id = key_pair.get_peer_id();
+                particle.sign(&key_pair).expect("Could not sign particle");
+                plumber.ingest(
+                    ExtendedParticle::new(particle, Span::none()),
+                    None,
+                    peer_scope,
+                    ParticleOrigin::Network,
+                );
+            }
+        }
+
+        set_mock_time(now_ms() + 1000);
+        for _ in 0..100 {
+            if plumber.data_store.cleaned.lock().len() == 8 {
+                break;
+            }
+            let _ = plumber.poll(&mut context());
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let data_store = &plumber.data_store;
+        assert_eq!(data_store.cleaned.lock().len(), 8);
+        assert_eq!(data_store.max_in_flight.load(Ordering::SeqCst), 1);
+        assert!(data_store.batches.lock().iter().all(|size| *size <= 3));
+    }
+
+    /// Checks that service registrations beyond the limit are queued until a slot frees up
+    #[tokio::test]
+    async fn service_tasks_limit() {
+        let (plumber, _env) = plumber_with_env(PlumberConfig {
+            max_service_tasks: 2,
+            ..<_>::default()
+        })
+        .await;
+
+        for i in 0..10 {
+            plumber
+                .add_service(format!("service_{i}"), <_>::default(), None)
+                .expect("Could not spawn add_service task");
+        }
+        for i in 0..5 {
+            plumber
+                .remove_service(format!("service_{i}"))
+                .expect("Could not spawn remove_service task");
+        }
+
+        for _ in 0..100 {
+            if plumber.builtins.finished.load(Ordering::SeqCst) == 15 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(plumber.builtins.finished.load(Ordering::SeqCst), 15);
+        assert!(plumber.builtins.max_running.load(Ordering::SeqCst) <= 2);
+        assert_eq!(plumber.service_tasks.available_permits(), 2);
+    }
+
+    /// Checks that ingest reports what happened to the particle
+    #[tokio::test]
+    async fn ingest_outcome() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let mut ingest = |particle: ExtendedParticle| {
+            plumber.ingest(particle, None, PeerScope::Host, ParticleOrigin::Network)
+        };
+
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        let outcome = ingest(ExtendedParticle::new(particle.clone(), Span::none()));
+        assert_eq!(outcome, IngestOutcome::Accepted);
+        let outcome = ingest(ExtendedParticle::new(particle.clone(), Span::none()));
+        assert_eq!(outcome, IngestOutcome::ForwardedToExistingActor);
+
+        let expired = signed_particle(&key_pair, now_ms() - 2000, 1000);
+        let outcome = ingest(ExtendedParticle::new(expired, Span::none()));
+        assert_eq!(outcome, IngestOutcome::Rejected(RejectReason::Expired));
+
+        let looping = ExtendedParticle::new(particle.clone(), Span::none()).with_hops(u32::MAX);
+        let outcome = ingest(looping);
+        assert_eq!(
+            outcome,
+            IngestOutcome::Rejected(RejectReason::MaxHopsExceeded)
+        );
+
+        let mut forged = signed_particle(&key_pair, now_ms() + 1, 10000);
+        forged.script = "(null)".to_string();
+        let outcome = ingest(ExtendedParticle::new(forged, Span::none()));
+        assert_eq!(
+            outcome,
+            IngestOutcome::Rejected(RejectReason::InvalidSignature)
+        );
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            offload_signature_verification: true,
+            ..<_>::default()
+        })
+        .await;
+        let outcome = plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+        assert_eq!(outcome, IngestOutcome::PendingVerification);
+    }
+
+    /// Checks that particles for a worker pool without VMs are rejected according to the policy
+    #[tokio::test]
+    async fn no_capacity_policy() {
+        set_mock_time(real_time::now_ms());
+
+        let policies = [
+            (NoCapacityPolicy::Keep, 3),
+            (NoCapacityPolicy::Reject, 0),
+            (NoCapacityPolicy::Queue { max_particles: 2 }, 2),
+        ];
+        for (no_capacity_policy, accepted) in policies {
+            let (mut plumber, env) = plumber_with_env(PlumberConfig {
+                no_capacity_policy,
+                ..<_>::default()
+            })
+            .await;
+            let key_pair = KeyPair::generate_ed25519();
+            let worker_id = env.create_worker(&key_pair).await;
+            plumber.create_worker_pool(worker_id, 0);
+
+            let outcomes: Vec<_> = (0..3)
+                .map(|i| {
+                    let particle = signed_particle(&key_pair, now_ms() + i, 10000);
+                    plumber.ingest(
+                        ExtendedParticle::new(particle, Span::none()),
+                        None,
+                        PeerScope::WorkerId(worker_id),
+                        ParticleOrigin::Network,
+                    )
+                })
+                .collect();
+
+            let rejected = IngestOutcome::Rejected(RejectReason::NoCapacity);
+            let expected: Vec<_> = (0..3)
+                .map(|i| {
+                    if i < accepted {
+                        IngestOutcome::Accepted
+                    } else {
+                        rejected
+                    }
+                })
+                .collect();
+            assert_eq!(outcomes, expected, "{no_capacity_policy:?}");
+            assert_eq!(plumber.events.len(), 3 - accepted);
+            assert!(plumber
+                .events
+                .iter()
+                .all(|event| matches!(event, Err(NoCapacity { .. }))));
+        }
+    }
+
+    /// Checks that particles are rejected once the actor mailbox is full, with separate host and worker limits
+    #[tokio::test]
+    async fn mailbox_full() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig {
+            max_host_mailbox_size: Some(2),
+            max_worker_mailbox_size: Some(1),
+            ..<_>::default()
+        })
+        .await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+
+        // VMs aren't created before the first poll, so particles stay in the mailboxes
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        let mut ingest = |peer_scope| {
+            plumber.ingest(
+                ExtendedParticle::new(particle.clone(), Span::none()),
+                None,
+                peer_scope,
+                ParticleOrigin::Network,
+            )
+        };
+        let host_outcomes: Vec<_> = (0..3).map(|_| ingest(PeerScope::Host)).collect();
+        let worker_outcomes: Vec<_> = (0..2)
+            .map(|_| ingest(PeerScope::WorkerId(worker_id)))
+            .collect();
+
+        let rejected = IngestOutcome::Rejected(RejectReason::MailboxFull);
+        assert_eq!(
+            host_outcomes,
+            vec![
+                IngestOutcome::Accepted,
+                IngestOutcome::ForwardedToExistingActor,
+                rejected
+            ]
+        );
+        assert_eq!(worker_outcomes, vec![IngestOutcome::Accepted, rejected]);
+        assert_eq!(plumber.events.len(), 2);
+        for event in plumber.events.drain(..) {
+            match event {
+                Err(MailboxFull { particle_id }) => assert_eq!(particle_id, particle.id),
+                unexpected => panic!(
+                    "Expected Err(AquamarineApiError::MailboxFull), got {:?}",
+                    unexpected
+                ),
+            }
+        }
+    }
+
+    /// Checks that shutdown waits for the interpretation in progress and cleans up the expired actors
+    #[tokio::test]
+    async fn shutdown() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) =
+            plumber_with_store(PlumberConfig::default(), |_| MockDataStore::default()).await;
+        let metrics = VmPoolMetrics::new(&mut Registry::default());
+        let host_label = WorkerLabel::new(WorkerType::Host, "host".to_string());
+        plumber.host_vm_pool = VmPool::new(
+            1,
+            (),
+            Some(metrics.clone()),
+            host_label.clone(),
+            None,
+            plumber.avm_wasm_backend.clone(),
+            <_>::default(),
+        );
+        let data_store = plumber.data_store.clone();
+
+        let key_pair = KeyPair::generate_ed25519();
+        let mut particle = particle(now_ms(), 10000);
+        particle.data = b"sleep 300".to_vec();
+        particle.init_peer_id = key_pair.get_peer_id();
+        particle.sign(&key_pair).expect("Could not sign particle");
+        plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        let is_executing = |plumber: &Plumber<_, _, _>| {
+            plumber
+                .host_actors
+                .values()
+                .any(|actor| actor.is_executing())
+        };
+        for _ in 0..100 {
+            if is_executing(&plumber) {
+                break;
+            }
+            let _ = plumber.poll(&mut context());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(is_executing(&plumber));
+
+        // the particle expires while it's being executed
+        set_mock_time(now_ms() + 20000);
+        let put_vm = || metrics.put_vm.get_or_create(&host_label).get();
+        assert_eq!(put_vm(), 0);
+
+        plumber.shutdown().await;
+
+        assert_eq!(put_vm(), 1);
+        assert_eq!(data_store.cleaned.lock().len(), 1);
+    }
+
+    /// Checks that particles left in the mailboxes on shutdown are restored on the next start
+    #[tokio::test]
+    async fn restore_mailboxes() {
+        let now = real_time::now_ms();
+        set_mock_time(now);
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig::default()).await;
+        let data_store = plumber.data_store.clone();
+
+        let long_lived = signed_particle(&KeyPair::generate_ed25519(), now, 10000);
+        let short_lived = signed_particle(&KeyPair::generate_ed25519(), now, 1000);
+        for particle in [&long_lived, &long_lived, &short_lived] {
+            plumber.ingest(
+                ExtendedParticle::new(particle.clone(), Span::none()).with_hops(1),
+                None,
+                PeerScope::Host,
+                ParticleOrigin::Network,
+            );
+        }
+        assert_eq!(plumber.host_actors.len(), 2);
+        plumber.shutdown().await;
+
+        // short-lived particle expires while the peer is down
+        set_mock_time(now + 2000);
+        let (mut plumber, _env) =
+            plumber_with_store(PlumberConfig::default(), |_| (*data_store).clone()).await;
+        let restored = plumber
+            .restore()
+            .await
+            .expect("Could not restore mailboxes");
+
+        assert_eq!(restored, 2);
+        assert_eq!(plumber.host_actors.len(), 1);
+        let actor = plumber.host_actors.values().next().unwrap();
+        let mailbox: Vec<_> = actor
+            .mailbox()
+            .map(|p| (p.particle.clone(), p.hops))
+            .collect();
+        assert_eq!(mailbox, vec![(long_lived.clone(), 1), (long_lived, 1)]);
+
+        // snapshot is restored only once
+        assert_eq!(plumber.restore().await.expect("Could not restore"), 0);
+    }
+
+    /// Checks that a particle routed to the host by an interpretation finished on shutdown
+    /// is saved with the particles of the mailboxes
+    #[tokio::test]
+    async fn shutdown_saves_local_effects() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) =
+            plumber_with_store(PlumberConfig::default(), |_| MockDataStore::default()).await;
+        let data_store = plumber.data_store.clone();
+
+        // VMMock routes particle to the peer in its script
+        let key_pair = KeyPair::generate_ed25519();
+        let mut particle = particle(now_ms(), 10000);
+        particle.script = plumber.scopes.get_host_peer_id().to_base58();
+        particle.data = b"sleep 300".to_vec();
+        particle.init_peer_id = key_pair.get_peer_id();
+        particle.sign(&key_pair).expect("Could not sign particle");
+        plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()).with_hops(1),
+            None,
+            PeerScope::Host,
+            ParticleOrigin::Network,
+        );
+
+        let is_executing = |plumber: &Plumber<_, _, _>| {
+            plumber
+                .host_actors
+                .values()
+                .any(|actor| actor.is_executing())
+        };
+        for _ in 0..100 {
+            if is_executing(&plumber) {
+                break;
+            }
+            let _ = plumber.poll(&mut context());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(is_executing(&plumber));
+
+        plumber.shutdown().await;
+
+        let saved = data_store.mailboxes.lock().clone();
+        assert_eq!(
+            saved,
+            vec![QueuedParticle {
+                particle,
+                peer_scope: PeerScope::Host,
+                hops: 2,
+            }]
+        );
+    }
+
+    /// Checks that restore admits particles expired within the clock skew tolerance
+    #[tokio::test]
+    async fn restore_within_clock_skew_tolerance() {
+        let now = real_time::now_ms();
+        set_mock_time(now);
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+            clock_skew_tolerance_ms: Some(1000),
+            ..<_>::default()
+        })
+        .await;
+        let queued = [500, 1500].map(|expired_for| QueuedParticle {
+            particle: signed_particle(&KeyPair::generate_ed25519(), now - expired_for, 0),
+            peer_scope: PeerScope::Host,
+            hops: 0,
+        });
+        plumber
+            .data_store
+            .store_mailboxes(&queued)
+            .await
+            .expect("Could not store mailboxes");
+
+        let restored = plumber.restore().await.expect("Could not restore");
+        assert_eq!(restored, 1);
+        assert_eq!(plumber.host_actors.len(), 1);
+    }
+
+    /// Checks that a particle of a worker restored before the worker's pool is created
+    /// waits for the pool and is executed once it appears
+    #[tokio::test]
+    async fn restore_worker_mailboxes() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+
+        // VMMock routes particle to the peer in its script
+        let remote_peer = RandomPeerId::random();
+        let mut particle = particle(now_ms(), 10000);
+        particle.script = remote_peer.to_base58();
+        particle.init_peer_id = key_pair.get_peer_id();
+        particle.sign(&key_pair).expect("Could not sign particle");
+        let queued = QueuedParticle {
+            particle: particle.clone(),
+            peer_scope: PeerScope::WorkerId(worker_id),
+            hops: 0,
+        };
+        plumber
+            .data_store
+            .store_mailboxes(&[queued])
+            .await
+            .expect("Could not store mailboxes");
+
+        let restored = plumber.restore().await.expect("Could not restore");
+        assert_eq!(restored, 1);
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(HashMap::len),
+            Some(1)
+        );
+        assert!(plumber.poll(&mut context()).is_pending());
+
+        plumber.create_worker_pool(worker_id, 1);
+        let mut next_peers = None;
+        for _ in 0..100 {
+            if let std::task::Poll::Ready(Ok(effects)) = plumber.poll(&mut context()) {
+                assert_eq!(effects.particle.particle.id, particle.id);
+                next_peers = Some(effects.next_peers);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(next_peers, Some(vec![remote_peer]));
+    }
+
+    /// Checks that each worker pool publishes its metrics under its own label
+    #[tokio::test]
+    async fn worker_pool_metrics() {
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let mut registry = Registry::default();
+        plumber.host_vm_pool = VmPool::new(
+            1,
+            (),
+            Some(VmPoolMetrics::new(&mut registry)),
+            WorkerLabel::new(WorkerType::Host, "host".to_string()),
+            None,
+            plumber.avm_wasm_backend.clone(),
+            <_>::default(),
+        );
+
+        let mut expected =
+            vec![r#"aqua_vm_pool_pool_size{worker_type="Host",peer_id="host"} 1"#.to_string()];
+        for thread_count in [2, 3] {
+            let worker_id = env.create_worker(&KeyPair::generate_ed25519()).await;
+            plumber.create_worker_pool(worker_id, thread_count);
+            let peer_id: PeerId = worker_id.into();
+            expected.push(format!(
+                r#"aqua_vm_pool_pool_size{{worker_type="Worker",peer_id="{peer_id}"}} {thread_count}"#
+            ));
+        }
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry).expect("Could not encode metrics");
+        let mut pool_sizes: Vec<_> = encoded
+            .lines()
+            .filter(|line| line.starts_with("aqua_vm_pool_pool_size{"))
+            .map(String::from)
+            .collect();
+        pool_sizes.sort();
+        expected.sort();
+        assert_eq!(pool_sizes, expected);
+    }
+
+    /// Checks that only the particle with an invalid signature is rejected from a batch
+    #[tokio::test]
+    async fn ingest_batch() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig::default()).await;
+        let alice = KeyPair::generate_ed25519();
+        let bob = KeyPair::generate_ed25519();
+        let particle = |key_pair: &KeyPair, id: &str, ts: u64| {
+            let mut particle = particle(ts, 10000);
+            particle.id = id.to_string();
+            particle.init_peer_id = key_pair.get_peer_id();
+            particle.sign(key_pair).expect("Could not sign particle");
+            particle
+        };
+
+        let mut forged = particle(&alice, "forged", now_ms() + 1);
+        forged.script = "(null)".to_string();
+        let batch = vec![
+            particle(&alice, "alice_1", now_ms()),
+            forged,
+            particle(&bob, "bob", now_ms()),
+            particle(&alice, "alice_2", now_ms() + 2),
+        ];
+        let batch = batch
+            .into_iter()
+            .map(|p| (ExtendedParticle::new(p, Span::none()), None))
+            .collect();
+
+        let outcomes = plumber.ingest_batch(batch, PeerScope::Host, ParticleOrigin::Network);
+        assert_eq!(
+            outcomes,
+            vec![
+                IngestOutcome::Accepted,
+                IngestOutcome::Rejected(RejectReason::InvalidSignature),
+                IngestOutcome::Accepted,
+                IngestOutcome::Accepted,
+            ]
+        );
+        assert_eq!(plumber.host_actors.len(), 3);
+        match plumber.events.pop_front() {
+            Some(Err(SignatureVerificationFailed { particle_id, .. })) => {
+                assert_eq!(particle_id, "forged")
+            }
+            unexpected => panic!(
+                "Expected Err(AquamarineApiError::SignatureVerificationFailed), got {:?}",
+                unexpected
+            ),
+        }
+        assert!(plumber.events.is_empty());
+    }
+
+    /// Checks that particles sharing a signature are kept apart under the strict actor keying
+    #[tokio::test]
+    async fn strict_actor_keying() {
+        set_mock_time(real_time::now_ms());
+
+        let key_pair = KeyPair::generate_ed25519();
+        let first = Particle {
+            id: "first".to_string(),
+            ..signed_particle(&key_pair, now_ms(), 10000)
+        };
+        let second = Particle {
+            id: "second".to_string(),
+            ..first.clone()
+        };
+
+        for (actor_keying, expected_actors) in
+            [(ActorKeying::Signature, 1), (ActorKeying::Strict, 2)]
+        {
+            let (mut plumber, _env) = plumber_with_env(PlumberConfig {
+                actor_keying,
+                ..<_>::default()
+            })
+            .await;
+            // signature verification is bypassed, as the particles can't both be valid
+            for particle in [first.clone(), second.clone()] {
+                plumber.ingest_to_actor(
+                    ExtendedParticle::new(particle, Span::none()),
+                    None,
+                    PeerScope::Host,
+                );
+            }
+            assert_eq!(
+                plumber.host_actors.len(),
+                expected_actors,
+                "{actor_keying:?}"
+            );
+        }
+    }
+
+    /// Checks that deal expiry removes worker actors and cleans their data
+    #[tokio::test]
+    async fn on_deal_expired() {
+        set_mock_time(real_time::now_ms());
+
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+
+        for ts in [now_ms(), now_ms() + 1] {
+            let particle = signed_particle(&key_pair, ts, 10000);
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                PeerScope::WorkerId(worker_id),
+                ParticleOrigin::Network,
+            );
+        }
+
+        let unknown_deal = DealId::from("unknown_deal".to_string());
+        assert!(plumber.on_deal_expired(unknown_deal).is_none());
+
+        let deal_id = env.workers.get_deal_id(worker_id).unwrap();
+        let report = plumber
+            .on_deal_expired(deal_id)
+            .expect("worker must be found");
+        assert_eq!(report.actors, 2);
+        assert_eq!(plumber.pending_cleanup_keys.len(), 2);
+        assert!(!plumber.worker_vm_pools.contains_key(&worker_id));
+
+        // cleanup is scheduled on the next poll
+        let _ = plumber.poll(&mut context());
+        assert!(plumber.pending_cleanup_keys.is_empty());
+        assert!(plumber.cleanup_future.is_some());
+        assert!(plumber.worker_actors.get(&worker_id).is_none());
+    }
+
+    /// Checks that particles of a worker without a pool are rejected with a distinct error
+    #[tokio::test]
+    async fn reject_without_worker_pool() {
+        set_mock_time(real_time::now_ms());
 
-    use particle_args::Args;
-    use particle_execution::{FunctionOutcome, ParticleFunction, ParticleParams, ServiceFunction};
-    use particle_protocol::{ExtendedParticle, Particle};
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
 
-    use crate::deadline::Deadline;
-    use crate::plumber::mock_time::set_mock_time;
-    use crate::plumber::{now_ms, real_time};
-    use crate::vm_pool::VmPool;
-    use crate::AquamarineApiError::ParticleExpired;
-    use crate::{AquaRuntime, ParticleDataStore, ParticleEffects, Plumber};
-    use async_trait::async_trait;
-    use avm_server::avm_runner::RawAVMOutcome;
-    use marine_wasmtime_backend::{WasmtimeConfig, WasmtimeWasmBackend};
-    use particle_services::{PeerScope, WasmBackendConfig};
-    use tracing::Span;
+        // by default particle waits for the pool
+        let waiting = signed_particle(&key_pair, now_ms(), 10000);
+        let outcome = plumber.ingest(
+            ExtendedParticle::new(waiting, Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+        assert_eq!(outcome, IngestOutcome::Accepted);
 
-    struct MockF;
+        plumber.plumber_config.reject_without_worker_pool = true;
+        let particle = signed_particle(&key_pair, now_ms() + 1, 10000);
+        let outcome = plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
 
-    #[async_trait]
-    impl ParticleFunction for MockF {
-        async fn call(&self, _args: Args, _particle: ParticleParams) -> FunctionOutcome {
-            panic!("no builtins in plumber tests!")
+        assert_eq!(outcome, IngestOutcome::Rejected(RejectReason::NoWorkerPool));
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(HashMap::len),
+            Some(1)
+        );
+        match plumber.poll(&mut context()) {
+            std::task::Poll::Ready(Err(NoWorkerPool {
+                worker_id: rejected_worker_id,
+                particle_id,
+            })) => {
+                assert_eq!(rejected_worker_id, worker_id.to_string());
+                assert_eq!(particle_id, particle.id);
+            }
+            unexpected => panic!(
+                "Expected Err(AquamarineApiError::NoWorkerPool), got {:?}",
+                unexpected
+            ),
         }
+    }
 
-        async fn extend(
-            &self,
-            _service: String,
-            _functions: HashMap<String, ServiceFunction>,
-            _fallback: Option<ServiceFunction>,
-        ) {
-            todo!()
-        }
+    /// Checks that particles of a worker that was never created are rejected with an error
+    #[tokio::test]
+    async fn reject_unknown_worker() {
+        set_mock_time(real_time::now_ms());
 
-        async fn remove(&self, _service: &str) {
-            todo!()
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let worker_id = WorkerId::from(RandomPeerId::random());
+        plumber.create_worker_pool(worker_id, 1);
+
+        // manager can reach inactive workers, so the particle gets as far as actor creation
+        let particle = signed_particle(&env.management_key_pair, now_ms(), 10000);
+        let outcome = plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+
+        assert_eq!(outcome, IngestOutcome::Rejected(RejectReason::NoSuchWorker));
+        assert!(plumber
+            .worker_actors
+            .get(&worker_id)
+            .map_or(true, |actors| actors.is_empty()));
+        match plumber.poll(&mut context()) {
+            std::task::Poll::Ready(Err(NoSuchWorker {
+                worker_id: rejected_worker_id,
+                particle_id,
+            })) => {
+                assert_eq!(rejected_worker_id, worker_id.to_string());
+                assert_eq!(particle_id, particle.id);
+            }
+            unexpected => panic!(
+                "Expected Err(AquamarineApiError::NoSuchWorker), got {:?}",
+                unexpected
+            ),
         }
     }
 
-    struct VMMock;
+    /// Checks that any sender gets NoSuchWorker for a worker that was never created
+    #[tokio::test]
+    async fn reject_unknown_worker_from_any_peer() {
+        set_mock_time(real_time::now_ms());
 
-    #[async_trait]
-    impl AquaRuntime for VMMock {
-        type Config = ();
-        type Error = Infallible;
+        let (mut plumber, _env) = plumber_with_env(PlumberConfig::default()).await;
+        let worker_id = WorkerId::from(RandomPeerId::random());
+        let key_pair = KeyPair::generate_ed25519();
 
-        fn create_runtime(
-            _config: Self::Config,
-            _backend: WasmtimeWasmBackend,
-            _waker: Waker,
-        ) -> Result<Self, Self::Error> {
-            Ok(VMMock)
-        }
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        let outcome = plumber.ingest(
+            ExtendedParticle::new(particle.clone(), Span::none()),
+            None,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
 
-        fn into_effects(
-            _outcome: Result<RawAVMOutcome, Self::Error>,
-            _particle_id: String,
-        ) -> ParticleEffects {
-            ParticleEffects {
-                new_data: vec![],
-                next_peers: vec![],
-                call_requests: Default::default(),
+        assert_eq!(outcome, IngestOutcome::Rejected(RejectReason::NoSuchWorker));
+        match plumber.poll(&mut context()) {
+            std::task::Poll::Ready(Err(NoSuchWorker {
+                worker_id: rejected_worker_id,
+                particle_id,
+            })) => {
+                assert_eq!(rejected_worker_id, worker_id.to_string());
+                assert_eq!(particle_id, particle.id);
             }
+            unexpected => panic!(
+                "Expected Err(AquamarineApiError::NoSuchWorker), got {:?}",
+                unexpected
+            ),
         }
+    }
 
-        async fn call(
-            &mut self,
-            _air: impl Into<String> + Send,
-            _prev_data: impl Into<Vec<u8>> + Send,
-            _current_data: impl Into<Vec<u8>> + Send,
-            _particle_params: ParticleParameters<'_>,
-            _call_results: CallResults,
-            _key_pair: &KeyPair,
-        ) -> Result<RawAVMOutcome, Self::Error> {
-            let soft_limits_triggering = <_>::default();
-            Ok(RawAVMOutcome {
-                ret_code: 0,
-                error_message: "".to_string(),
-                data: vec![],
-                call_requests: Default::default(),
-                next_peer_pks: vec![],
-                soft_limits_triggering,
-            })
-        }
+    /// Checks that queued worker pools are created a few per poll
+    #[tokio::test]
+    async fn queue_worker_pools() {
+        set_mock_time(real_time::now_ms());
 
-        fn memory_stats(&self) -> AVMMemoryStats {
-            AVMMemoryStats {
-                memory_size: 0,
-                total_memory_limit: None,
-                allocation_rejects: None,
-            }
+        let config = PlumberConfig {
+            worker_pools_per_poll: Some(2),
+            ..<_>::default()
+        };
+        let (mut plumber, _env) = plumber_with_env(config).await;
+        for _ in 0..5 {
+            plumber.create_worker_pool(WorkerId::from(RandomPeerId::random()), 1);
         }
+        assert!(plumber.worker_vm_pools.is_empty());
+        assert_eq!(plumber.queued_worker_pools(), 5);
+
+        let _ = plumber.poll(&mut context());
+        assert_eq!(plumber.worker_vm_pools.len(), 2);
+        assert_eq!(plumber.queued_worker_pools(), 3);
+
+        let _ = plumber.poll(&mut context());
+        let _ = plumber.poll(&mut context());
+        assert_eq!(plumber.worker_vm_pools.len(), 5);
+        assert_eq!(plumber.queued_worker_pools(), 0);
     }
 
-    async fn plumber() -> Plumber<VMMock, Arc<MockF>> {
-        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
-        let avm_wasm_backend =
-            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
-        // Pool is of size 1 so it's easier to control tests
-        let vm_pool = VmPool::new(1, (), None, None, avm_wasm_backend.clone());
-        let builtin_mock = Arc::new(MockF);
+    /// Checks that worker pool becomes ready once all its VMs are created
+    #[tokio::test]
+    async fn worker_pool_ready() {
+        set_mock_time(real_time::now_ms());
 
-        let root_key_pair: KeyPair = KeyPair::generate_ed25519();
-        let key_pair_path: PathBuf = "keypair".into();
-        let workers_path: PathBuf = "workers".into();
-        let key_storage = KeyStorage::from_path(key_pair_path.clone(), root_key_pair.clone())
-            .await
-            .expect("Could not load key storage");
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        assert!(!plumber.worker_pool_ready(worker_id));
 
-        let key_storage = Arc::new(key_storage);
+        // VMs are created in the background starting from the first poll
+        plumber.create_worker_pool(worker_id, 2);
+        assert!(!plumber.worker_pool_ready(worker_id));
 
-        let core_manager = Arc::new(DummyCoreManager::default().into());
+        for _ in 0..100 {
+            if plumber.worker_pool_ready(worker_id) {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(plumber.worker_pool_ready(worker_id));
 
-        let scope = PeerScopes::new(
-            root_key_pair.get_peer_id(),
-            RandomPeerId::random(),
-            RandomPeerId::random(),
-            key_storage.clone(),
-        );
+        plumber.remove_worker_pool(worker_id);
+        assert!(!plumber.worker_pool_ready(worker_id));
+    }
 
-        let (workers, _receiver) =
-            Workers::from_path(workers_path.clone(), key_storage.clone(), core_manager, 128)
-                .await
-                .expect("Could not load worker registry");
+    /// Checks that idle worker pool is removed and created again when a particle arrives
+    #[tokio::test]
+    async fn shutdown_idle_worker_pool() {
+        set_mock_time(real_time::now_ms());
 
-        let workers = Arc::new(workers);
+        let (mut plumber, env) = plumber_with_env(PlumberConfig {
+            worker_pool_idle_timeout: Some(Duration::from_secs(1)),
+            ..<_>::default()
+        })
+        .await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
 
-        let tmp_dir = tempfile::tempdir().expect("Could not create temp dir");
-        let tmp_path = tmp_dir.path();
-        let data_store = ParticleDataStore::new(
-            tmp_path.join("particles"),
-            tmp_path.join("vault"),
-            tmp_path.join("anomaly"),
-        );
-        data_store
-            .initialize()
-            .await
-            .expect("Could not initialize datastore");
-        let data_store = Arc::new(data_store);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert!(plumber.worker_vm_pools.contains_key(&worker_id));
 
-        Plumber::new(
-            (),
-            vm_pool,
-            data_store,
-            builtin_mock,
+        set_mock_time(now_ms() + 2000);
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert!(!plumber.worker_vm_pools.contains_key(&worker_id));
+
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
+        let outcome = plumber.ingest(
+            ExtendedParticle::new(particle, Span::none()),
             None,
-            workers.clone(),
-            key_storage.clone(),
-            scope.clone(),
-            avm_wasm_backend,
-        )
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
+        );
+        assert_eq!(outcome, IngestOutcome::Accepted);
+        assert!(plumber.worker_vm_pools.contains_key(&worker_id));
     }
 
-    fn particle(ts: u64, ttl: u32) -> Particle {
-        let mut particle = Particle::default();
-        particle.timestamp = ts;
-        particle.ttl = ttl;
+    /// Checks that cancelling actors of one init peer leaves actors of the others intact
+    #[tokio::test]
+    async fn cancel_by_init_peer() {
+        set_mock_time(real_time::now_ms());
 
-        particle
-    }
+        let (mut plumber, env) = plumber_with_env(PlumberConfig::default()).await;
+        let abuser = KeyPair::generate_ed25519();
+        let client = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&client).await;
+        plumber.create_worker_pool(worker_id, 1);
 
-    fn context() -> Context<'static> {
-        Context::from_waker(noop_waker_ref())
+        for (key_pair, peer_scope) in [
+            (&abuser, PeerScope::Host),
+            (&abuser, PeerScope::WorkerId(worker_id)),
+            (&client, PeerScope::Host),
+            (&client, PeerScope::WorkerId(worker_id)),
+        ] {
+            let particle = signed_particle(key_pair, now_ms(), 10000);
+            plumber.ingest(
+                ExtendedParticle::new(particle, Span::none()),
+                None,
+                peer_scope,
+                ParticleOrigin::Network,
+            );
+        }
+        assert_eq!(plumber.host_actors.len(), 2);
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(|a| a.len()),
+            Some(2)
+        );
+
+        let cancelled = plumber.cancel_by_init_peer(abuser.get_peer_id());
+
+        assert_eq!(cancelled, 2);
+        assert_eq!(plumber.pending_cleanup_keys.len(), 2);
+        let client_peer_id = client.get_peer_id();
+        let host_actors: Vec<_> = plumber.host_actors.values().collect();
+        assert_eq!(host_actors.len(), 1);
+        assert_eq!(host_actors[0].init_peer_id(), client_peer_id);
+        let worker_actors: Vec<_> = plumber.worker_actors[&worker_id].values().collect();
+        assert_eq!(worker_actors.len(), 1);
+        assert_eq!(worker_actors[0].init_peer_id(), client_peer_id);
     }
 
-    /// Checks that expired actor will be removed
-    #[ignore]
+    /// Checks that particle is dropped when the worker runtime doesn't appear in time
     #[tokio::test]
-    async fn remove_expired() {
+    async fn drop_after_worker_runtime_wait() {
         set_mock_time(real_time::now_ms());
 
-        let mut plumber = plumber().await;
-
-        let particle = particle(now_ms(), 1);
-        let deadline = Deadline::from(&particle);
-        assert!(!deadline.is_expired(now_ms()));
+        let config = PlumberConfig {
+            worker_runtime_wait: Duration::from_secs(1),
+            ..<_>::default()
+        };
+        let (mut plumber, env) = plumber_with_env(config).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        hide_runtime_handle(worker_id);
 
+        let particle = signed_particle(&key_pair, now_ms(), 10000);
         plumber.ingest(
-            ExtendedParticle::new(particle, Span::none()),
+            ExtendedParticle::new(particle.clone(), Span::none()),
             None,
-            PeerScope::Host,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
         );
+        assert_eq!(plumber.deferred.len(), 1);
 
-        assert_eq!(plumber.host_actors.len(), 1);
-        let mut cx = context();
-        assert!(plumber.poll(&mut cx).is_pending());
-        assert_eq!(plumber.host_actors.len(), 1);
-
-        assert_eq!(plumber.host_vm_pool.free_vms(), 0);
-        // pool is single VM, wait until VM is free
-        loop {
-            if plumber.host_vm_pool.free_vms() == 1 {
-                break;
-            };
-            // 'is_pending' is used to suppress "must use" warning
-            plumber.poll(&mut cx).is_pending();
+        set_mock_time(now_ms() + 1001);
+        match plumber.poll(&mut context()) {
+            std::task::Poll::Ready(Err(WorkerRuntimeNotFound {
+                worker_id: rejected_worker_id,
+                particle_id,
+            })) => {
+                assert_eq!(rejected_worker_id, worker_id.to_string());
+                assert_eq!(particle_id, particle.id);
+            }
+            unexpected => panic!(
+                "Expected Err(AquamarineApiError::WorkerRuntimeNotFound), got {:?}",
+                unexpected
+            ),
         }
+        assert_eq!(plumber.deferred.len(), 0);
+        assert!(plumber.worker_actors.get(&worker_id).is_none());
 
-        set_mock_time(now_ms() + 2);
-        assert!(plumber.poll(&mut cx).is_pending());
-        assert_eq!(plumber.host_actors.len(), 0);
+        reveal_runtime_handle(worker_id);
     }
 
-    /// Checks that expired particle won't create an actor
+    /// Checks that deferred particle expired while waiting isn't executed once the runtime appears
     #[tokio::test]
-    async fn ignore_expired() {
+    async fn drop_expired_deferred() {
         set_mock_time(real_time::now_ms());
-        // set_mock_time(1000);
 
-        let mut plumber = plumber().await;
-        let particle = particle(now_ms() - 100, 99);
-        let deadline = Deadline::from(&particle);
-        assert!(deadline.is_expired(now_ms()));
+        let config = PlumberConfig {
+            worker_runtime_wait: Duration::from_secs(10),
+            ..<_>::default()
+        };
+        let (mut plumber, env) = plumber_with_env(config).await;
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        plumber.create_worker_pool(worker_id, 1);
+        hide_runtime_handle(worker_id);
 
+        let particle = signed_particle(&key_pair, now_ms(), 1000);
         plumber.ingest(
             ExtendedParticle::new(particle.clone(), Span::none()),
             None,
-            PeerScope::Host,
+            PeerScope::WorkerId(worker_id),
+            ParticleOrigin::Network,
         );
+        assert_eq!(plumber.deferred.len(), 1);
 
-        assert_eq!(plumber.host_actors.len(), 0);
-
-        // Check actor doesn't appear after poll somehow
-        set_mock_time(now_ms() + 1000);
-        let poll = plumber.poll(&mut context());
-        assert!(poll.is_ready());
-        match poll {
+        set_mock_time(now_ms() + 1001);
+        reveal_runtime_handle(worker_id);
+        match plumber.poll(&mut context()) {
             std::task::Poll::Ready(Err(ParticleExpired { particle_id })) => {
                 assert_eq!(particle_id, particle.id)
             }
             unexpected => panic!(
-                "Expected Poll::Ready(Err(AquamarineApiError::ParticleExpired)), got {:?}",
+                "Expected Err(AquamarineApiError::ParticleExpired), got {:?}",
                 unexpected
             ),
         }
-        assert_eq!(plumber.host_actors.len(), 0);
+        assert_eq!(plumber.deferred.len(), 0);
+        assert!(plumber.worker_actors.get(&worker_id).is_none());
+    }
+
+    /// Checks that the plumber wakes up to retry deferred particles on its own,
+    /// so they are ingested or rejected without any other particle arriving
+    #[tokio::test]
+    async fn wake_up_for_deferred() {
+        set_mock_time(real_time::now_ms());
+
+        struct CountingWaker(AtomicUsize);
+        impl futures::task::ArcWake for CountingWaker {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let config = PlumberConfig {
+            worker_runtime_wait: Duration::from_secs(1),
+            ..<_>::default()
+        };
+        let (mut plumber, env) = plumber_with_env(config).await;
+        // wait until the host VM is created, so the pool doesn't wake the plumber anymore
+        for _ in 0..100 {
+            if plumber.host_vm_pool.free_vms() == 1 {
+                break;
+            }
+            assert!(plumber.poll(&mut context()).is_pending());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(plumber.host_vm_pool.free_vms(), 1);
+
+        let key_pair = KeyPair::generate_ed25519();
+        let worker_id = env.create_worker(&key_pair).await;
+        hide_runtime_handle(worker_id);
+        let ingest = |plumber: &mut Plumber<VMMock, Arc<MockF>>, ts| {
+            let particle = signed_particle(&key_pair, ts, 10000);
+            plumber.ingest(
+                ExtendedParticle::new(particle.clone(), Span::none()),
+                None,
+                PeerScope::WorkerId(worker_id),
+                ParticleOrigin::Network,
+            );
+            particle
+        };
+        let wait_for_wake_up = |counter: &Arc<CountingWaker>| {
+            let counter = counter.clone();
+            async move {
+                for _ in 0..100 {
+                    if counter.0.load(Ordering::SeqCst) > 0 {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                assert!(counter.0.load(Ordering::SeqCst) > 0);
+            }
+        };
+
+        // the runtime appears while the particle waits
+        ingest(&mut plumber, now_ms());
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = futures::task::waker(counter.clone());
+        assert!(plumber.poll(&mut Context::from_waker(&waker)).is_pending());
+        assert_eq!(plumber.deferred.len(), 1);
+        reveal_runtime_handle(worker_id);
+        wait_for_wake_up(&counter).await;
+        assert!(plumber.poll(&mut context()).is_pending());
+        assert_eq!(plumber.deferred.len(), 0);
+        assert_eq!(
+            plumber.worker_actors.get(&worker_id).map(HashMap::len),
+            Some(1)
+        );
+
+        // the runtime doesn't appear in time
+        hide_runtime_handle(worker_id);
+        let particle = ingest(&mut plumber, now_ms() + 1);
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = futures::task::waker(counter.clone());
+        assert!(plumber.poll(&mut Context::from_waker(&waker)).is_pending());
+        assert_eq!(plumber.deferred.len(), 1);
+        set_mock_time(now_ms() + 1001);
+        wait_for_wake_up(&counter).await;
+        match plumber.poll(&mut context()) {
+            std::task::Poll::Ready(Err(WorkerRuntimeNotFound { particle_id, .. })) => {
+                assert_eq!(particle_id, particle.id)
+            }
+            unexpected => panic!(
+                "Expected Err(AquamarineApiError::WorkerRuntimeNotFound), got {:?}",
+                unexpected
+            ),
+        }
+        assert_eq!(plumber.deferred.len(), 0);
+
+        reveal_runtime_handle(worker_id);
     }
 }
 
@@ -920,3 +6324,35 @@ pub mod mock_time {
         MOCK_TIME.with(|cell| *cell.borrow_mut() = time);
     }
 }
+
+/// Allows hiding worker runtime handles in tests, the same way `mock_time` mocks the clock
+#[cfg(test)]
+pub mod mock_runtime {
+    #![allow(dead_code)]
+
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+
+    use tokio::runtime::Handle;
+    use types::peer_scope::WorkerId;
+    use workers::Workers;
+
+    thread_local! {
+        static HIDDEN_RUNTIMES: RefCell<HashSet<WorkerId>> = RefCell::new(HashSet::new());
+    }
+
+    pub fn get_runtime_handle(workers: &Workers, worker_id: WorkerId) -> Option<Handle> {
+        if HIDDEN_RUNTIMES.with(|cell| cell.borrow().contains(&worker_id)) {
+            return None;
+        }
+        workers.get_runtime_handle(worker_id)
+    }
+
+    pub fn hide_runtime_handle(worker_id: WorkerId) {
+        HIDDEN_RUNTIMES.with(|cell| cell.borrow_mut().insert(worker_id));
+    }
+
+    pub fn reveal_runtime_handle(worker_id: WorkerId) {
+        HIDDEN_RUNTIMES.with(|cell| cell.borrow_mut().remove(&worker_id));
+    }
+}
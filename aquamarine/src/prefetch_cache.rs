@@ -0,0 +1,136 @@
+/*
+ * Copyright 2024 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Prefetched {
+    data: Vec<u8>,
+    tick: u64,
+    loaded_at: Instant,
+}
+
+/// Particle data loaded ahead of execution, bounded both in size and in age.
+/// Each entry is served once: the execution it was loaded for stores new data right after,
+/// so keeping it any longer would only waste memory.
+#[derive(Debug)]
+pub(crate) struct PrefetchCache {
+    capacity: usize,
+    ttl: Duration,
+    tick: u64,
+    entries: HashMap<PathBuf, Prefetched>,
+    /// Paths by the tick they were loaded at, so the oldest are evicted first
+    order: BTreeMap<u64, PathBuf>,
+}
+
+impl PrefetchCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Remembers loaded data, evicting expired entries and the oldest one if full
+    pub fn insert(&mut self, path: PathBuf, data: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.evict_expired();
+
+        self.tick += 1;
+        let prefetched = Prefetched {
+            data,
+            tick: self.tick,
+            loaded_at: Instant::now(),
+        };
+        if let Some(previous) = self.entries.insert(path.clone(), prefetched) {
+            self.order.remove(&previous.tick);
+        }
+        self.order.insert(self.tick, path);
+
+        if self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Takes the data out of the cache unless it's expired
+    pub fn take(&mut self, path: &Path) -> Option<Vec<u8>> {
+        let prefetched = self.entries.remove(path)?;
+        self.order.remove(&prefetched.tick);
+        (prefetched.loaded_at.elapsed() < self.ttl).then_some(prefetched.data)
+    }
+
+    fn evict_expired(&mut self) {
+        loop {
+            let is_expired = match self.order.first_key_value() {
+                Some((_, oldest)) => self.entries.get(oldest).map_or(true, |prefetched| {
+                    prefetched.loaded_at.elapsed() >= self.ttl
+                }),
+                None => break,
+            };
+            if !is_expired {
+                break;
+            }
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        PathBuf::from(name)
+    }
+
+    #[test]
+    fn evict_oldest() {
+        let mut cache = PrefetchCache::new(2, Duration::from_secs(60));
+        cache.insert(path("a"), vec![1]);
+        cache.insert(path("b"), vec![2]);
+        cache.insert(path("c"), vec![3]);
+
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.take(&path("a")), None);
+        assert_eq!(cache.take(&path("b")), Some(vec![2]));
+        assert_eq!(cache.take(&path("c")), Some(vec![3]));
+        assert_eq!(cache.entries.len(), 0);
+    }
+
+    #[test]
+    fn expire_entries() {
+        let mut cache = PrefetchCache::new(2, Duration::ZERO);
+        cache.insert(path("a"), vec![1]);
+        assert_eq!(cache.take(&path("a")), None);
+
+        cache.insert(path("b"), vec![2]);
+        cache.insert(path("c"), vec![3]);
+        // expired entries are dropped on insert, so they don't hold memory
+        assert_eq!(cache.entries.len(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ * Copyright 2024 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use fluence_libp2p::PeerId;
+use particle_protocol::Particle;
+
+/// Signature together with everything it signs, so a cached signature
+/// can't be reused for a particle with a different content
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+struct SignedContent {
+    signature: Vec<u8>,
+    init_peer_id: PeerId,
+    id: String,
+    timestamp: u64,
+    ttl: u32,
+    script: String,
+}
+
+impl SignedContent {
+    fn new(particle: &Particle) -> Self {
+        Self {
+            signature: particle.signature.clone(),
+            init_peer_id: particle.init_peer_id,
+            id: particle.id.clone(),
+            timestamp: particle.timestamp,
+            ttl: particle.ttl,
+            script: particle.script.clone(),
+        }
+    }
+}
+
+/// LRU cache of the particles with already verified signatures
+pub(crate) struct SignatureCache {
+    capacity: usize,
+    tick: u64,
+    /// Verified content with the tick it was last used at
+    entries: HashMap<Arc<SignedContent>, u64>,
+    lru: BTreeMap<u64, Arc<SignedContent>>,
+}
+
+impl SignatureCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+        }
+    }
+
+    /// Checks whether particle's signature was verified before, marking it as recently used
+    pub fn touch(&mut self, particle: &Particle) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+
+        let content = SignedContent::new(particle);
+        match self.entries.get_mut(&content) {
+            Some(last_used) => {
+                self.tick += 1;
+                if let Some(key) = self.lru.remove(last_used) {
+                    self.lru.insert(self.tick, key);
+                }
+                *last_used = self.tick;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remembers particle with a verified signature, evicting the least recently used one if full
+    pub fn insert(&mut self, particle: &Particle) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.tick += 1;
+        let key = Arc::new(SignedContent::new(particle));
+        if let Some(last_used) = self.entries.insert(key.clone(), self.tick) {
+            self.lru.remove(&last_used);
+        }
+        self.lru.insert(self.tick, key);
+
+        if self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.lru.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(id: &str) -> Particle {
+        Particle {
+            id: id.to_string(),
+            signature: vec![1, 2, 3],
+            ..<_>::default()
+        }
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut cache = SignatureCache::new(2);
+        cache.insert(&particle("a"));
+        cache.insert(&particle("b"));
+
+        assert!(cache.touch(&particle("a")));
+        cache.insert(&particle("c"));
+
+        assert!(cache.touch(&particle("a")));
+        assert!(!cache.touch(&particle("b")));
+        assert!(cache.touch(&particle("c")));
+    }
+
+    #[test]
+    fn same_signature_different_content() {
+        let mut cache = SignatureCache::new(2);
+        let verified = particle("a");
+        cache.insert(&verified);
+
+        let forged = Particle {
+            script: "(null)".to_string(),
+            ..verified
+        };
+        assert!(!cache.touch(&forged));
+    }
+}
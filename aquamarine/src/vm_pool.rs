@@ -17,6 +17,7 @@
 use std::error::Error;
 use std::fmt::Debug;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use futures::future::BoxFuture;
 use futures::FutureExt;
@@ -24,13 +25,25 @@ use marine_wasmtime_backend::WasmtimeWasmBackend;
 use tokio::task::JoinError;
 
 use health::HealthCheckRegistry;
-use peer_metrics::VmPoolMetrics;
+use peer_metrics::{VmPoolMetrics, WorkerLabel};
 
+use crate::config::VmRecreationPolicy;
 use crate::health::VMPoolHealth;
 use crate::AquaRuntime;
 
 type RuntimeF<RT> = BoxFuture<'static, Result<RT, CreateAVMError>>;
 
+/// Settings `VmPool` was created with
+#[derive(Debug, Clone)]
+pub struct VmPoolConfigSummary<C> {
+    pub pool_size: usize,
+    /// Identity of the pool its metrics are published under
+    pub label: WorkerLabel,
+    /// Config every VM of the pool is created with, including its memory limits
+    pub runtime_config: C,
+    pub recreation_policy: VmRecreationPolicy,
+}
+
 #[derive(Debug)]
 enum CreateAVMError {
     AVMError(Box<dyn Error + Send + Sync + 'static>),
@@ -49,11 +62,31 @@ enum CreateAVMError {
 pub struct VmPool<RT: AquaRuntime> {
     runtimes: Vec<Option<RT>>,
     creating_runtimes: Option<Vec<(usize, RuntimeF<RT>)>>,
+    /// Ids of VMs that were lost or worn out, they are recreated on `poll`
+    lost_runtimes: Vec<usize>,
+    /// VMs busy with calls abandoned on timeout, their slots are kept until the calls return
+    reclaiming_runtimes: Vec<(usize, BoxFuture<'static, Option<RT>>)>,
+    /// Number of executions of each VM since it was created
+    vm_executions: Vec<usize>,
+    /// When each VM was created, `None` until it's created
+    vm_created_at: Vec<Option<Instant>>,
     runtime_config: RT::Config,
     pool_size: usize,
     metrics: Option<VmPoolMetrics>,
+    /// Identity of the pool its metrics are published under
+    label: WorkerLabel,
     health: Option<VMPoolHealth>,
     wasm_backend: WasmtimeWasmBackend,
+    recreation_policy: VmRecreationPolicy,
+    last_recreation: Option<Instant>,
+    /// Number of recreations in a row each happened within `rapid_window` after the previous one
+    rapid_recreations: usize,
+    /// Set when VMs are lost too often, lost VMs aren't recreated until the pool recovers
+    degraded_at: Option<Instant>,
+    /// Ids of VMs lost while the pool is degraded, recreated once it recovers
+    parked_runtimes: Vec<usize>,
+    /// Id of the VM degraded pool tries to recreate to check whether it can recover
+    probe: Option<usize>,
 }
 
 impl<RT: AquaRuntime> VmPool<RT> {
@@ -62,8 +95,10 @@ impl<RT: AquaRuntime> VmPool<RT> {
         pool_size: usize,
         runtime_config: RT::Config,
         metrics: Option<VmPoolMetrics>,
+        label: WorkerLabel,
         health_registry: Option<&mut HealthCheckRegistry>,
         wasm_backend: WasmtimeWasmBackend,
+        recreation_policy: VmRecreationPolicy,
     ) -> Self {
         let health = health_registry.map(|registry| {
             let health = VMPoolHealth::new(pool_size);
@@ -74,25 +109,51 @@ impl<RT: AquaRuntime> VmPool<RT> {
         let mut this = Self {
             runtimes: (0..pool_size).map(|_| None).collect(),
             creating_runtimes: None,
+            lost_runtimes: vec![],
+            reclaiming_runtimes: vec![],
+            vm_executions: vec![0; pool_size],
+            vm_created_at: vec![None; pool_size],
             runtime_config,
             pool_size,
             metrics,
+            label,
             health,
             wasm_backend,
+            recreation_policy,
+            last_recreation: None,
+            rapid_recreations: 0,
+            degraded_at: None,
+            parked_runtimes: vec![],
+            probe: None,
         };
 
-        this.meter(|m| m.set_pool_size(pool_size));
+        this.meter(|m, label| m.set_pool_size(label, pool_size));
 
         this
     }
 
-    fn meter<U, FF: Fn(&mut VmPoolMetrics) -> U>(&mut self, f: FF) {
-        self.metrics.as_mut().map(f);
+    fn meter<U, FF: Fn(&mut VmPoolMetrics, &WorkerLabel) -> U>(&mut self, f: FF) {
+        if let Some(metrics) = self.metrics.as_mut() {
+            f(metrics, &self.label);
+        }
+    }
+
+    pub fn metrics(&self) -> Option<&VmPoolMetrics> {
+        self.metrics.as_ref()
+    }
+
+    pub fn pool_size(&self) -> usize {
+        self.pool_size
+    }
+
+    /// All VMs of the pool were created at least once
+    pub fn warmed_up(&self) -> bool {
+        self.vm_created_at.iter().all(Option::is_some)
     }
 
     /// Number of currently unused vms
     pub fn free_vms(&self) -> usize {
-        self.runtimes.len()
+        self.runtimes.iter().filter(|vm| vm.is_some()).count()
     }
 
     /// Takes VM from pool
@@ -103,13 +164,13 @@ impl<RT: AquaRuntime> VmPool<RT> {
             .find_map(|(idx, vm)| vm.take().map(|vm| (idx, vm)));
 
         let free_vms_count = self.runtimes.iter().filter(|vm| vm.is_some()).count();
-        self.meter(|m| {
-            m.get_vm.inc();
+        self.meter(|m, label| {
+            m.get_vm.get_or_create(label).inc();
 
             if vm.is_none() {
-                m.no_free_vm.inc();
+                m.no_free_vm.get_or_create(label).inc();
             }
-            m.free_vms.set(free_vms_count as i64);
+            m.free_vms.get_or_create(label).set(free_vms_count as i64);
         });
 
         vm
@@ -122,13 +183,20 @@ impl<RT: AquaRuntime> VmPool<RT> {
             "put_vm must never happen before get_vm"
         );
         let memory_stats = vm.memory_stats();
-        self.runtimes[id] = Some(vm);
+        self.vm_executions[id] += 1;
+        if self.is_worn_out(id, Instant::now()) {
+            tracing::debug!("AVM {} reached its max lifetime, recreating", id);
+            drop(vm);
+            self.lost_runtimes.push(id);
+        } else {
+            self.runtimes[id] = Some(vm);
+        }
 
         let free_vms_count = self.runtimes.iter().filter(|vm| vm.is_some()).count();
-        self.meter(|m| {
-            m.put_vm.inc();
-            m.free_vms.set(free_vms_count as i64);
-            m.measure_memory(id, memory_stats.memory_size as u64);
+        self.meter(|m, label| {
+            m.put_vm.get_or_create(label).inc();
+            m.free_vms.get_or_create(label).set(free_vms_count as i64);
+            m.measure_memory(label, id, memory_stats.memory_size as u64);
             // TODO: measure max memory
         });
     }
@@ -141,12 +209,197 @@ impl<RT: AquaRuntime> VmPool<RT> {
             return;
         }
 
-        let avm_f = self.create_avm(cx);
+        let backoff = match self.next_recreation_backoff(Instant::now()) {
+            Some(backoff) => backoff,
+            None => {
+                tracing::error!(
+                    "VM pool is degraded, lost AVM {} won't be recreated for now",
+                    id
+                );
+                self.parked_runtimes.push(id);
+                return;
+            }
+        };
+
+        let create_f = self.create_avm(cx);
+        let avm_f = async move {
+            tokio::time::sleep(backoff).await;
+            create_f.await
+        }
+        .boxed();
         if let Some(creating_vms) = self.creating_runtimes.as_mut() {
             creating_vms.push((id, avm_f))
         }
     }
 
+    /// Pool lost VMs too often and stopped recreating them until it recovers
+    pub fn is_degraded(&self) -> bool {
+        self.degraded_at.is_some()
+    }
+
+    pub fn recreation_policy(&self) -> &VmRecreationPolicy {
+        &self.recreation_policy
+    }
+
+    pub fn config_summary(&self) -> VmPoolConfigSummary<RT::Config> {
+        VmPoolConfigSummary {
+            pool_size: self.pool_size,
+            label: self.label.clone(),
+            runtime_config: self.runtime_config.clone(),
+            recreation_policy: self.recreation_policy.clone(),
+        }
+    }
+
+    /// Returns delay before the next recreation, or `None` if the pool became degraded
+    fn next_recreation_backoff(&mut self, now: Instant) -> Option<Duration> {
+        if self.is_degraded() {
+            return None;
+        }
+
+        let policy = &self.recreation_policy;
+        let is_rapid = self
+            .last_recreation
+            .map_or(false, |last| now.duration_since(last) < policy.rapid_window);
+        self.last_recreation = Some(now);
+        self.rapid_recreations = if is_rapid {
+            self.rapid_recreations + 1
+        } else {
+            0
+        };
+
+        if policy
+            .max_rapid_recreations
+            .is_some_and(|max| self.rapid_recreations >= max)
+        {
+            tracing::error!(
+                "AVMs were recreated {} times in a row, VM pool is marked degraded",
+                self.rapid_recreations
+            );
+            self.degraded_at = Some(now);
+            return None;
+        }
+
+        let factor = 1u32
+            .checked_shl(self.rapid_recreations as u32)
+            .unwrap_or(u32::MAX);
+        let backoff = policy
+            .initial_backoff
+            .saturating_mul(factor)
+            .min(policy.max_backoff);
+        Some(backoff)
+    }
+
+    /// Once the cool-down passes, degraded pool tries to recreate one of the lost VMs
+    fn probe_degraded(&mut self, now: Instant) {
+        let Some(degraded_at) = self.degraded_at else {
+            return;
+        };
+        if self.probe.is_some()
+            || now.duration_since(degraded_at) < self.recreation_policy.degraded_cooldown
+        {
+            return;
+        }
+        if let Some(id) = self.parked_runtimes.pop() {
+            tracing::info!("VM pool is degraded, trying to recreate AVM {}", id);
+            self.probe = Some(id);
+            self.lost_runtimes.push(id);
+        }
+    }
+
+    /// Probe VM was created, so lost VMs are recreated as usual again
+    fn recover(&mut self) {
+        tracing::info!(
+            "VM pool recovered, recreating {} lost AVMs",
+            self.parked_runtimes.len()
+        );
+        self.degraded_at = None;
+        self.probe = None;
+        self.rapid_recreations = 0;
+        self.last_recreation = None;
+        self.lost_runtimes.append(&mut self.parked_runtimes);
+    }
+
+    /// Keeps the slot of a VM that is still busy with an abandoned call, so no VM is created
+    /// in its place. The VM is put back once the call returns, or recreated if the call fails.
+    pub fn reclaim_vm(&mut self, id: usize, call: BoxFuture<'static, Option<RT>>) {
+        debug_assert!(
+            self.runtimes[id].is_none(),
+            "reclaim_vm must never happen before get_vm"
+        );
+        self.reclaiming_runtimes.push((id, call));
+    }
+
+    /// Puts back VMs whose abandoned calls have returned
+    fn poll_reclaiming(&mut self, cx: &mut Context<'_>) -> bool {
+        let mut reclaimed = vec![];
+        self.reclaiming_runtimes
+            .retain_mut(|(id, call)| match call.poll_unpin(cx) {
+                Poll::Ready(vm) => {
+                    reclaimed.push((*id, vm));
+                    false
+                }
+                Poll::Pending => true,
+            });
+
+        let wake = !reclaimed.is_empty();
+        for (id, vm) in reclaimed {
+            match vm {
+                Some(vm) => {
+                    tracing::debug!("Abandoned call of AVM {} returned, reusing it", id);
+                    self.put_vm(id, vm);
+                }
+                None => {
+                    tracing::warn!("Abandoned call of AVM {} failed, recreating it", id);
+                    self.lost_runtimes.push(id);
+                }
+            }
+        }
+        wake
+    }
+
+    /// Marks VM taken from the pool as lost because its holder was dropped, e.g. a cancelled actor.
+    /// The VM is recreated on the next `poll` without backoff.
+    pub fn release_lost_vm(&mut self, id: usize) {
+        if self.creating_runtimes.is_none() {
+            // all VMs will be created on the first poll anyway
+            return;
+        }
+        debug_assert!(
+            self.runtimes[id].is_none(),
+            "release_lost_vm must never happen before get_vm"
+        );
+        self.lost_runtimes.push(id);
+    }
+
+    /// Whether the VM has lived for too long according to the policy and should be recreated
+    fn is_worn_out(&self, id: usize, now: Instant) -> bool {
+        let policy = &self.recreation_policy;
+        let too_many_executions = policy
+            .max_vm_executions
+            .map_or(false, |max| self.vm_executions[id] >= max);
+        let too_old = match (policy.max_vm_age, self.vm_created_at[id]) {
+            (Some(max_age), Some(created_at)) => now.duration_since(created_at) >= max_age,
+            _ => false,
+        };
+        too_many_executions || too_old
+    }
+
+    /// Takes out idle VMs that have lived for too long, so they are recreated
+    fn retire_worn_out_vms(&mut self) {
+        if self.recreation_policy.max_vm_age.is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        for id in 0..self.pool_size {
+            if self.runtimes[id].is_some() && self.is_worn_out(id, now) {
+                tracing::debug!("Idle AVM {} reached its max age, recreating", id);
+                self.runtimes[id] = None;
+                self.lost_runtimes.push(id);
+            }
+        }
+    }
+
     fn create_avm(&self, cx: &Context<'_>) -> RuntimeF<RT> {
         let config = self.runtime_config.clone();
         let wasm_backend = self.wasm_backend.clone();
@@ -166,6 +419,21 @@ impl<RT: AquaRuntime> VmPool<RT> {
 
     /// Moves created VMs from `creating_vms` to `vms`
     pub fn poll(&mut self, cx: &mut Context<'_>) {
+        let reclaimed = self.poll_reclaiming(cx);
+
+        if self.creating_runtimes.is_some() {
+            self.retire_worn_out_vms();
+            self.probe_degraded(Instant::now());
+        }
+
+        for id in std::mem::take(&mut self.lost_runtimes) {
+            tracing::debug!("Recreating AVM {}", id);
+            let avm_f = self.create_avm(cx);
+            if let Some(creating_vms) = self.creating_runtimes.as_mut() {
+                creating_vms.push((id, avm_f))
+            }
+        }
+
         let creating_vms = match &mut self.creating_runtimes {
             None => {
                 tracing::debug!("Starting creation {} AVMs", self.pool_size);
@@ -179,7 +447,8 @@ impl<RT: AquaRuntime> VmPool<RT> {
             Some(ref mut vms) => vms,
         };
 
-        let mut wake = false;
+        let mut wake = reclaimed;
+        let mut recovered = false;
 
         let mut fut_index = 0;
         while fut_index < creating_vms.len() {
@@ -198,12 +467,21 @@ impl<RT: AquaRuntime> VmPool<RT> {
                 match vm {
                     Ok(vm) => {
                         vms[id] = Some(vm);
+                        self.vm_executions[id] = 0;
+                        self.vm_created_at[id] = Some(Instant::now());
                         if let Some(h) = self.health.as_ref() {
                             h.increment_count()
                         }
+                        recovered |= self.probe == Some(id);
                     }
                     Err(err) => {
-                        tracing::error!("Failed to create vm: {:?}", err)
+                        tracing::error!("Failed to create vm: {:?}", err);
+                        if self.probe == Some(id) {
+                            // still degraded, the next try is after another cool-down
+                            self.probe = None;
+                            self.degraded_at = Some(Instant::now());
+                            self.parked_runtimes.push(id);
+                        }
                     } // TODO: don't panic
                 }
 
@@ -212,8 +490,343 @@ impl<RT: AquaRuntime> VmPool<RT> {
             fut_index += 1;
         }
 
+        if recovered {
+            self.recover();
+        }
+
         if wake {
             cx.waker().wake_by_ref()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::task::{Context, Waker};
+    use std::time::{Duration, Instant};
+
+    use async_trait::async_trait;
+    use avm_server::avm_runner::RawAVMOutcome;
+    use avm_server::{AVMMemoryStats, CallResults, ParticleParameters};
+    use fluence_keypair::KeyPair;
+    use futures::task::noop_waker_ref;
+    use marine_wasmtime_backend::{WasmtimeConfig, WasmtimeWasmBackend};
+    use particle_services::WasmBackendConfig;
+    use peer_metrics::{VmPoolMetrics, WorkerLabel, WorkerType};
+    use prometheus_client::encoding::text::encode;
+    use prometheus_client::registry::Registry;
+
+    use crate::config::VmRecreationPolicy;
+    use crate::vm_pool::VmPool;
+    use crate::{AquaRuntime, ParticleEffects};
+
+    /// Runtime whose VM is always lost, so it's never actually called
+    struct LostVM;
+
+    #[async_trait]
+    impl AquaRuntime for LostVM {
+        type Config = ();
+        type Error = Infallible;
+
+        fn create_runtime(
+            _config: Self::Config,
+            _backend: WasmtimeWasmBackend,
+            _waker: Waker,
+        ) -> Result<Self, Self::Error> {
+            Ok(LostVM)
+        }
+
+        fn into_effects(
+            _outcome: Result<RawAVMOutcome, Self::Error>,
+            _particle_id: String,
+        ) -> ParticleEffects {
+            unreachable!("VM is always lost")
+        }
+
+        async fn call(
+            &mut self,
+            _air: impl Into<String> + Send,
+            _prev_data: impl Into<Vec<u8>> + Send,
+            _current_data: impl Into<Vec<u8>> + Send,
+            _particle_params: ParticleParameters<'_>,
+            _call_results: CallResults,
+            _key_pair: &KeyPair,
+        ) -> Result<RawAVMOutcome, Self::Error> {
+            unreachable!("VM is always lost")
+        }
+
+        fn memory_stats(&self) -> AVMMemoryStats {
+            AVMMemoryStats {
+                memory_size: 0,
+                total_memory_limit: None,
+                allocation_rejects: None,
+            }
+        }
+    }
+
+    fn host_label() -> WorkerLabel {
+        WorkerLabel::new(WorkerType::Host, "host".to_string())
+    }
+
+    #[test]
+    fn distinct_pool_labels() {
+        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
+        let wasm_backend =
+            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
+        let mut registry = Registry::default();
+        let metrics = VmPoolMetrics::new(&mut registry);
+
+        let worker_label = WorkerLabel::new(WorkerType::Worker, "worker".to_string());
+        let _host_pool: VmPool<LostVM> = VmPool::new(
+            1,
+            (),
+            Some(metrics.clone()),
+            host_label(),
+            None,
+            wasm_backend.clone(),
+            <_>::default(),
+        );
+        let _worker_pool: VmPool<LostVM> = VmPool::new(
+            2,
+            (),
+            Some(metrics.for_another_pool()),
+            worker_label,
+            None,
+            wasm_backend,
+            <_>::default(),
+        );
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry).expect("Could not encode metrics");
+        let mut pool_sizes: Vec<_> = encoded
+            .lines()
+            .filter(|line| line.starts_with("aqua_vm_pool_pool_size{"))
+            .collect();
+        pool_sizes.sort();
+
+        assert_eq!(
+            pool_sizes,
+            vec![
+                r#"aqua_vm_pool_pool_size{worker_type="Host",peer_id="host"} 1"#,
+                r#"aqua_vm_pool_pool_size{worker_type="Worker",peer_id="worker"} 2"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn distinct_pool_memory() {
+        let mut registry = Registry::default();
+        let mut host_metrics = VmPoolMetrics::new(&mut registry);
+        let mut worker_metrics = host_metrics.for_another_pool();
+
+        let worker_label = WorkerLabel::new(WorkerType::Worker, "worker".to_string());
+        host_metrics.set_pool_size(&host_label(), 1);
+        worker_metrics.set_pool_size(&worker_label, 1);
+        host_metrics.measure_memory(&host_label(), 0, 1024);
+        worker_metrics.measure_memory(&worker_label, 0, 4096);
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry).expect("Could not encode metrics");
+        let mut sums: Vec<_> = encoded
+            .lines()
+            .filter(|line| line.starts_with("aqua_vm_pool_vm_mem_histo_sum{"))
+            .collect();
+        sums.sort();
+
+        assert_eq!(
+            sums,
+            vec![
+                r#"aqua_vm_pool_vm_mem_histo_sum{worker_type="Host",peer_id="host"} 1024.0"#,
+                r#"aqua_vm_pool_vm_mem_histo_sum{worker_type="Worker",peer_id="worker"} 4096.0"#,
+            ]
+        );
+    }
+
+    /// Polls the pool until all its VMs are created
+    async fn wait_for_vms(pool: &mut VmPool<LostVM>) {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        for _ in 0..100 {
+            pool.poll(&mut cx);
+            if pool.free_vms() == pool.pool_size {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("VMs weren't created in time");
+    }
+
+    #[test]
+    fn config_summary() {
+        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
+        let wasm_backend =
+            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
+        let policy = VmRecreationPolicy {
+            max_vm_executions: Some(5),
+            max_vm_age: Some(Duration::from_secs(60)),
+            ..<_>::default()
+        };
+        let pool: VmPool<LostVM> =
+            VmPool::new(3, (), None, host_label(), None, wasm_backend, policy);
+
+        let summary = pool.config_summary();
+        assert_eq!(summary.pool_size, 3);
+        assert_eq!(summary.label, host_label());
+        assert_eq!(summary.recreation_policy.max_vm_executions, Some(5));
+        assert_eq!(
+            summary.recreation_policy.max_vm_age,
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[tokio::test]
+    async fn recreate_after_max_executions() {
+        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
+        let wasm_backend =
+            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
+        let policy = VmRecreationPolicy {
+            max_vm_executions: Some(2),
+            ..<_>::default()
+        };
+        let mut pool: VmPool<LostVM> =
+            VmPool::new(1, (), None, host_label(), None, wasm_backend, policy);
+        wait_for_vms(&mut pool).await;
+
+        let (id, vm) = pool.get_vm().expect("VM must be free");
+        pool.put_vm(id, vm);
+        assert_eq!(pool.free_vms(), 1);
+        assert_eq!(pool.vm_executions[id], 1);
+
+        let (id, vm) = pool.get_vm().expect("VM must be free");
+        pool.put_vm(id, vm);
+        // worn out VM isn't returned to the pool
+        assert_eq!(pool.free_vms(), 0);
+
+        wait_for_vms(&mut pool).await;
+        assert_eq!(pool.vm_executions[id], 0);
+        assert!(!pool.is_degraded());
+    }
+
+    #[test]
+    fn recreation_backoff() {
+        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
+        let wasm_backend =
+            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
+        let policy = VmRecreationPolicy {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            rapid_window: Duration::from_secs(60),
+            max_rapid_recreations: Some(4),
+            ..<_>::default()
+        };
+        let mut pool: VmPool<LostVM> =
+            VmPool::new(1, (), None, host_label(), None, wasm_backend, policy);
+
+        let now = Instant::now();
+        let backoffs: Vec<_> = (0..5)
+            .map(|i| pool.next_recreation_backoff(now + Duration::from_millis(i)))
+            .collect();
+
+        let expected = [10, 20, 40, 40]
+            .into_iter()
+            .map(|ms| Some(Duration::from_millis(ms)))
+            .chain([None])
+            .collect::<Vec<_>>();
+        assert_eq!(backoffs, expected);
+        assert!(pool.is_degraded());
+        // degraded pool doesn't recreate VMs on its own, only its probe does
+        assert_eq!(
+            pool.next_recreation_backoff(now + Duration::from_secs(3600)),
+            None
+        );
+    }
+
+    #[test]
+    fn no_degradation_by_default() {
+        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
+        let wasm_backend =
+            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
+        let mut pool: VmPool<LostVM> = VmPool::new(
+            1,
+            (),
+            None,
+            host_label(),
+            None,
+            wasm_backend,
+            <_>::default(),
+        );
+
+        let now = Instant::now();
+        for i in 0..100 {
+            let backoff = pool.next_recreation_backoff(now + Duration::from_millis(i));
+            assert!(backoff.is_some());
+        }
+        assert!(!pool.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn recover_after_cooldown() {
+        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
+        let wasm_backend =
+            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
+        let policy = VmRecreationPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            rapid_window: Duration::from_secs(60),
+            max_rapid_recreations: Some(1),
+            degraded_cooldown: Duration::from_millis(100),
+            ..<_>::default()
+        };
+        let mut pool: VmPool<LostVM> =
+            VmPool::new(1, (), None, host_label(), None, wasm_backend, policy);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        wait_for_vms(&mut pool).await;
+
+        let (id, vm) = pool.get_vm().expect("VM must be free");
+        drop(vm);
+        pool.recreate_avm(id, &cx);
+        wait_for_vms(&mut pool).await;
+
+        // the second rapid recreation degrades the pool
+        let (id, vm) = pool.get_vm().expect("VM must be free");
+        drop(vm);
+        pool.recreate_avm(id, &cx);
+        assert!(pool.is_degraded());
+        pool.poll(&mut cx);
+        assert_eq!(pool.free_vms(), 0);
+
+        // after the cool-down the lost VM is recreated and the pool is healthy again
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        wait_for_vms(&mut pool).await;
+        assert!(!pool.is_degraded());
+
+        let (id, vm) = pool.get_vm().expect("VM must be free");
+        drop(vm);
+        pool.recreate_avm(id, &cx);
+        assert!(!pool.is_degraded());
+    }
+
+    #[test]
+    fn recreation_backoff_resets() {
+        let avm_wasm_config: WasmtimeConfig = WasmBackendConfig::default().into();
+        let wasm_backend =
+            WasmtimeWasmBackend::new(avm_wasm_config).expect("Could not create wasm backend");
+        let policy = VmRecreationPolicy {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            rapid_window: Duration::from_secs(1),
+            max_rapid_recreations: Some(4),
+            ..<_>::default()
+        };
+        let mut pool: VmPool<LostVM> =
+            VmPool::new(1, (), None, host_label(), None, wasm_backend, policy);
+
+        let now = Instant::now();
+        pool.next_recreation_backoff(now);
+        pool.next_recreation_backoff(now + Duration::from_millis(1));
+        let backoff = pool.next_recreation_backoff(now + Duration::from_secs(10));
+
+        assert_eq!(backoff, Some(Duration::from_millis(10)));
+        assert!(!pool.is_degraded());
+    }
+}
@@ -40,7 +40,8 @@ use crate::connection_pool::LifecycleEvent;
 use crate::{Command, ConnectionPoolApi};
 use fluence_libp2p::remote_multiaddr;
 use particle_protocol::{
-    CompletionChannel, Contact, ExtendedParticle, HandlerMessage, ProtocolConfig, SendStatus,
+    CompletionChannel, Contact, ExtendedParticle, HandlerMessage, ProtocolConfig, ProtocolVersion,
+    SendStatus,
 };
 use peer_metrics::ConnectionPoolMetrics;
 
@@ -108,6 +109,8 @@ pub struct ConnectionPoolBehaviour {
     queue: VecDeque<ExtendedParticle>,
     contacts: HashMap<PeerId, Peer>,
     dialing: HashMap<Multiaddr, Vec<oneshot::Sender<Option<Contact>>>>,
+    /// Protocol version negotiated on each connection
+    protocol_versions: HashMap<ConnectionId, ProtocolVersion>,
 
     events: VecDeque<SwarmEventType>,
     waker: Option<Waker>,
@@ -297,6 +300,7 @@ impl ConnectionPoolBehaviour {
             queue: <_>::default(),
             contacts: <_>::default(),
             dialing: <_>::default(),
+            protocol_versions: <_>::default(),
             events: <_>::default(),
             waker: None,
             protocol_config,
@@ -371,6 +375,29 @@ impl ConnectionPoolBehaviour {
         }
     }
 
+    /// Protocol version last negotiated with the remote peer on that connection
+    pub fn protocol_version(&self, connection_id: ConnectionId) -> Option<ProtocolVersion> {
+        self.protocol_versions.get(&connection_id).copied()
+    }
+
+    fn record_protocol_version(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        version: ProtocolVersion,
+    ) {
+        if self.protocol_versions.insert(connection_id, version) != Some(version) {
+            log::debug!(
+                target: "network",
+                "{}: negotiated protocol {} with {} on {:?}",
+                self.peer_id,
+                version,
+                peer_id,
+                connection_id
+            );
+        }
+    }
+
     fn get_contact_impl(&self, peer_id: PeerId) -> Option<Contact> {
         self.contacts.get(&peer_id).map(|c| Contact {
             peer_id,
@@ -583,6 +610,7 @@ impl NetworkBehaviour for ConnectionPoolBehaviour {
                 }
             }
             FromSwarm::ConnectionClosed(event) => {
+                self.protocol_versions.remove(&event.connection_id);
                 self.on_connection_closed(
                     &event.peer_id,
                     event.endpoint,
@@ -616,11 +644,12 @@ impl NetworkBehaviour for ConnectionPoolBehaviour {
     fn on_connection_handler_event(
         &mut self,
         from: PeerId,
-        _connection_id: ConnectionId,
+        connection_id: ConnectionId,
         event: THandlerOutEvent<Self>,
     ) {
         match event {
-            Ok(HandlerMessage::InParticle(particle)) => {
+            Ok(HandlerMessage::InParticle(particle, version)) => {
+                self.record_protocol_version(from, connection_id, version);
                 tracing::info!(target: "network", particle_id = particle.id,"{}: received particle from {}; queue {}", self.peer_id, from, self.queue.len());
                 let root_span = tracing::info_span!("Particle", particle_id = particle.id);
 
@@ -636,6 +665,21 @@ impl NetworkBehaviour for ConnectionPoolBehaviour {
                 self.wake();
             }
             Ok(HandlerMessage::Upgrade) => {}
+            Ok(HandlerMessage::Negotiated(version)) => {
+                self.record_protocol_version(from, connection_id, version);
+            }
+            Ok(HandlerMessage::UnsupportedFormat { code, version }) => {
+                self.record_protocol_version(from, connection_id, version);
+                tracing::warn!(
+                    "{}: rejected message from {} with unsupported format {:#x}, {} expects {:#x}",
+                    self.peer_id,
+                    from,
+                    code,
+                    version,
+                    version.format_code()
+                );
+                self.meter(|m| m.unsupported_format_message());
+            }
             Ok(HandlerMessage::OutParticle(..)) => unreachable!("can't receive OutParticle"),
             Err(err) => log::warn!("Handler error: {:?}", err),
         }
@@ -0,0 +1,137 @@
+/*
+ * Copyright 2024 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::BTreeMap;
+
+use alloy_primitives::BlockNumber;
+use jsonrpsee::core::JsonValue;
+
+use crate::replay::{ChainEventKind, LogPosition};
+
+/// Holds events until their block is buried under `depth` blocks
+pub(crate) struct ConfirmationBuffer {
+    depth: u64,
+    pending: BTreeMap<LogPosition, (ChainEventKind, JsonValue)>,
+}
+
+impl ConfirmationBuffer {
+    pub fn new(depth: u64) -> Self {
+        Self {
+            depth,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the event back if it doesn't need to wait for confirmations
+    pub fn push(
+        &mut self,
+        kind: ChainEventKind,
+        event: JsonValue,
+    ) -> Option<(ChainEventKind, JsonValue)> {
+        if self.depth == 0 {
+            return Some((kind, event));
+        }
+        // Can't tell the block of the event, let the handlers deal with it
+        let Some(position) = LogPosition::from_json(&event) else {
+            return Some((kind, event));
+        };
+
+        let removed = event
+            .get("removed")
+            .and_then(JsonValue::as_bool)
+            .unwrap_or(false);
+        if removed {
+            if self.pending.remove(&position).is_some() {
+                tracing::info!(target: "chain-listener", "Discarded {kind:?} event at {position:?}: removed by reorg");
+            } else {
+                tracing::warn!(target: "chain-listener", "{kind:?} event at {position:?} was removed by reorg after it was confirmed");
+            }
+        } else {
+            self.pending.insert(position, (kind, event));
+        }
+
+        None
+    }
+
+    /// Takes the events that have enough confirmations at the `head` block, in chain order
+    pub fn confirmed(&mut self, head: BlockNumber) -> Vec<(ChainEventKind, JsonValue)> {
+        let Some(last_confirmed) = head.checked_sub(self.depth) else {
+            return vec![];
+        };
+        let unconfirmed = self.pending.split_off(&LogPosition {
+            block_number: last_confirmed.saturating_add(1),
+            log_index: 0,
+        });
+        let confirmed = std::mem::replace(&mut self.pending, unconfirmed);
+
+        confirmed.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(block_number: u64, log_index: u64, removed: bool) -> JsonValue {
+        json!({
+            "blockNumber": format!("{block_number:#x}"),
+            "logIndex": format!("{log_index:#x}"),
+            "removed": removed,
+        })
+    }
+
+    fn blocks(events: Vec<(ChainEventKind, JsonValue)>) -> Vec<u64> {
+        events
+            .iter()
+            .map(|(_, event)| LogPosition::from_json(event).unwrap().block_number)
+            .collect()
+    }
+
+    #[test]
+    fn emit_after_confirmations() {
+        let mut buffer = ConfirmationBuffer::new(2);
+
+        assert!(buffer
+            .push(ChainEventKind::UnitMatched, event(10, 0, false))
+            .is_none());
+        assert!(buffer
+            .push(ChainEventKind::UnitActivated, event(11, 0, false))
+            .is_none());
+        assert!(buffer
+            .push(ChainEventKind::UnitDeactivated, event(12, 0, false))
+            .is_none());
+
+        assert!(buffer.confirmed(11).is_empty());
+        assert_eq!(blocks(buffer.confirmed(12)), vec![10]);
+
+        // event at block 11 is reorged away
+        assert!(buffer
+            .push(ChainEventKind::UnitActivated, event(11, 0, true))
+            .is_none());
+        assert!(buffer.confirmed(13).is_empty());
+        assert_eq!(blocks(buffer.confirmed(14)), vec![12]);
+        assert!(buffer.confirmed(100).is_empty());
+    }
+
+    #[test]
+    fn no_confirmations() {
+        let mut buffer = ConfirmationBuffer::new(0);
+
+        let emitted = buffer.push(ChainEventKind::UnitMatched, event(10, 0, false));
+        assert!(emitted.is_some());
+        assert!(buffer.confirmed(10).is_empty());
+    }
+}
@@ -1,3 +1,7 @@
+use alloy_sol_types::SolEvent;
+use chain_data::{parse_log, Log};
+use jsonrpsee::core::JsonValue;
+
 pub mod cc_activated;
 mod compute_unit_matched;
 mod unit_activated;
@@ -6,3 +10,54 @@ mod unit_deactivated;
 pub use compute_unit_matched::ComputeUnitMatched;
 pub use unit_activated::UnitActivated;
 pub use unit_deactivated::UnitDeactivated;
+
+/// Decodes a raw log received from the chain as the event `E`
+pub(crate) fn decode_log<E: SolEvent>(event: &JsonValue) -> eyre::Result<E> {
+    let log = serde_json::from_value::<Log>(event.clone())?;
+    Ok(parse_log::<E>(log)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn unit_deactivated(unit_id: &str) -> JsonValue {
+        json!({
+            "data": "0x",
+            "blockNumber": "0x10",
+            "topics": [
+                UnitDeactivated::SIGNATURE_HASH.to_string(),
+                "0x91cfcc4a139573b08646960be31b278152ef3480710ab15d9b39262be37038a1",
+                unit_id,
+            ]
+        })
+    }
+
+    #[test]
+    fn decode_errors_are_isolated() {
+        let block = vec![
+            unit_deactivated("0xf3660ca1eaf461cbbb5e1d06ade6ba4a9a503c0d680ba825e09cddd3f9b45fc6"),
+            json!({ "data": "0x", "blockNumber": "0x10", "topics": ["0xdeadbeef"] }),
+            unit_deactivated("0x0000000000000000000000000000000000000000000000000000000000000001"),
+        ];
+
+        let (decoded, errors): (Vec<_>, Vec<_>) = block
+            .iter()
+            .map(decode_log::<UnitDeactivated>)
+            .partition(Result::is_ok);
+
+        assert_eq!(errors.len(), 1);
+        let unit_ids: Vec<_> = decoded
+            .into_iter()
+            .map(|event| event.unwrap().unitId.to_string())
+            .collect();
+        assert_eq!(
+            unit_ids,
+            vec![
+                "0xf3660ca1eaf461cbbb5e1d06ade6ba4a9a503c0d680ba825e09cddd3f9b45fc6",
+                "0x0000000000000000000000000000000000000000000000000000000000000001",
+            ]
+        );
+    }
+}
@@ -8,7 +8,9 @@ extern crate core;
 
 pub use listener::ChainListener;
 
+mod confirmation;
 mod event;
 mod listener;
 
 mod persistence;
+mod replay;
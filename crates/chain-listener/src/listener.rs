@@ -18,7 +18,7 @@ use ccp_shared::types::{Difficulty, GlobalNonce, LocalNonce, ResultHash};
 use cpu_utils::PhysicalCoreId;
 
 use eyre::{eyre, Report};
-use jsonrpsee::core::client::{Client as WsClient, Subscription, SubscriptionClientT};
+use jsonrpsee::core::client::{Client as WsClient, ClientT, Subscription, SubscriptionClientT};
 use jsonrpsee::core::params::ArrayParams;
 use jsonrpsee::core::{client, JsonValue};
 use jsonrpsee::rpc_params;
@@ -36,7 +36,7 @@ use chain_connector::{
     is_commitment_not_active, is_too_many_proofs, CCStatus, ChainConnector, CommitmentId,
     ConnectorError, Deal, PEER_NOT_EXISTS,
 };
-use chain_data::{parse_log, peer_id_to_hex, Log};
+use chain_data::peer_id_to_hex;
 use core_manager::errors::AcquireError;
 use core_manager::types::{AcquireRequest, Assignment, WorkType};
 use core_manager::{CoreManager, CoreManagerFunctions, CUID};
@@ -44,11 +44,15 @@ use peer_metrics::ChainListenerMetrics;
 use server_config::{ChainConfig, ChainListenerConfig};
 use types::DealId;
 
+use crate::confirmation::ConfirmationBuffer;
 use crate::event::cc_activated::CommitmentActivated;
-use crate::event::{ComputeUnitMatched, UnitActivated, UnitDeactivated};
-use crate::persistence;
+use crate::event::{decode_log, ComputeUnitMatched, UnitActivated, UnitDeactivated};
+use crate::persistence::ProofIdPersistence;
+use crate::replay::{replay_order, ChainEventKind, LogPosition, SeenLogs};
 
 const PROOF_POLL_LIMIT: usize = 50;
+/// How many processed logs are remembered to avoid acting on them twice
+const SEEN_LOGS_WINDOW: usize = 1024;
 
 pub struct ChainListener {
     config: ChainConfig,
@@ -87,7 +91,7 @@ pub struct ChainListener {
     /// Resets every epoch
     last_submitted_proof_id: ProofIdx,
     pending_proof_txs: Vec<(String, CUID)>,
-    persisted_proof_id_dir: PathBuf,
+    persistence: ProofIdPersistence,
 
     // TODO: move out to a separate struct, get rid of Option
     // Subscriptions that are polled when we have commitment
@@ -97,6 +101,9 @@ pub struct ChainListener {
     heads: Option<Subscription<JsonValue>>,
     commitment_activated: Option<Subscription<JsonValue>>,
     unit_matched: Option<Subscription<JsonValue>>,
+    seen_logs: SeenLogs,
+    // Events waiting for confirmations
+    confirmations: ConfirmationBuffer,
 
     metrics: Option<ChainListenerMetrics>,
 }
@@ -111,6 +118,15 @@ where
     }
 }
 
+async fn poll_timer(timer: &mut Option<IntervalStream>) {
+    match timer {
+        Some(ref mut timer) => {
+            timer.next().await;
+        }
+        None => pending().await,
+    }
+}
+
 impl ChainListener {
     pub fn new(
         chain_config: ChainConfig,
@@ -127,6 +143,10 @@ impl ChainListener {
             tracing::warn!(target: "chain-listener", "CCP client is not set, will submit mocked proofs");
         }
 
+        let persistence =
+            ProofIdPersistence::new(persisted_proof_id_dir, listener_config.persistence.clone());
+        let confirmations = ConfirmationBuffer::new(listener_config.confirmations);
+
         Self {
             chain_connector,
             ws_client,
@@ -147,13 +167,15 @@ impl ChainListener {
             ccp_client,
             last_submitted_proof_id: ProofIdx::zero(),
             pending_proof_txs: vec![],
-            persisted_proof_id_dir,
+            persistence,
             unit_activated: None,
             unit_deactivated: None,
             heads: None,
             commitment_activated: None,
             unit_matched: None,
             active_deals: BTreeMap::new(),
+            seen_logs: SeenLogs::new(SEEN_LOGS_WINDOW),
+            confirmations,
             metrics,
         }
     }
@@ -176,7 +198,6 @@ impl ChainListener {
         let result = tokio::task::Builder::new()
             .name("ChainListener")
             .spawn(async move {
-
                 if let Err(err) = self.set_utility_core().await {
                     tracing::error!(target: "chain-listener", "Failed to set utility core: {err}; Stopping...");
                     exit(1);
@@ -196,6 +217,11 @@ impl ChainListener {
 
                 tracing::info!(target: "chain-listener", "State successfully refreshed, starting main loop");
                 let mut timer = IntervalStream::new(interval(self.listener_config.proof_poll_period));
+                // Flushing is driven by the main loop, so it stops together with the listener
+                let mut flush_timer = self
+                    .persistence
+                    .flush_interval()
+                    .map(|period| IntervalStream::new(interval(period)));
 
                 loop {
                     tokio::select! {
@@ -205,29 +231,34 @@ impl ChainListener {
                             }
                         },
                         event = poll_subscription(&mut self.commitment_activated) => {
-                            if let Err(err) = self.process_commitment_activated(event).await {
+                            if let Err(err) = self.process_event(ChainEventKind::CommitmentActivated, event).await {
                                 self.handle_subscription_error("CommitmentActivated", err).await;
                             }
                         },
                         event = poll_subscription(&mut self.unit_activated) => {
                             if self.unit_activated.is_some() {
-                                if let Err(err) = self.process_unit_activated(event).await {
+                                if let Err(err) = self.process_event(ChainEventKind::UnitActivated, event).await {
                                     self.handle_subscription_error("UnitActivated", err).await;
                                 }
                             }
                         },
                         event = poll_subscription(&mut self.unit_deactivated) => {
                             if self.unit_deactivated.is_some() {
-                                 if let Err(err) = self.process_unit_deactivated(event).await {
+                                 if let Err(err) = self.process_event(ChainEventKind::UnitDeactivated, event).await {
                                     self.handle_subscription_error("UnitDeactivated", err).await;
                                 }
                             }
                         },
                         event = poll_subscription(&mut self.unit_matched) => {
-                            if let Err(err) = self.process_unit_matched(event) {
+                            if let Err(err) = self.process_event(ChainEventKind::UnitMatched, event).await {
                                 self.handle_subscription_error("ComputeUnitMatched", err).await;
                             }
                         },
+                        _ = poll_timer(&mut flush_timer) => {
+                            if let Err(err) = self.persistence.flush().await {
+                                tracing::warn!(target: "chain-listener", "Failed to fsync proof id: {err}");
+                            }
+                        },
                         _ = timer.next() => {
                             if self.ccp_client.is_some() {
                                 if let Err(err) = self.poll_proofs().await {
@@ -369,8 +400,7 @@ impl ChainListener {
         };
 
         let write = retry(backoff, || async {
-            persistence::persist_proof_id(
-                &self.persisted_proof_id_dir,
+            self.persistence.persist_proof_id(
                 self.last_submitted_proof_id,
                 self.current_epoch,
             ).await.map_err(|err|{
@@ -390,8 +420,7 @@ impl ChainListener {
     }
 
     async fn load_proof_id(&mut self) -> eyre::Result<()> {
-        let persisted_proof_id =
-            persistence::load_persisted_proof_id(&self.persisted_proof_id_dir).await?;
+        let persisted_proof_id = self.persistence.load_persisted_proof_id().await?;
 
         if let Some(persisted_proof_id) = persisted_proof_id {
             self.last_submitted_proof_id = persisted_proof_id.proof_id;
@@ -605,40 +634,134 @@ impl ChainListener {
         Ok(sub)
     }
 
-    fn cc_activated_params(&self) -> ArrayParams {
+    fn cc_activated_filter(&self) -> JsonValue {
         let topic = CommitmentActivated::SIGNATURE_HASH.to_string();
         let topics = vec![topic, peer_id_to_hex(self.host_id)];
-        rpc_params![
-            "logs",
-            json!({"address": self.config.cc_contract_address, "topics": topics})
-        ]
+        json!({"address": self.config.cc_contract_address, "topics": topics})
     }
 
-    fn unit_activated_params(&self, commitment_id: &CommitmentId) -> ArrayParams {
+    fn cc_activated_params(&self) -> ArrayParams {
+        rpc_params!["logs", self.cc_activated_filter()]
+    }
+
+    fn unit_activated_filter(&self, commitment_id: &CommitmentId) -> JsonValue {
         let topic = UnitActivated::SIGNATURE_HASH.to_string();
-        rpc_params![
-            "logs",
-            json!({"address": self.config.cc_contract_address, "topics":  vec![topic, hex::encode(commitment_id.0)]})
-        ]
+        json!({"address": self.config.cc_contract_address, "topics":  vec![topic, hex::encode(commitment_id.0)]})
     }
 
-    fn unit_deactivated_params(&self, commitment_id: &CommitmentId) -> ArrayParams {
+    fn unit_activated_params(&self, commitment_id: &CommitmentId) -> ArrayParams {
+        rpc_params!["logs", self.unit_activated_filter(commitment_id)]
+    }
+
+    fn unit_deactivated_filter(&self, commitment_id: &CommitmentId) -> JsonValue {
         let topic = UnitDeactivated::SIGNATURE_HASH.to_string();
-        rpc_params![
-            "logs",
-            json!({"address": self.config.cc_contract_address, "topics":  vec![topic, hex::encode(commitment_id.0)]})
-        ]
+        json!({"address": self.config.cc_contract_address, "topics":  vec![topic, hex::encode(commitment_id.0)]})
     }
 
-    fn unit_matched_params(&self) -> ArrayParams {
+    fn unit_deactivated_params(&self, commitment_id: &CommitmentId) -> ArrayParams {
+        rpc_params!["logs", self.unit_deactivated_filter(commitment_id)]
+    }
+
+    fn unit_matched_filter(&self) -> JsonValue {
         let topics = vec![
             ComputeUnitMatched::SIGNATURE_HASH.to_string(),
             peer_id_to_hex(self.host_id),
         ];
-        rpc_params![
-            "logs",
-            json!({"address": self.config.market_contract_address, "topics": topics})
-        ]
+        json!({"address": self.config.market_contract_address, "topics": topics})
+    }
+
+    fn unit_matched_params(&self) -> ArrayParams {
+        rpc_params!["logs", self.unit_matched_filter()]
+    }
+
+    /// Reprocess the events from `block` up to the head through the regular handlers.
+    /// Events that were recently processed are skipped.
+    pub async fn replay_from(&mut self, block: BlockNumber) -> eyre::Result<()> {
+        let mut filters = vec![
+            (
+                ChainEventKind::CommitmentActivated,
+                self.cc_activated_filter(),
+            ),
+            (ChainEventKind::UnitMatched, self.unit_matched_filter()),
+        ];
+        if let Some(commitment_id) = self.current_commitment.as_ref() {
+            filters.push((
+                ChainEventKind::UnitActivated,
+                self.unit_activated_filter(commitment_id),
+            ));
+            filters.push((
+                ChainEventKind::UnitDeactivated,
+                self.unit_deactivated_filter(commitment_id),
+            ));
+        }
+
+        let mut logs = vec![];
+        for (kind, mut filter) in filters {
+            filter["fromBlock"] = json!(format!("{block:#x}"));
+            filter["toBlock"] = json!("latest");
+            let fetched: Vec<JsonValue> = self
+                .ws_client
+                .request("eth_getLogs", rpc_params![filter])
+                .await?;
+            logs.extend(fetched.into_iter().map(|log| (kind, log)));
+        }
+
+        let logs = replay_order(logs, block, &self.seen_logs);
+        tracing::info!(target: "chain-listener", "Replaying {} events from block {block}", logs.len());
+
+        for (kind, log) in logs {
+            if let Some((kind, log)) = self.confirmations.push(kind, log) {
+                if let Err(err) = self.dispatch_event(kind, log).await {
+                    tracing::warn!(target: "chain-listener", "Failed to replay {kind:?} event: {err}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Passes the event to its handler once it has enough confirmations
+    async fn process_event(
+        &mut self,
+        kind: ChainEventKind,
+        event: Option<Result<JsonValue, client::Error>>,
+    ) -> eyre::Result<()> {
+        let event = event.ok_or(eyre!("Failed to process {kind:?} event: got None"))??;
+        if let Some((kind, event)) = self.confirmations.push(kind, event) {
+            self.dispatch_event(kind, event).await?;
+        }
+        Ok(())
+    }
+
+    async fn dispatch_event(&mut self, kind: ChainEventKind, event: JsonValue) -> eyre::Result<()> {
+        let event = Some(Ok(event));
+        match kind {
+            ChainEventKind::CommitmentActivated => self.process_commitment_activated(event).await,
+            ChainEventKind::UnitActivated => self.process_unit_activated(event).await,
+            ChainEventKind::UnitDeactivated => self.process_unit_deactivated(event).await,
+            ChainEventKind::UnitMatched => self.process_unit_matched(event),
+        }
+    }
+
+    /// Decodes the event. Logs that can't be decoded are reported and skipped,
+    /// so a single malformed log doesn't interrupt processing of the others
+    fn decode_event<E: SolEvent>(&self, event: &JsonValue) -> Option<E> {
+        match decode_log::<E>(event) {
+            Ok(decoded) => Some(decoded),
+            Err(err) => {
+                tracing::error!(target: "chain-listener", "Failed to decode {} event: {err}, data: {event}; Skipping", E::SIGNATURE);
+                self.observe(|m| m.observe_decode_error());
+                None
+            }
+        }
+    }
+
+    /// Remembers the log as processed, returns false if it was already processed
+    fn mark_seen(&mut self, event: &JsonValue) -> bool {
+        match LogPosition::from_json(event) {
+            Some(position) => self.seen_logs.insert(position),
+            None => true,
+        }
     }
 
     async fn process_new_header(
@@ -650,6 +773,12 @@ impl ChainListener {
         let (block_timestamp, block_number) = Self::parse_block_header(header?)?;
         self.observe(|m| m.observe_new_block(block_number));
 
+        for (kind, event) in self.confirmations.confirmed(block_number) {
+            if let Err(err) = self.dispatch_event(kind, event).await {
+                tracing::warn!(target: "chain-listener", "Failed to process confirmed {kind:?} event: {err}");
+            }
+        }
+
         // `epoch_number = 1 + (block_timestamp - init_timestamp) / epoch_duration`
         let epoch_number =
             U256::from(1) + (block_timestamp - self.init_timestamp) / self.epoch_duration;
@@ -696,12 +825,12 @@ impl ChainListener {
         let event = event.ok_or(eyre!(
             "Failed to process CommitmentActivated event: got None"
         ))??;
-        let log = serde_json::from_value::<Log>(event.clone()).map_err(|err| {
-            tracing::error!(target: "chain-listener", "Failed to parse CommitmentActivated event: {err}, data: {event}");
-            err
-        })?;
-
-        let cc_event = parse_log::<CommitmentActivated>(log)?;
+        if !self.mark_seen(&event) {
+            return Ok(());
+        }
+        let Some(cc_event) = self.decode_event::<CommitmentActivated>(&event) else {
+            return Ok(());
+        };
         let unit_ids = cc_event.unitIds;
         tracing::info!(target: "chain-listener",
             "Received CommitmentActivated event for commitment: {}, startEpoch: {}, unitIds: {:?}",
@@ -744,13 +873,13 @@ impl ChainListener {
         event: Option<Result<JsonValue, client::Error>>,
     ) -> eyre::Result<()> {
         let event = event.ok_or(eyre!("Failed to process UnitActivated event: got None"))??;
+        if !self.mark_seen(&event) {
+            return Ok(());
+        }
 
-        let log = serde_json::from_value::<Log>(event.clone()).map_err(|err| {
-            tracing::error!(target: "chain-listener", "Failed to parse UnitActivated event: {err}, data: {event}");
-            err
-        })?;
-
-        let unit_event = parse_log::<UnitActivated>(log)?;
+        let Some(unit_event) = self.decode_event::<UnitActivated>(&event) else {
+            return Ok(());
+        };
         tracing::info!(target: "chain-listener",
             "Received UnitActivated event for unit: {}, startEpoch: {}",
             unit_event.unitId,
@@ -776,11 +905,12 @@ impl ChainListener {
         event: Option<Result<JsonValue, client::Error>>,
     ) -> eyre::Result<()> {
         let event = event.ok_or(eyre!("Failed to process UnitDeactivated event: got None"))??;
-        let log = serde_json::from_value::<Log>(event.clone()).map_err(|err| {
-            tracing::error!(target: "chain-listener", "Failed to parse UnitDeactivated event: {err}, data: {event}");
-            err
-        })?;
-        let unit_event = parse_log::<UnitDeactivated>(log)?;
+        if !self.mark_seen(&event) {
+            return Ok(());
+        }
+        let Some(unit_event) = self.decode_event::<UnitDeactivated>(&event) else {
+            return Ok(());
+        };
         let unit_id = CUID::new(unit_event.unitId.0);
         tracing::info!(target: "chain-listener",
             "Received UnitDeactivated event for unit: {}",
@@ -797,11 +927,12 @@ impl ChainListener {
         event: Option<Result<JsonValue, client::Error>>,
     ) -> eyre::Result<()> {
         let event = event.ok_or(eyre!("Failed to process DealMatched event: got None"))??;
-        let log = serde_json::from_value::<Log>(event.clone()).map_err(|err| {
-            tracing::error!(target: "chain-listener", "Failed to parse DealMatched event: {err}, data: {event}");
-            err
-        })?;
-        let deal_event = parse_log::<ComputeUnitMatched>(log)?;
+        if !self.mark_seen(&event) {
+            return Ok(());
+        }
+        let Some(deal_event) = self.decode_event::<ComputeUnitMatched>(&event) else {
+            return Ok(());
+        };
         tracing::info!(target: "chain-listener",
             "Received DealMatched event for deal: {}",
             deal_event.deal
@@ -15,11 +15,15 @@
 */
 use alloy_primitives::U256;
 use eyre::Context;
-use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 
 use alloy_serde_macro::{U256_as_String, U256_from_String};
 use ccp_shared::proof::ProofIdx;
 use serde::{Deserialize, Serialize};
+use server_config::{FsyncPolicy, PersistenceConfig};
+
 #[derive(Serialize, Deserialize)]
 pub struct PersistedProofId {
     pub proof_id: ProofIdx,
@@ -34,36 +38,163 @@ pub(crate) fn proof_id_filename() -> String {
     "proof_id.toml".to_string()
 }
 
-pub(crate) async fn persist_proof_id(
-    proof_id_dir: &Path,
-    proof_id: ProofIdx,
-    current_epoch: U256,
-) -> eyre::Result<()> {
-    let path = proof_id_dir.join(proof_id_filename());
-    let bytes = toml_edit::ser::to_vec(&PersistedProofId {
-        proof_id,
-        epoch: current_epoch,
-    })
-    .map_err(|err| eyre::eyre!("Proof id serialization failed {err}"))?;
-    tokio::fs::write(&path, bytes)
-        .await
-        .context(format!("error writing proof id to {}", path.display()))
+/// Writes listener progress to disk, fsyncing it according to the configured [`FsyncPolicy`]
+pub(crate) struct ProofIdPersistence {
+    proof_id_dir: PathBuf,
+    fsync: FsyncPolicy,
+    /// Whether there were writes since the last fsync
+    dirty: AtomicBool,
+    fsyncs: AtomicUsize,
+}
+
+impl ProofIdPersistence {
+    pub fn new(proof_id_dir: PathBuf, config: PersistenceConfig) -> Self {
+        Self {
+            proof_id_dir,
+            fsync: config.fsync,
+            dirty: AtomicBool::new(false),
+            fsyncs: AtomicUsize::new(0),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.proof_id_dir.join(proof_id_filename())
+    }
+
+    pub async fn persist_proof_id(
+        &self,
+        proof_id: ProofIdx,
+        current_epoch: U256,
+    ) -> eyre::Result<()> {
+        let path = self.path();
+        let bytes = toml_edit::ser::to_vec(&PersistedProofId {
+            proof_id,
+            epoch: current_epoch,
+        })
+        .map_err(|err| eyre::eyre!("Proof id serialization failed {err}"))?;
+        tokio::fs::write(&path, bytes)
+            .await
+            .context(format!("error writing proof id to {}", path.display()))?;
+
+        match self.fsync {
+            FsyncPolicy::Always => self.sync().await,
+            FsyncPolicy::Periodic { .. } => {
+                self.dirty.store(true, Ordering::Release);
+                Ok(())
+            }
+        }
+    }
+
+    /// Fsync the writes made since the last fsync, if there are any
+    pub async fn flush(&self) -> eyre::Result<()> {
+        if self.dirty.swap(false, Ordering::AcqRel) {
+            if let Err(err) = self.sync().await {
+                self.dirty.store(true, Ordering::Release);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    async fn sync(&self) -> eyre::Result<()> {
+        let path = self.path();
+        let file = tokio::fs::File::open(&path)
+            .await
+            .context(format!("error opening proof id file {}", path.display()))?;
+        file.sync_all()
+            .await
+            .context(format!("error syncing proof id to {}", path.display()))?;
+        self.fsyncs.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Number of fsyncs done so far
+    pub fn fsyncs(&self) -> usize {
+        self.fsyncs.load(Ordering::Relaxed)
+    }
+
+    /// How often [`flush`](Self::flush) should be called, `None` if every write is fsynced
+    pub fn flush_interval(&self) -> Option<Duration> {
+        match self.fsync {
+            FsyncPolicy::Always => None,
+            FsyncPolicy::Periodic { interval } => Some(interval),
+        }
+    }
+
+    pub async fn load_persisted_proof_id(&self) -> eyre::Result<Option<PersistedProofId>> {
+        let path = self.path();
+        if path.exists() {
+            let bytes = tokio::fs::read(&path)
+                .await
+                .context(format!("error reading proof id from {}", path.display()))?;
+            let persisted_proof = toml_edit::de::from_slice(&bytes).context(format!(
+                "error deserializing proof id from {}",
+                path.display()
+            ))?;
+            Ok(Some(persisted_proof))
+        } else {
+            Ok(None)
+        }
+    }
 }
 
-pub(crate) async fn load_persisted_proof_id(
-    proof_id_dir: &Path,
-) -> eyre::Result<Option<PersistedProofId>> {
-    let path = proof_id_dir.join(proof_id_filename());
-    if path.exists() {
-        let bytes = tokio::fs::read(&path)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fsync_always() {
+        let dir = tempfile::tempdir().unwrap();
+        let persistence = ProofIdPersistence::new(
+            dir.path().to_path_buf(),
+            PersistenceConfig {
+                fsync: FsyncPolicy::Always,
+            },
+        );
+
+        for i in 0..3u64 {
+            persistence
+                .persist_proof_id(ProofIdx::zero(), U256::from(i))
+                .await
+                .unwrap();
+        }
+        assert_eq!(persistence.fsyncs(), 3);
+        assert_eq!(persistence.flush_interval(), None);
+
+        let loaded = persistence
+            .load_persisted_proof_id()
             .await
-            .context(format!("error reading proof id from {}", path.display()))?;
-        let persisted_proof = toml_edit::de::from_slice(&bytes).context(format!(
-            "error deserializing proof id from {}",
-            path.display()
-        ))?;
-        Ok(Some(persisted_proof))
-    } else {
-        Ok(None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.epoch, U256::from(2));
+    }
+
+    #[tokio::test]
+    async fn fsync_periodic() {
+        let dir = tempfile::tempdir().unwrap();
+        let persistence = ProofIdPersistence::new(
+            dir.path().to_path_buf(),
+            PersistenceConfig {
+                fsync: FsyncPolicy::Periodic {
+                    interval: Duration::from_secs(1),
+                },
+            },
+        );
+
+        for i in 0..3u64 {
+            persistence
+                .persist_proof_id(ProofIdx::zero(), U256::from(i))
+                .await
+                .unwrap();
+        }
+        assert_eq!(persistence.fsyncs(), 0);
+        assert_eq!(persistence.flush_interval(), Some(Duration::from_secs(1)));
+
+        persistence.flush().await.unwrap();
+        assert_eq!(persistence.fsyncs(), 1);
+
+        // nothing was written since the last flush
+        persistence.flush().await.unwrap();
+        assert_eq!(persistence.fsyncs(), 1);
     }
 }
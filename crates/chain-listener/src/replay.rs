@@ -0,0 +1,169 @@
+/*
+ * Copyright 2024 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::{HashSet, VecDeque};
+
+use alloy_primitives::BlockNumber;
+use jsonrpsee::core::JsonValue;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ChainEventKind {
+    CommitmentActivated,
+    UnitActivated,
+    UnitDeactivated,
+    UnitMatched,
+}
+
+/// Position of a log on chain, unique for each log
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct LogPosition {
+    pub block_number: BlockNumber,
+    pub log_index: u64,
+}
+
+impl LogPosition {
+    pub fn from_json(log: &JsonValue) -> Option<Self> {
+        let parse = |field: &str| {
+            let value = log.get(field)?.as_str()?.strip_prefix("0x")?;
+            u64::from_str_radix(value, 16).ok()
+        };
+
+        Some(Self {
+            block_number: parse("blockNumber")?,
+            log_index: parse("logIndex")?,
+        })
+    }
+}
+
+/// Bounded window of the recently processed logs
+pub(crate) struct SeenLogs {
+    capacity: usize,
+    order: VecDeque<LogPosition>,
+    positions: HashSet<LogPosition>,
+}
+
+impl SeenLogs {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            positions: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Returns false if the log was already seen
+    pub fn insert(&mut self, position: LogPosition) -> bool {
+        if !self.positions.insert(position) {
+            return false;
+        }
+
+        self.order.push_back(position);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.positions.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, position: &LogPosition) -> bool {
+        self.positions.contains(position)
+    }
+}
+
+/// Orders the fetched logs as they happened on chain, leaving out the logs before `from_block`
+/// and the ones that were already processed
+pub(crate) fn replay_order(
+    logs: Vec<(ChainEventKind, JsonValue)>,
+    from_block: BlockNumber,
+    seen: &SeenLogs,
+) -> Vec<(ChainEventKind, JsonValue)> {
+    let mut logs: Vec<_> = logs
+        .into_iter()
+        .filter_map(|(kind, log)| {
+            let position = LogPosition::from_json(&log)?;
+            (position.block_number >= from_block && !seen.contains(&position))
+                .then_some((position, kind, log))
+        })
+        .collect();
+    logs.sort_by_key(|(position, _, _)| *position);
+    logs.dedup_by_key(|(position, _, _)| *position);
+
+    logs.into_iter().map(|(_, kind, log)| (kind, log)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn log(block_number: u64, log_index: u64) -> JsonValue {
+        json!({
+            "blockNumber": format!("{block_number:#x}"),
+            "logIndex": format!("{log_index:#x}"),
+        })
+    }
+
+    #[test]
+    fn replay_from_block() {
+        let logs = vec![
+            (ChainEventKind::UnitMatched, log(12, 0)),
+            (ChainEventKind::UnitActivated, log(9, 3)),
+            (ChainEventKind::CommitmentActivated, log(10, 1)),
+            (ChainEventKind::UnitDeactivated, log(10, 0)),
+            (ChainEventKind::UnitActivated, log(11, 2)),
+        ];
+
+        let mut seen = SeenLogs::new(16);
+        seen.insert(LogPosition {
+            block_number: 11,
+            log_index: 2,
+        });
+
+        let replayed = replay_order(logs, 10, &seen);
+        let replayed: Vec<_> = replayed
+            .iter()
+            .map(|(kind, log)| (*kind, LogPosition::from_json(log).unwrap()))
+            .map(|(kind, p)| (kind, p.block_number, p.log_index))
+            .collect();
+
+        assert_eq!(
+            replayed,
+            vec![
+                (ChainEventKind::UnitDeactivated, 10, 0),
+                (ChainEventKind::CommitmentActivated, 10, 1),
+                (ChainEventKind::UnitMatched, 12, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn seen_logs_window() {
+        let position = |log_index| LogPosition {
+            block_number: 1,
+            log_index,
+        };
+
+        let mut seen = SeenLogs::new(2);
+        assert!(seen.insert(position(0)));
+        assert!(!seen.insert(position(0)));
+        assert!(seen.insert(position(1)));
+        assert!(seen.insert(position(2)));
+
+        assert!(!seen.contains(&position(0)));
+        assert!(seen.contains(&position(1)));
+        assert!(seen.contains(&position(2)));
+    }
+}
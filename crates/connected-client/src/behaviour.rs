@@ -255,7 +255,7 @@ impl NetworkBehaviour for ClientBehaviour {
     ) {
         use ClientEvent::Particle;
 
-        if let Ok(HandlerMessage::InParticle(particle)) = event {
+        if let Ok(HandlerMessage::InParticle(particle, _)) = event {
             self.events.push_back(GenerateEvent(Particle {
                 particle,
                 sender: peer_id,
@@ -2,8 +2,10 @@ use crate::Map;
 use ccp_shared::types::CUID;
 use cpu_utils::pinning::pin_current_thread_to_cpuset;
 use cpu_utils::{LogicalCoreId, PhysicalCoreId};
+use fxhash::FxHasher64;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub enum WorkType {
@@ -44,4 +46,69 @@ impl Assignment {
     pub fn pin_current_thread(&self) {
         pin_current_thread_to_cpuset(self.logical_core_ids.iter().cloned());
     }
+
+    /// Hash that doesn't depend on the iteration order of `cuid_cores`,
+    /// so assignments made on different nodes can be compared
+    pub fn stable_hash(&self) -> u64 {
+        let mut hasher = FxHasher64::default();
+        self.physical_core_ids.hash(&mut hasher);
+        self.logical_core_ids.hash(&mut hasher);
+
+        let mut cuid_cores: Vec<_> = self.cuid_cores.iter().collect();
+        cuid_cores.sort_by(|(left, _), (right, _)| left.cmp(right));
+        for (cuid, cores) in cuid_cores {
+            cuid.hash(&mut hasher);
+            cores.physical_core_id.hash(&mut hasher);
+            let logical_core_ids: BTreeSet<_> = cores.logical_core_ids.iter().collect();
+            logical_core_ids.hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fxhash::FxBuildHasher;
+
+    #[test]
+    fn stable_hash_ignores_insertion_order() {
+        let entries: Vec<_> = (0..16u8)
+            .map(|i| {
+                let cores = Cores {
+                    physical_core_id: PhysicalCoreId::new(i as u32),
+                    logical_core_ids: vec![
+                        LogicalCoreId::new(2 * i as u32),
+                        LogicalCoreId::new(2 * i as u32 + 1),
+                    ],
+                };
+                (CUID::new([i; 32]), cores)
+            })
+            .collect();
+
+        let mut forward = Map::with_capacity_and_hasher(1, FxBuildHasher::default());
+        for (cuid, cores) in entries.iter().cloned() {
+            forward.insert(cuid, cores);
+        }
+
+        let mut backward = Map::with_capacity_and_hasher(64, FxBuildHasher::default());
+        for (cuid, mut cores) in entries.iter().rev().cloned() {
+            cores.logical_core_ids.reverse();
+            backward.insert(cuid, cores);
+        }
+
+        let assignment = |cuid_cores| Assignment {
+            physical_core_ids: (0..16).map(PhysicalCoreId::new).collect(),
+            logical_core_ids: (0..32).map(LogicalCoreId::new).collect(),
+            cuid_cores,
+        };
+        let forward = assignment(forward);
+        let backward = assignment(backward);
+        assert_eq!(forward.stable_hash(), backward.stable_hash());
+
+        let mut other = forward.clone();
+        other.physical_core_ids.remove(&PhysicalCoreId::new(0));
+        assert_ne!(forward.stable_hash(), other.stable_hash());
+    }
 }
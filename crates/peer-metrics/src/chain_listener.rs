@@ -34,6 +34,8 @@ pub struct ChainListenerMetrics {
     // How many block we manage to process while processing the block
     blocks_processed: Counter,
     last_process_block: Gauge,
+    // How many chain events we failed to decode and skipped
+    decode_errors: Counter,
 }
 
 impl ChainListenerMetrics {
@@ -116,6 +118,13 @@ impl ChainListenerMetrics {
             "Last processed block from the newHead subscription",
         );
 
+        let decode_errors = register(
+            sub_registry,
+            Counter::default(),
+            "decode_errors",
+            "Total number of chain events skipped because they failed to decode",
+        );
+
         Self {
             ccp_requests_total,
             ccp_replies_total,
@@ -128,6 +137,7 @@ impl ChainListenerMetrics {
             last_seen_block,
             blocks_processed,
             last_process_block,
+            decode_errors,
         }
     }
 
@@ -166,4 +176,8 @@ impl ChainListenerMetrics {
         self.blocks_processed.inc();
         self.last_process_block.set(block_number as i64);
     }
+
+    pub fn observe_decode_error(&self) {
+        self.decode_errors.inc();
+    }
 }
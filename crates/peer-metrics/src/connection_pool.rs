@@ -11,6 +11,7 @@ pub struct ConnectionPoolMetrics {
     pub particle_sizes: Family<ParticleLabel, Histogram>,
     pub connected_peers: Gauge,
     pub particle_queue_size: Gauge,
+    pub unsupported_format_messages: Counter,
 }
 
 impl ConnectionPoolMetrics {
@@ -47,11 +48,19 @@ impl ConnectionPoolMetrics {
             particle_queue_size.clone(),
         );
 
+        let unsupported_format_messages = Counter::default();
+        sub_registry.register(
+            "unsupported_format_messages",
+            "Number of messages rejected due to unsupported serialization format",
+            unsupported_format_messages.clone(),
+        );
+
         Self {
             received_particles,
             particle_sizes,
             connected_peers,
             particle_queue_size,
+            unsupported_format_messages,
         }
     }
 
@@ -65,4 +74,8 @@ impl ConnectionPoolMetrics {
             .get_or_create(&label)
             .observe(particle_len);
     }
+
+    pub fn unsupported_format_message(&self) {
+        self.unsupported_format_messages.inc();
+    }
 }
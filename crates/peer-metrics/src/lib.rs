@@ -10,7 +10,10 @@ pub use connectivity::Resolution;
 pub use dispatcher::DispatcherMetrics;
 pub use info::add_info_metrics;
 use particle_execution::ParticleParams;
-pub use particle_executor::{FunctionKind, ParticleExecutorMetrics, WorkerLabel, WorkerType};
+pub use particle_executor::{
+    ExecutorCounter, FunctionKind, MetricsSink, ParticleExecutorMetrics, ParticleOrigin,
+    ParticleOriginLabel, WorkerCounter, WorkerGauge, WorkerLabel, WorkerType,
+};
 pub use services_metrics::{
     ServiceCallStats, ServiceMemoryStat, ServiceType, ServicesMetrics, ServicesMetricsBackend,
     ServicesMetricsBuiltin, ServicesMetricsExternal,
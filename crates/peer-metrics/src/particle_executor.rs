@@ -4,7 +4,7 @@ use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
 use prometheus_client::metrics::counter::Counter;
 use prometheus_client::metrics::family::Family;
 use prometheus_client::metrics::gauge::Gauge;
-use prometheus_client::metrics::histogram::Histogram;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
 use prometheus_client::registry::Registry;
 
 use crate::execution_time_buckets;
@@ -17,21 +17,97 @@ pub enum FunctionKind {
     NotHappened,
 }
 
+impl FunctionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FunctionKind::Service => "Service",
+            FunctionKind::ParticleFunction => "ParticleFunction",
+            FunctionKind::NotHappened => "NotHappened",
+        }
+    }
+}
+
 #[derive(EncodeLabelSet, Hash, Clone, Eq, PartialEq, Debug)]
 pub struct FunctionKindLabel {
     function_kind: FunctionKind,
 }
 
+/// Function kind of a service call made on the host or a worker
+#[derive(EncodeLabelSet, Hash, Clone, Eq, PartialEq, Debug)]
+pub struct WorkerFunctionKindLabel {
+    worker_type: WorkerType,
+    peer_id: String,
+    function_kind: FunctionKind,
+}
+
+impl WorkerFunctionKindLabel {
+    fn new(worker: &WorkerLabel, function_kind: FunctionKind) -> Self {
+        Self {
+            worker_type: worker.worker_type.clone(),
+            peer_id: worker.peer_id.clone(),
+            function_kind,
+        }
+    }
+}
+
+/// Where the particle came from
+#[derive(Copy, Clone, Debug, EncodeLabelValue, Hash, Eq, PartialEq)]
+pub enum ParticleOrigin {
+    /// Received from the network
+    Network,
+    /// Re-ingested as a local effect of another particle's execution
+    LocalEffect,
+}
+
+impl ParticleOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParticleOrigin::Network => "Network",
+            ParticleOrigin::LocalEffect => "LocalEffect",
+        }
+    }
+}
+
+#[derive(EncodeLabelSet, Hash, Clone, Eq, PartialEq, Debug)]
+pub struct ParticleOriginLabel {
+    origin: ParticleOrigin,
+}
+
+impl ParticleOriginLabel {
+    pub fn new(origin: ParticleOrigin) -> Self {
+        Self { origin }
+    }
+}
+
 #[derive(Clone)]
 pub struct ParticleExecutorMetrics {
     pub interpretation_time_sec: Family<WorkerLabel, Histogram>,
     pub interpretation_successes: Family<WorkerLabel, Counter>,
     pub interpretation_failures: Family<WorkerLabel, Counter>,
+    pub empty_effects: Family<WorkerLabel, Counter>,
     pub total_actors_mailbox: Family<WorkerLabel, Gauge>,
     pub alive_actors: Family<WorkerLabel, Gauge>,
+    pub avm_recreated: Family<WorkerLabel, Counter>,
+    /// Memory size of the AquaVM that finished the latest interpretation
+    pub avm_memory_size: Family<WorkerLabel, Gauge>,
+    /// Allocations rejected over the lifetime of the AquaVM that finished the latest interpretation
+    pub avm_allocation_rejects: Family<WorkerLabel, Gauge>,
+    pub unknown_peer_scopes: Family<WorkerLabel, Counter>,
+    pub ingested_particles: Family<ParticleOriginLabel, Counter>,
+    /// Number of local peers a single particle execution was routed to
+    pub local_effect_fanout: Histogram,
+    pub local_reingests: Counter,
+    pub signature_verifications: Counter,
+    cleanups: Counter,
+    cleanup_keys: Counter,
+    cleanup_time_sec: Histogram,
+    pub cleanups_skipped: Counter,
     service_call_time_sec: Family<FunctionKindLabel, Histogram>,
     service_call_success: Family<FunctionKindLabel, Counter>,
     service_call_failure: Family<FunctionKindLabel, Counter>,
+    worker_service_call_time_sec: Family<WorkerFunctionKindLabel, Histogram>,
+    worker_service_call_success: Family<WorkerFunctionKindLabel, Counter>,
+    worker_service_call_failure: Family<WorkerFunctionKindLabel, Counter>,
 }
 
 #[derive(EncodeLabelSet, Debug, Clone, Hash, Eq, PartialEq)]
@@ -47,12 +123,126 @@ impl WorkerLabel {
             peer_id,
         }
     }
+
+    pub fn worker_type(&self) -> &WorkerType {
+        &self.worker_type
+    }
+
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// Label as name-value pairs, for the sinks exporting metrics by name
+    pub fn pairs(&self) -> [(&'static str, &str); 2] {
+        [
+            ("worker_type", self.worker_type.as_str()),
+            ("peer_id", &self.peer_id),
+        ]
+    }
 }
 
 #[derive(EncodeLabelValue, Debug, Clone, Hash, Eq, PartialEq)]
 pub enum WorkerType {
     Worker,
+    /// Host scope, all particles executed on the host
     Host,
+    /// Part of the host scope, particles sent by the host peer itself
+    Root,
+}
+
+impl WorkerType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerType::Worker => "Worker",
+            WorkerType::Host => "Host",
+            WorkerType::Root => "Root",
+        }
+    }
+}
+
+/// Counters of the particle executor as a whole
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum ExecutorCounter {
+    LocalReingests,
+    SignatureVerifications,
+    CleanupsSkipped,
+}
+
+impl ExecutorCounter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExecutorCounter::LocalReingests => "local_reingests",
+            ExecutorCounter::SignatureVerifications => "signature_verifications",
+            ExecutorCounter::CleanupsSkipped => "cleanups_skipped",
+        }
+    }
+}
+
+/// Counters of the host or a worker
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum WorkerCounter {
+    InterpretationSuccesses,
+    InterpretationFailures,
+    EmptyEffects,
+    AvmRecreated,
+    UnknownPeerScopes,
+}
+
+impl WorkerCounter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerCounter::InterpretationSuccesses => "interpretation_successes",
+            WorkerCounter::InterpretationFailures => "interpretation_failures",
+            WorkerCounter::EmptyEffects => "empty_effects",
+            WorkerCounter::AvmRecreated => "avm_recreated",
+            WorkerCounter::UnknownPeerScopes => "unknown_peer_scopes",
+        }
+    }
+}
+
+/// Gauges of the host or a worker
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum WorkerGauge {
+    TotalActorsMailbox,
+    AliveActors,
+    AvmMemorySize,
+    AvmAllocationRejects,
+}
+
+impl WorkerGauge {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerGauge::TotalActorsMailbox => "total_actors_mailbox",
+            WorkerGauge::AliveActors => "alive_actors",
+            WorkerGauge::AvmMemorySize => "avm_memory_size",
+            WorkerGauge::AvmAllocationRejects => "avm_allocation_rejects",
+        }
+    }
+}
+
+/// Destination of the metrics emitted by the particle executor.
+/// `ParticleExecutorMetrics` puts them into the prometheus registry,
+/// other implementations may export them to StatsD or any other metrics system.
+pub trait MetricsSink: Send + Sync {
+    /// Increments `counter` by `value`
+    fn counter(&self, counter: ExecutorCounter, value: u64);
+    /// Increments `counter` of the host or the worker by `value`
+    fn worker_counter(&self, counter: WorkerCounter, worker: &WorkerLabel, value: u64);
+    /// Sets `gauge` of the host or the worker to `value`
+    fn worker_gauge(&self, gauge: WorkerGauge, worker: &WorkerLabel, value: i64);
+    fn interpretation_time(&self, worker: &WorkerLabel, time: Duration);
+    fn ingested_particle(&self, origin: ParticleOrigin);
+    /// Number of local peers a single particle execution was routed to
+    fn local_effect_fanout(&self, peers: usize);
+    fn cleanup_finished(&self, keys: usize, time: Duration);
+    /// Counts the call both in total and for the host or the worker it was made on
+    fn service_call(
+        &self,
+        worker: &WorkerLabel,
+        success: bool,
+        kind: FunctionKind,
+        run_time: Option<Duration>,
+    );
 }
 
 impl ParticleExecutorMetrics {
@@ -88,6 +278,13 @@ impl ParticleExecutorMetrics {
             interpretation_failures.clone(),
         );
 
+        let empty_effects = Family::default();
+        sub_registry.register(
+            "empty_effects",
+            "Number of successful particle interpretations that produced neither data, nor peers to send to, nor calls",
+            empty_effects.clone(),
+        );
+
         let total_actors_mailbox: Family<WorkerLabel, Gauge> =
             Family::new_with_constructor(Gauge::default);
         sub_registry.register(
@@ -102,6 +299,87 @@ impl ParticleExecutorMetrics {
             alive_actors.clone(),
         );
 
+        let avm_recreated = Family::default();
+        sub_registry.register(
+            "avm_recreated",
+            "Number of AquaVMs lost to a panic, cancellation or timeout and recreated",
+            avm_recreated.clone(),
+        );
+
+        let avm_memory_size: Family<WorkerLabel, Gauge> =
+            Family::new_with_constructor(Gauge::default);
+        sub_registry.register(
+            "avm_memory_size",
+            "Memory size of the AquaVM that finished the latest interpretation",
+            avm_memory_size.clone(),
+        );
+        let avm_allocation_rejects: Family<WorkerLabel, Gauge> =
+            Family::new_with_constructor(Gauge::default);
+        sub_registry.register(
+            "avm_allocation_rejects",
+            "Number of allocations rejected by the AquaVM that finished the latest interpretation because of its memory limit",
+            avm_allocation_rejects.clone(),
+        );
+
+        let unknown_peer_scopes = Family::default();
+        sub_registry.register(
+            "unknown_peer_scopes",
+            "Number of next peers dropped in strict mode because they aren't local",
+            unknown_peer_scopes.clone(),
+        );
+
+        let ingested_particles = Family::default();
+        sub_registry.register(
+            "ingested_particles",
+            "Number of particles ingested for execution by origin",
+            ingested_particles.clone(),
+        );
+
+        let local_effect_fanout = Histogram::new(exponential_buckets(1.0, 2.0, 10));
+        sub_registry.register(
+            "local_effect_fanout",
+            "Distribution of the number of local peers a particle was routed to by a single execution",
+            local_effect_fanout.clone(),
+        );
+        let local_reingests = Counter::default();
+        sub_registry.register(
+            "local_reingests",
+            "Number of particles re-ingested locally as effects of executions",
+            local_reingests.clone(),
+        );
+
+        let signature_verifications = Counter::default();
+        sub_registry.register(
+            "signature_verifications",
+            "Number of particle signature verifications, not counting the ones skipped by the cache",
+            signature_verifications.clone(),
+        );
+
+        let cleanups = Counter::default();
+        sub_registry.register(
+            "cleanups",
+            "Number of started cleanups of the expired particles data",
+            cleanups.clone(),
+        );
+        let cleanup_keys = Counter::default();
+        sub_registry.register(
+            "cleanup_keys",
+            "Number of particles whose data was cleaned up",
+            cleanup_keys.clone(),
+        );
+        let cleanup_time_sec = Histogram::new(execution_time_buckets());
+        sub_registry.register(
+            "cleanup_time_sec",
+            "Distribution of time it took to clean up the expired particles data",
+            cleanup_time_sec.clone(),
+        );
+        let cleanups_skipped = Counter::default();
+        sub_registry.register(
+            "cleanups_skipped",
+            "Number of cleanups that were due while the previous cleanup was still in progress",
+            cleanups_skipped.clone(),
+        );
+
         let service_call_time_sec: Family<_, _> =
             Family::new_with_constructor(|| Histogram::new(execution_time_buckets()));
         sub_registry.register(
@@ -122,32 +400,167 @@ impl ParticleExecutorMetrics {
             service_call_failure.clone(),
         );
 
+        let worker_service_call_time_sec: Family<_, _> =
+            Family::new_with_constructor(|| Histogram::new(execution_time_buckets()));
+        sub_registry.register(
+            "worker_service_call_time_sec",
+            "Distribution of time it took to execute a single service or builtin call on the host or a worker",
+            worker_service_call_time_sec.clone(),
+        );
+        let worker_service_call_success = Family::default();
+        sub_registry.register(
+            "worker_service_call_success",
+            "Number of succeeded service calls on the host or a worker",
+            worker_service_call_success.clone(),
+        );
+        let worker_service_call_failure = Family::default();
+        sub_registry.register(
+            "worker_service_call_failure",
+            "Number of failed service calls on the host or a worker",
+            worker_service_call_failure.clone(),
+        );
+
         Self {
             interpretation_time_sec,
             interpretation_successes,
             interpretation_failures,
+            empty_effects,
             total_actors_mailbox,
             alive_actors,
+            avm_recreated,
+            avm_memory_size,
+            avm_allocation_rejects,
+            unknown_peer_scopes,
+            ingested_particles,
+            local_effect_fanout,
+            local_reingests,
+            signature_verifications,
+            cleanups,
+            cleanup_keys,
+            cleanup_time_sec,
+            cleanups_skipped,
             service_call_time_sec,
             service_call_success,
             service_call_failure,
+            worker_service_call_time_sec,
+            worker_service_call_success,
+            worker_service_call_failure,
         }
     }
+}
+
+impl MetricsSink for ParticleExecutorMetrics {
+    fn counter(&self, counter: ExecutorCounter, value: u64) {
+        let counter = match counter {
+            ExecutorCounter::LocalReingests => &self.local_reingests,
+            ExecutorCounter::SignatureVerifications => &self.signature_verifications,
+            ExecutorCounter::CleanupsSkipped => &self.cleanups_skipped,
+        };
+        counter.inc_by(value);
+    }
+
+    fn worker_counter(&self, counter: WorkerCounter, worker: &WorkerLabel, value: u64) {
+        let family = match counter {
+            WorkerCounter::InterpretationSuccesses => &self.interpretation_successes,
+            WorkerCounter::InterpretationFailures => &self.interpretation_failures,
+            WorkerCounter::EmptyEffects => &self.empty_effects,
+            WorkerCounter::AvmRecreated => &self.avm_recreated,
+            WorkerCounter::UnknownPeerScopes => &self.unknown_peer_scopes,
+        };
+        family.get_or_create(worker).inc_by(value);
+    }
+
+    fn worker_gauge(&self, gauge: WorkerGauge, worker: &WorkerLabel, value: i64) {
+        let family = match gauge {
+            WorkerGauge::TotalActorsMailbox => &self.total_actors_mailbox,
+            WorkerGauge::AliveActors => &self.alive_actors,
+            WorkerGauge::AvmMemorySize => &self.avm_memory_size,
+            WorkerGauge::AvmAllocationRejects => &self.avm_allocation_rejects,
+        };
+        family.get_or_create(worker).set(value);
+    }
 
-    pub fn service_call(&self, success: bool, kind: FunctionKind, run_time: Option<Duration>) {
+    fn interpretation_time(&self, worker: &WorkerLabel, time: Duration) {
+        self.interpretation_time_sec
+            .get_or_create(worker)
+            .observe(time.as_secs_f64());
+    }
+
+    fn ingested_particle(&self, origin: ParticleOrigin) {
+        self.ingested_particles
+            .get_or_create(&ParticleOriginLabel::new(origin))
+            .inc();
+    }
+
+    fn local_effect_fanout(&self, peers: usize) {
+        self.local_effect_fanout.observe(peers as f64);
+    }
+
+    fn cleanup_finished(&self, keys: usize, time: Duration) {
+        self.cleanups.inc();
+        self.cleanup_keys.inc_by(keys as u64);
+        self.cleanup_time_sec.observe(time.as_secs_f64());
+    }
+
+    fn service_call(
+        &self,
+        worker: &WorkerLabel,
+        success: bool,
+        kind: FunctionKind,
+        run_time: Option<Duration>,
+    ) {
         let label = FunctionKindLabel {
             function_kind: kind,
         };
+        let worker_label = WorkerFunctionKindLabel::new(worker, kind);
 
         if success {
             self.service_call_success.get_or_create(&label).inc();
+            self.worker_service_call_success
+                .get_or_create(&worker_label)
+                .inc();
         } else {
             self.service_call_failure.get_or_create(&label).inc();
+            self.worker_service_call_failure
+                .get_or_create(&worker_label)
+                .inc();
         }
         if let Some(run_time) = run_time {
             self.service_call_time_sec
                 .get_or_create(&label)
-                .observe(run_time.as_secs_f64())
+                .observe(run_time.as_secs_f64());
+            self.worker_service_call_time_sec
+                .get_or_create(&worker_label)
+                .observe(run_time.as_secs_f64());
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_call_counted_in_total_and_per_worker() {
+        let mut registry = Registry::default();
+        let metrics = ParticleExecutorMetrics::new(&mut registry);
+        let host = WorkerLabel::new(WorkerType::Host, "host".to_string());
+        let worker = WorkerLabel::new(WorkerType::Worker, "worker".to_string());
+
+        metrics.service_call(&host, true, FunctionKind::Service, None);
+        metrics.service_call(&worker, true, FunctionKind::Service, None);
+
+        let total = FunctionKindLabel {
+            function_kind: FunctionKind::Service,
+        };
+        assert_eq!(metrics.service_call_success.get_or_create(&total).get(), 2);
+
+        let host_label = WorkerFunctionKindLabel::new(&host, FunctionKind::Service);
+        let worker_label = WorkerFunctionKindLabel::new(&worker, FunctionKind::Service);
+        assert_ne!(host_label, worker_label);
+        let success = &metrics.worker_service_call_success;
+        assert_eq!(success.get_or_create(&host_label).get(), 1);
+        assert_eq!(success.get_or_create(&worker_label).get(), 1);
+        assert_eq!(metrics.service_call_failure.get_or_create(&total).get(), 0);
+    }
+}
@@ -1,96 +1,99 @@
 use std::cmp::{max, min};
 
 use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
 use prometheus_client::metrics::gauge::Gauge;
 use prometheus_client::metrics::histogram::Histogram;
 use prometheus_client::registry::Registry;
 
-use crate::mem_buckets;
+use crate::{mem_buckets, WorkerLabel};
 
+/// Metrics are labeled by the pool: the host pool or a worker pool
 #[derive(Clone)]
 pub struct VmPoolMetrics {
-    pool_size: Gauge,
-    pub free_vms: Gauge,
-    pub get_vm: Counter,
-    pub put_vm: Counter,
-    pub no_free_vm: Counter,
+    pool_size: Family<WorkerLabel, Gauge>,
+    pub free_vms: Family<WorkerLabel, Gauge>,
+    pub get_vm: Family<WorkerLabel, Counter>,
+    pub put_vm: Family<WorkerLabel, Counter>,
+    pub no_free_vm: Family<WorkerLabel, Counter>,
 
     pub vm_mem_max_value: u64,
-    pub vm_mem_max: Gauge,
+    pub vm_mem_max: Family<WorkerLabel, Gauge>,
     pub vm_mem_min_value: u64,
-    pub vm_mem_min: Gauge,
+    pub vm_mem_min: Family<WorkerLabel, Gauge>,
     // store memory sizes for each vm
     pub vm_mems: Vec<u64>,
-    pub vm_mem_total: Gauge,
+    pub vm_mem_total: Family<WorkerLabel, Gauge>,
     // cumulative moving average
     pub vm_mem_cma: u64,
     pub vm_mem_measures: u64,
-    pub vm_mem_avg: Gauge,
+    pub vm_mem_avg: Family<WorkerLabel, Gauge>,
     // histogram
-    pub vm_mem_histo: Histogram,
+    pub vm_mem_histo: Family<WorkerLabel, Histogram>,
 }
 
 impl VmPoolMetrics {
     pub fn new(registry: &mut Registry) -> Self {
         let sub_registry = registry.sub_registry_with_prefix("aqua_vm_pool");
 
-        let pool_size = Gauge::default();
+        let pool_size = Family::default();
         sub_registry.register("pool_size", "Size of the AquaVM pool", pool_size.clone());
 
-        let free_vms = Gauge::default();
+        let free_vms = Family::default();
         sub_registry.register(
             "free_vms",
             "Number of currently free AquaVMs",
             free_vms.clone(),
         );
 
-        let get_vm = Counter::default();
+        let get_vm = Family::default();
         sub_registry.register(
             "get_vm",
             "Number of times an AquaVM has been taken from the pool",
             get_vm.clone(),
         );
 
-        let put_vm = Counter::default();
+        let put_vm = Family::default();
         sub_registry.register(
             "put_vm",
             "Number of times an AquaVM has been put back to the pool",
             put_vm.clone(),
         );
 
-        let no_free_vm = Counter::default();
+        let no_free_vm = Family::default();
         sub_registry.register(
             "no_free_vm",
             "Number of time when we tried to take an AquaVM from an empty pool",
             no_free_vm.clone(),
         );
 
-        let vm_mem_max = Gauge::default();
+        let vm_mem_max = Family::default();
         sub_registry.register(
             "vm_mem_max",
             "Maximum allocated memory among all interpreters (after first interpretation)",
             vm_mem_max.clone(),
         );
-        let vm_mem_min = Gauge::default();
+        let vm_mem_min = Family::default();
         sub_registry.register(
             "vm_mem_min",
             "Minumum allocated memory among all interpreters (after first interpretation)",
             vm_mem_min.clone(),
         );
-        let vm_mem_total = Gauge::default();
+        let vm_mem_total = Family::default();
         sub_registry.register(
             "vm_mem_total",
             "Total memory allocated by all interpreters on node",
             vm_mem_total.clone(),
         );
-        let vm_mem_avg = Gauge::default();
+        let vm_mem_avg = Family::default();
         sub_registry.register(
             "vm_mem_avg",
             "Average allocated memory of an interpreter",
             vm_mem_avg.clone(),
         );
         // 1mb, 5mb, 10mb, 25mb, 50mb, 100mb, 200mb
-        let vm_mem_histo = Histogram::new(mem_buckets());
+        let vm_mem_histo: Family<WorkerLabel, Histogram> =
+            Family::new_with_constructor(|| Histogram::new(mem_buckets()));
         sub_registry.register(
             "vm_mem_histo",
             "Interpreter memory size distribution",
@@ -117,12 +120,24 @@ impl VmPoolMetrics {
         }
     }
 
-    pub fn set_pool_size(&mut self, size: usize) {
+    /// Metrics for another pool: published to the same registry, but with empty memory stats
+    pub fn for_another_pool(&self) -> Self {
+        Self {
+            vm_mem_max_value: 0,
+            vm_mem_min_value: 0,
+            vm_mems: vec![],
+            vm_mem_cma: 0,
+            vm_mem_measures: 0,
+            ..self.clone()
+        }
+    }
+
+    pub fn set_pool_size(&mut self, label: &WorkerLabel, size: usize) {
         self.vm_mems.resize(size, 0);
-        self.pool_size.set(size as i64);
+        self.pool_size.get_or_create(label).set(size as i64);
     }
 
-    pub fn measure_memory(&mut self, idx: usize, memory_size: u64) {
+    pub fn measure_memory(&mut self, label: &WorkerLabel, idx: usize, memory_size: u64) {
         // TODO: this is a HACK until we stop using `get_vm` for cleaning up Actor resources.
         //       Until then, intentionally ignore memory measurements for AquaVMs that haven't
         //       yet processed any particles.
@@ -131,7 +146,9 @@ impl VmPoolMetrics {
         }
 
         // Histogram
-        self.vm_mem_histo.observe(memory_size as f64);
+        self.vm_mem_histo
+            .get_or_create(label)
+            .observe(memory_size as f64);
 
         // Cumulative Moving Average
         // cma_n+1 = cma_n + ((x_n+1 - cma_n) / (n + 1))
@@ -143,7 +160,7 @@ impl VmPoolMetrics {
         let vm_mem_cma = i64::try_from(self.vm_mem_cma);
         match vm_mem_cma {
             Ok(vm_mem_cma) => {
-                self.vm_mem_avg.set(vm_mem_cma);
+                self.vm_mem_avg.get_or_create(label).set(vm_mem_cma);
             }
             Err(err) => {
                 log::warn!("Could not set vm_mem_cma metric {}", err);
@@ -155,7 +172,7 @@ impl VmPoolMetrics {
         let vm_mem_max_value = i64::try_from(self.vm_mem_max_value);
         match vm_mem_max_value {
             Ok(vm_mem_max_value) => {
-                self.vm_mem_max.set(vm_mem_max_value);
+                self.vm_mem_max.get_or_create(label).set(vm_mem_max_value);
             }
             Err(err) => {
                 log::warn!("Could not set vm_mem_max_value metric {}", err);
@@ -167,7 +184,7 @@ impl VmPoolMetrics {
         let vm_mem_min_value = i64::try_from(self.vm_mem_min_value);
         match vm_mem_min_value {
             Ok(vm_mem_min_value) => {
-                self.vm_mem_min.set(vm_mem_min_value);
+                self.vm_mem_min.get_or_create(label).set(vm_mem_min_value);
             }
             Err(err) => {
                 log::warn!("Could not set vm_mem_min_value metric {}", err);
@@ -184,7 +201,7 @@ impl VmPoolMetrics {
                 let total = i64::try_from(total);
                 match total {
                     Ok(total) => {
-                        self.vm_mem_total.set(total);
+                        self.vm_mem_total.get_or_create(label).set(total);
                     }
                     Err(err) => {
                         log::warn!("Could not set total metric {}", err);
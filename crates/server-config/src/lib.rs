@@ -37,6 +37,7 @@ mod kademlia_config;
 mod keys;
 mod network_config;
 mod node_config;
+mod plumber_config;
 mod resolved_config;
 mod services_config;
 pub mod system_services_config;
@@ -50,7 +51,10 @@ pub use resolved_config::ConfigData;
 pub use bootstrap_config::BootstrapConfig;
 pub use kademlia_config::KademliaConfig;
 pub use network_config::NetworkConfig;
-pub use node_config::{ChainConfig, ChainListenerConfig, NodeConfig, TransportConfig};
+pub use node_config::{
+    ChainConfig, ChainListenerConfig, FsyncPolicy, NodeConfig, PersistenceConfig, TransportConfig,
+};
+pub use plumber_config::{ActorKeying, NoCapacityPolicy, PlumberConfig};
 pub use resolved_config::TracingConfig;
 pub use resolved_config::{ResolvedConfig, UnresolvedConfig};
 pub use system_services_config::{AquaIpfsConfig, DeciderConfig, SystemServicesConfig};
@@ -24,6 +24,7 @@ use types::peer_id;
 
 use crate::avm_config::AVMConfig;
 use crate::keys::{decode_key, decode_secret_key, load_key};
+use crate::plumber_config::PlumberConfig;
 use crate::services_config::ServicesConfig;
 use crate::system_services_config::{ServiceKey, SystemServicesConfig};
 use crate::{BootstrapConfig, KademliaConfig};
@@ -150,6 +151,9 @@ pub struct UnresolvedNodeConfig {
 
     pub chain_listener_config: Option<ChainListenerConfig>,
 
+    #[serde(default)]
+    pub plumber: PlumberConfig,
+
     #[serde(default = "default_dev_mode_config")]
     pub dev_mode: DevModeConfig,
 
@@ -224,6 +228,7 @@ impl UnresolvedNodeConfig {
             http_config: self.http_config,
             chain_config: self.chain_config,
             chain_listener_config: self.chain_listener_config,
+            plumber: self.plumber,
             services: self.services,
         };
 
@@ -406,6 +411,8 @@ pub struct NodeConfig {
 
     pub chain_listener_config: Option<ChainListenerConfig>,
 
+    pub plumber: PlumberConfig,
+
     pub services: ServicesConfig,
 }
 
@@ -592,6 +599,31 @@ pub struct ChainListenerConfig {
     #[serde(default = "default_proof_poll_period")]
     #[serde(with = "humantime_serde")]
     pub proof_poll_period: Duration,
+    /// How many blocks must be on top of the event's block before the event is acted on
+    #[serde(default)]
+    pub confirmations: u64,
+    /// Durability of the persisted listener progress
+    #[serde(default)]
+    pub persistence: PersistenceConfig,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PersistenceConfig {
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "mode")]
+pub enum FsyncPolicy {
+    /// Fsync on every write: safe, but slower
+    #[default]
+    Always,
+    /// Fsync in background once per interval: faster, but writes since the last fsync may be lost on crash
+    Periodic {
+        #[serde(with = "humantime_serde")]
+        interval: Duration,
+    },
 }
 
 /// Name of the effector module
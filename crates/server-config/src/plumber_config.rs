@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+
+use crate::node_config::PeerIdSerializable;
+
+/// Particle execution settings, the defaults of the particle executor are used for the unset ones
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PlumberConfig {
+    /// How long a particle waits for its worker's runtime to appear before being dropped
+    #[serde(default, with = "humantime_serde")]
+    pub worker_runtime_wait: Option<Duration>,
+
+    /// Max number of undelivered effects kept for inspection
+    #[serde(default)]
+    pub max_dead_letters: Option<usize>,
+
+    /// Max number of times a particle may be re-ingested locally as an effect of its own execution
+    #[serde(default)]
+    pub max_local_hops: Option<u32>,
+
+    /// Executor is saturated when there are no free VMs and mailboxes hold that many particles
+    #[serde(default)]
+    pub saturation_mailbox_threshold: Option<usize>,
+
+    /// Max number of verified particle signatures remembered, 0 disables the cache
+    #[serde(default)]
+    pub signature_cache_size: Option<usize>,
+
+    /// Max number of particles whose data is removed in a single cleanup
+    #[serde(default)]
+    pub cleanup_batch_size: Option<usize>,
+
+    /// Verify particle signatures on the blocking thread pool
+    #[serde(default)]
+    pub offload_signature_verification: Option<bool>,
+
+    /// What particles are routed to the same actor
+    #[serde(default)]
+    pub actor_keying: Option<ActorKeying>,
+
+    /// Max number of add_service/remove_service tasks running at once
+    #[serde(default)]
+    pub max_service_tasks: Option<usize>,
+
+    /// Actor is evicted once its executions took longer than that in total
+    #[serde(default, with = "humantime_serde")]
+    pub max_actor_execution_time: Option<Duration>,
+
+    /// Interpretation running longer than the particle's TTL multiplied by that factor is cancelled
+    #[serde(default)]
+    pub execution_grace_factor: Option<f64>,
+
+    /// If set, only particles initiated by these peers are admitted
+    #[serde(default)]
+    pub peer_allowlist: Option<HashSet<PeerIdSerializable>>,
+
+    /// Particles initiated by these peers are rejected
+    #[serde(default)]
+    pub peer_blocklist: HashSet<PeerIdSerializable>,
+
+    /// How long ago expired particles are still admitted, and how far in the future
+    /// particles may be created
+    #[serde(default, with = "humantime_serde")]
+    pub clock_skew_tolerance: Option<Duration>,
+
+    /// Actors created while the executor is saturated expire no later than that from now
+    #[serde(default, with = "humantime_serde")]
+    pub saturated_ttl: Option<Duration>,
+
+    /// Max number of worker pools created per poll
+    #[serde(default)]
+    pub worker_pools_per_poll: Option<usize>,
+
+    /// Evict actor right after its particle is routed only to remote peers
+    #[serde(default)]
+    pub evict_remote_only_actors: Option<bool>,
+
+    /// Expired actors are cleaned up once in that interval instead of on every poll
+    #[serde(default, with = "humantime_serde")]
+    pub cleanup_interval: Option<Duration>,
+
+    /// Each cleanup interval is prolonged by a random part of that fraction of it
+    #[serde(default)]
+    pub cleanup_jitter: Option<f64>,
+
+    /// Drop and report effects addressed to peers that aren't local
+    #[serde(default)]
+    pub strict_peer_scopes: Option<bool>,
+
+    /// VM pool of a worker is removed after no particles were executed on it for that long
+    #[serde(default, with = "humantime_serde")]
+    pub worker_pool_idle_timeout: Option<Duration>,
+
+    /// New particles are shed while the estimated memory of the buffered particles exceeds that
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub memory_budget: Option<bytesize::ByteSize>,
+
+    /// Report particles whose script finished with a nonzero return code as errors
+    #[serde(default)]
+    pub report_avm_errors: Option<bool>,
+
+    /// Max number of workers whose actors are polled in parallel
+    #[serde(default)]
+    pub worker_polling_threads: Option<usize>,
+
+    /// Max number of VMs executing particles at once across the host and all workers
+    #[serde(default)]
+    pub max_executing_vms: Option<usize>,
+
+    /// Reject particles of a worker that has no VM pool instead of keeping them
+    #[serde(default)]
+    pub reject_without_worker_pool: Option<bool>,
+
+    /// What to do with particles for a worker whose VM pool has no VMs
+    #[serde(default)]
+    pub no_capacity_policy: Option<NoCapacityPolicy>,
+
+    /// Max number of particles waiting in the mailbox of a host actor
+    #[serde(default)]
+    pub max_host_mailbox_size: Option<usize>,
+
+    /// Max number of particles waiting in the mailbox of a worker actor
+    #[serde(default)]
+    pub max_worker_mailbox_size: Option<usize>,
+
+    /// How long the shutdown waits for the interpretations in progress to finish
+    #[serde(default, with = "humantime_serde")]
+    pub shutdown_timeout: Option<Duration>,
+
+    /// A single poll of the executor yields to the runtime once it runs longer than that
+    #[serde(default, with = "humantime_serde")]
+    pub poll_time_budget: Option<Duration>,
+
+    /// A warning is logged when the memory of an AquaVM reaches that fraction of its limit
+    #[serde(default)]
+    pub memory_limit_warning_ratio: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActorKeying {
+    /// Particles with the same signature share an actor
+    Signature,
+    /// Particles share an actor only if their signature, init peer id and particle id match
+    Strict,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "mode")]
+pub enum NoCapacityPolicy {
+    /// Particles wait in the mailboxes until the pool gets VMs
+    Keep,
+    /// Particles are rejected
+    Reject,
+    /// Up to that many particles wait in the mailboxes, the rest are rejected
+    Queue { max_particles: usize },
+}
@@ -253,6 +253,8 @@ mod tests {
     use fluence_keypair::KeyPair;
     use tempfile::{tempdir, NamedTempFile};
 
+    use crate::NoCapacityPolicy;
+
     use super::*;
 
     #[test]
@@ -738,6 +740,37 @@ mod tests {
         });
     }
 
+    #[test]
+    fn load_file_plumber_config() {
+        let mut file = NamedTempFile::new().expect("Could not create temp file");
+        write!(
+            file,
+            r#"
+            [plumber]
+            worker_runtime_wait = "5s"
+            max_worker_mailbox_size = 100
+            memory_budget = "1 GiB"
+            no_capacity_policy = {{ mode = "queue", max_particles = 10 }}
+            "#
+        )
+        .expect("Could not write in file");
+
+        let path = file.path().display().to_string();
+
+        temp_env::with_var("FLUENCE_CONFIG", Some(path), || {
+            let config = load_config_with_args(vec![], None).expect("Could not load config");
+            let plumber = &config.node_config.plumber;
+            assert_eq!(plumber.worker_runtime_wait, Some(Duration::from_secs(5)));
+            assert_eq!(plumber.max_worker_mailbox_size, Some(100));
+            assert_eq!(plumber.memory_budget, Some(bytesize::ByteSize::gib(1)));
+            assert_eq!(
+                plumber.no_capacity_policy,
+                Some(NoCapacityPolicy::Queue { max_particles: 10 })
+            );
+            assert_eq!(plumber.shutdown_timeout, None);
+        });
+    }
+
     #[test]
     fn load_multiple_configs() {
         let mut file = NamedTempFile::new().expect("Could not create temp file");
@@ -19,7 +19,7 @@ use libp2p::{
     core::{multiaddr::Protocol, Multiaddr},
     identify::Event as IdentifyEvent,
 };
-use particle_protocol::PROTOCOL_NAME;
+use particle_protocol::SUPPORTED_VERSIONS;
 use tokio::sync::oneshot;
 
 use super::FluenceNetworkBehaviour;
@@ -47,7 +47,12 @@ impl FluenceNetworkBehaviour {
                     if !supports_kademlia && protocol.eq(&"/ipfs/kad/1.0.0") {
                         supports_kademlia = true;
                     }
-                    if !supports_fluence && protocol.eq(&PROTOCOL_NAME) {
+                    // Peer is compatible if it speaks any of the versions this peer supports
+                    if !supports_fluence
+                        && SUPPORTED_VERSIONS
+                            .iter()
+                            .any(|version| protocol.eq(&version.protocol_name()))
+                    {
                         supports_fluence = true;
                     }
                     if supports_fluence && supports_kademlia {
@@ -39,8 +39,9 @@ use tokio_util::sync::CancellationToken;
 use tracing::Instrument;
 
 use aquamarine::{
-    AquaRuntime, AquamarineApi, AquamarineApiError, AquamarineBackend, DataStoreConfig,
-    RemoteRoutingEffects, VmPoolConfig, WasmBackendConfig,
+    ActorKeying, AquaRuntime, AquamarineApi, AquamarineApiError, AquamarineBackend,
+    DataStoreConfig, NoCapacityPolicy, PlumberConfig, RemoteRoutingEffects, VmPoolConfig,
+    WasmBackendConfig,
 };
 use chain_connector::HttpChainConnector;
 use chain_listener::ChainListener;
@@ -53,8 +54,8 @@ use particle_builtins::{Builtins, CustomService, NodeInfo, ParticleAppServicesCo
 use particle_execution::ParticleFunctionStatic;
 use particle_protocol::ExtendedParticle;
 use peer_metrics::{
-    ChainListenerMetrics, ConnectionPoolMetrics, ConnectivityMetrics, ParticleExecutorMetrics,
-    ServicesMetrics, ServicesMetricsBackend, SpellMetrics, VmPoolMetrics,
+    ChainListenerMetrics, ConnectionPoolMetrics, ConnectivityMetrics, MetricsSink,
+    ParticleExecutorMetrics, ServicesMetrics, ServicesMetricsBackend, SpellMetrics, VmPoolMetrics,
 };
 use server_config::system_services_config::ServiceKey;
 use server_config::{NetworkConfig, ResolvedConfig};
@@ -240,7 +241,9 @@ impl<RT: AquaRuntime> Node<RT> {
         let libp2p_metrics = metrics_registry.as_mut().map(|r| Arc::new(Metrics::new(r)));
         let connectivity_metrics = metrics_registry.as_mut().map(ConnectivityMetrics::new);
         let connection_pool_metrics = metrics_registry.as_mut().map(ConnectionPoolMetrics::new);
-        let plumber_metrics = metrics_registry.as_mut().map(ParticleExecutorMetrics::new);
+        let plumber_metrics = metrics_registry.as_mut().map(|registry| {
+            Arc::new(ParticleExecutorMetrics::new(registry)) as Arc<dyn MetricsSink>
+        });
         let vm_pool_metrics = metrics_registry.as_mut().map(VmPoolMetrics::new);
         let spell_metrics = metrics_registry.as_mut().map(SpellMetrics::new);
         let chain_listener_metrics = metrics_registry.as_mut().map(ChainListenerMetrics::new);
@@ -326,6 +329,7 @@ impl<RT: AquaRuntime> Node<RT> {
             vm_config,
             avm_wasm_backend_config,
             data_store_config,
+            plumber_config(&config),
             Arc::clone(&builtins),
             effects_out,
             plumber_metrics,
@@ -665,7 +669,8 @@ impl<RT: AquaRuntime> Node<RT> {
             let spell_event_bus = spell_event_bus.start();
             let sorcerer = sorcerer.start(spell_events_receiver);
             let chain_listener = chain_listener.map(|c| c.start());
-            let aquamarine_backend = aquamarine_backend.start();
+            let aquamarine_stop = CancellationToken::new();
+            let aquamarine_backend = aquamarine_backend.start(aquamarine_stop.clone());
             let mut connectivity = connectivity.start();
             let mut dispatcher = dispatcher.start(particle_stream, effects_stream);
             let mut exit_inlet = Some(exit_inlet);
@@ -695,7 +700,11 @@ impl<RT: AquaRuntime> Node<RT> {
             sorcerer.abort();
             dispatcher.cancel().await;
             connectivity.cancel().await;
-            aquamarine_backend.abort();
+            // Let the interpretations in progress finish and save the queued particles
+            aquamarine_stop.cancel();
+            if let Err(err) = aquamarine_backend.await {
+                log::error!("Aquamarine stopped with error: {err}");
+            }
             workers.shutdown();
             task_cancellation_token.cancel()
         }.in_current_span()).expect("Could not spawn task");
@@ -766,6 +775,112 @@ fn avm_wasm_backend_config(config: &ResolvedConfig) -> WasmBackendConfig {
     }
 }
 
+fn plumber_config(config: &ResolvedConfig) -> PlumberConfig {
+    let plumber = &config.node_config.plumber;
+    let default = PlumberConfig::default();
+    PlumberConfig {
+        worker_runtime_wait: plumber
+            .worker_runtime_wait
+            .unwrap_or(default.worker_runtime_wait),
+        max_dead_letters: plumber.max_dead_letters.unwrap_or(default.max_dead_letters),
+        max_local_hops: plumber.max_local_hops.unwrap_or(default.max_local_hops),
+        saturation_mailbox_threshold: plumber
+            .saturation_mailbox_threshold
+            .unwrap_or(default.saturation_mailbox_threshold),
+        signature_cache_size: plumber
+            .signature_cache_size
+            .unwrap_or(default.signature_cache_size),
+        cleanup_batch_size: plumber
+            .cleanup_batch_size
+            .unwrap_or(default.cleanup_batch_size),
+        offload_signature_verification: plumber
+            .offload_signature_verification
+            .unwrap_or(default.offload_signature_verification),
+        actor_keying: plumber
+            .actor_keying
+            .map(|keying| match keying {
+                server_config::ActorKeying::Signature => ActorKeying::Signature,
+                server_config::ActorKeying::Strict => ActorKeying::Strict,
+            })
+            .unwrap_or(default.actor_keying),
+        max_service_tasks: plumber
+            .max_service_tasks
+            .unwrap_or(default.max_service_tasks),
+        max_actor_execution_time: plumber
+            .max_actor_execution_time
+            .or(default.max_actor_execution_time),
+        execution_grace_factor: plumber
+            .execution_grace_factor
+            .or(default.execution_grace_factor),
+        peer_allowlist: plumber
+            .peer_allowlist
+            .as_ref()
+            .map(|peers| peers.iter().map(|peer_id| **peer_id).collect())
+            .or(default.peer_allowlist),
+        peer_blocklist: plumber
+            .peer_blocklist
+            .iter()
+            .map(|peer_id| **peer_id)
+            .chain(default.peer_blocklist)
+            .collect(),
+        clock_skew_tolerance_ms: plumber
+            .clock_skew_tolerance
+            .map(|tolerance| tolerance.as_millis() as u64)
+            .or(default.clock_skew_tolerance_ms),
+        saturated_ttl: plumber.saturated_ttl.or(default.saturated_ttl),
+        worker_pools_per_poll: plumber
+            .worker_pools_per_poll
+            .or(default.worker_pools_per_poll),
+        evict_remote_only_actors: plumber
+            .evict_remote_only_actors
+            .unwrap_or(default.evict_remote_only_actors),
+        cleanup_interval: plumber.cleanup_interval.or(default.cleanup_interval),
+        cleanup_jitter: plumber.cleanup_jitter.unwrap_or(default.cleanup_jitter),
+        strict_peer_scopes: plumber
+            .strict_peer_scopes
+            .unwrap_or(default.strict_peer_scopes),
+        worker_pool_idle_timeout: plumber
+            .worker_pool_idle_timeout
+            .or(default.worker_pool_idle_timeout),
+        memory_budget: plumber
+            .memory_budget
+            .map(|budget| budget.as_u64() as usize)
+            .or(default.memory_budget),
+        report_avm_errors: plumber
+            .report_avm_errors
+            .unwrap_or(default.report_avm_errors),
+        worker_polling_threads: plumber
+            .worker_polling_threads
+            .or(default.worker_polling_threads),
+        max_executing_vms: plumber.max_executing_vms.or(default.max_executing_vms),
+        reject_without_worker_pool: plumber
+            .reject_without_worker_pool
+            .unwrap_or(default.reject_without_worker_pool),
+        no_capacity_policy: plumber
+            .no_capacity_policy
+            .map(|policy| match policy {
+                server_config::NoCapacityPolicy::Keep => NoCapacityPolicy::Keep,
+                server_config::NoCapacityPolicy::Reject => NoCapacityPolicy::Reject,
+                server_config::NoCapacityPolicy::Queue { max_particles } => {
+                    NoCapacityPolicy::Queue { max_particles }
+                }
+            })
+            .unwrap_or(default.no_capacity_policy),
+        max_host_mailbox_size: plumber
+            .max_host_mailbox_size
+            .or(default.max_host_mailbox_size),
+        max_worker_mailbox_size: plumber
+            .max_worker_mailbox_size
+            .or(default.max_worker_mailbox_size),
+        shutdown_timeout: plumber.shutdown_timeout.unwrap_or(default.shutdown_timeout),
+        poll_time_budget: plumber.poll_time_budget.or(default.poll_time_budget),
+        memory_limit_warning_ratio: plumber
+            .memory_limit_warning_ratio
+            .unwrap_or(default.memory_limit_warning_ratio),
+        ..default
+    }
+}
+
 fn services_wasm_backend_config(config: &ResolvedConfig) -> WasmBackendConfig {
     WasmBackendConfig {
         debug_info: config.node_config.services.wasm_backend.debug_info,
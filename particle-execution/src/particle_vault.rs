@@ -41,6 +41,10 @@ impl ParticleVault {
         Self { vault_dir }
     }
 
+    pub fn vault_dir(&self) -> &Path {
+        &self.vault_dir
+    }
+
     pub fn real_worker_particle_vault(&self, peer_id: PeerId) -> PathBuf {
         self.vault_dir.join(peer_id.to_base58())
     }
@@ -27,9 +27,10 @@
 )]
 
 mod libp2p_protocol {
-    mod codec;
+    pub(super) mod codec;
     pub(super) mod message;
     pub(super) mod upgrade;
+    pub(super) mod version;
 }
 
 mod contact;
@@ -38,10 +39,12 @@ mod particle;
 
 pub use contact::Contact;
 pub use error::ParticleError;
+pub use libp2p_protocol::codec::SUPPORTED_FORMAT_CODE;
 pub use libp2p_protocol::message::CompletionChannel;
 pub use libp2p_protocol::message::SendStatus;
 pub use libp2p_protocol::message::{HandlerMessage, ProtocolMessage};
 pub use libp2p_protocol::upgrade::ProtocolConfig;
+pub use libp2p_protocol::version::{ProtocolVersion, SUPPORTED_VERSIONS};
 pub use particle::ExtendedParticle;
 pub use particle::Particle;
 
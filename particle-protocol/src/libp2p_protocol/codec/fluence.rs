@@ -1,3 +1,4 @@
+use crate::libp2p_protocol::version::ProtocolVersion;
 use crate::ProtocolMessage;
 use air_interpreter_sede::{
     define_simple_representation, Format as SedeFormat, FromSerialized as _, MsgPackMultiformat,
@@ -10,6 +11,8 @@ use unsigned_varint::codec::UviBytes;
 const MAX_BUF_SIZE: usize = 100 * 1024 * 1024;
 
 type ProtocolMessageFormat = MsgPackMultiformat;
+/// Multicodec code prepended to every message by `ProtocolMessageFormat`
+pub const SUPPORTED_FORMAT_CODE: u64 = 0x0201;
 
 define_simple_representation!(
     ProtocolMessageRepresentation,
@@ -20,13 +23,15 @@ define_simple_representation!(
 
 pub struct FluenceCodec {
     length: UviBytes<BytesMut>,
+    /// Version negotiated for the substream, messages of other versions' formats are rejected
+    version: ProtocolVersion,
 }
 
 impl FluenceCodec {
-    pub fn new() -> Self {
+    pub fn new(version: ProtocolVersion) -> Self {
         let mut length: UviBytes<BytesMut> = UviBytes::default();
         length.set_max_len(MAX_BUF_SIZE);
-        Self { length }
+        Self { length, version }
     }
 }
 
@@ -37,6 +42,15 @@ impl Decoder for FluenceCodec {
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         let bytes = self.length.decode(src)?;
         if let Some(bytes) = bytes {
+            // A peer may use a format other than the negotiated version's one.
+            // Malformed prefix is left to the deserializer to report.
+            if let Ok((code, _)) = unsigned_varint::decode::u64(&bytes) {
+                let expected = self.version.format_code();
+                if code != expected {
+                    return Err(FluenceCodecError::UnsupportedFormat { code, expected });
+                }
+            }
+
             return ProtocolMessageRepresentation
                 .deserialize(&bytes)
                 .map(Some)
@@ -67,6 +81,11 @@ pub enum FluenceCodecError {
     Length(std::io::Error),
     Serialize(<ProtocolMessageFormat as SedeFormat<ProtocolMessage>>::SerializationError),
     Deserialize(<ProtocolMessageFormat as SedeFormat<ProtocolMessage>>::DeserializationError),
+    /// Message is serialized with a format other than the negotiated version's one
+    UnsupportedFormat {
+        code: u64,
+        expected: u64,
+    },
 }
 
 impl From<std::io::Error> for FluenceCodecError {
@@ -82,6 +101,7 @@ impl std::error::Error for FluenceCodecError {
             FluenceCodecError::Length(ref e) => Some(e),
             FluenceCodecError::Serialize(ref e) => Some(e),
             FluenceCodecError::Deserialize(ref e) => Some(e),
+            FluenceCodecError::UnsupportedFormat { .. } => None,
         }
     }
 }
@@ -93,6 +113,11 @@ impl std::fmt::Display for FluenceCodecError {
             FluenceCodecError::Length(e) => write!(f, "I/O error: {}", e),
             FluenceCodecError::Serialize(e) => write!(f, "Serialization error: {}", e),
             FluenceCodecError::Deserialize(e) => write!(f, "Deserialization error: {}", e),
+            FluenceCodecError::UnsupportedFormat { code, expected } => write!(
+                f,
+                "Unsupported message format {:#x}, expected {:#x}",
+                code, expected
+            ),
         }
     }
 }
@@ -104,14 +129,17 @@ impl From<FluenceCodecError> for std::io::Error {
             FluenceCodecError::Length(e) => io::Error::new(io::ErrorKind::InvalidInput, e),
             FluenceCodecError::Serialize(e) => io::Error::new(io::ErrorKind::InvalidInput, e),
             FluenceCodecError::Deserialize(e) => io::Error::new(io::ErrorKind::InvalidInput, e),
+            e @ FluenceCodecError::UnsupportedFormat { .. } => {
+                io::Error::new(io::ErrorKind::InvalidData, e)
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::libp2p_protocol::codec::FluenceCodec;
-    use crate::{Particle, ProtocolMessage};
+    use crate::libp2p_protocol::codec::{FluenceCodec, FluenceCodecError};
+    use crate::{Particle, ProtocolMessage, ProtocolVersion};
     use asynchronous_codec::{BytesMut, Decoder, Encoder};
     use base64::{engine::general_purpose::STANDARD as base64, Engine};
     use libp2p::PeerId;
@@ -119,7 +147,7 @@ mod tests {
 
     #[test]
     fn isomorphic_codec_test() {
-        let mut codec = FluenceCodec::new();
+        let mut codec = FluenceCodec::new(ProtocolVersion::V2);
         let initial_message = ProtocolMessage::Particle(Particle {
             id: "id".to_string(),
             init_peer_id: PeerId::random(),
@@ -150,7 +178,7 @@ mod tests {
         let hex_data = base64.decode(raw_str).expect("Base64");
         let mut bytes = BytesMut::from(&hex_data[..]);
 
-        let mut codec = FluenceCodec::new();
+        let mut codec = FluenceCodec::new(ProtocolVersion::V2);
 
         let result = codec.decode(&mut bytes).expect("Decoding");
 
@@ -173,4 +201,29 @@ mod tests {
 
         assert_eq!(result, Some(expected))
     }
+
+    #[test]
+    fn unsupported_format_test() {
+        let mut codec = FluenceCodec::new(ProtocolVersion::V2);
+        let message = ProtocolMessage::Particle(Particle::default());
+        let mut bytes = BytesMut::new();
+        codec.encode(message, &mut bytes).expect("Encoding");
+
+        // Replace msgpack multicodec (0x0201, varint 81 04) with json (0x0200, varint 80 04)
+        let position = bytes
+            .windows(2)
+            .position(|w| w == [0x81, 0x04])
+            .expect("Format code");
+        bytes[position] = 0x80;
+
+        let result = codec.decode(&mut bytes);
+
+        match result {
+            Err(FluenceCodecError::UnsupportedFormat { code, expected }) => {
+                assert_eq!(code, 0x0200);
+                assert_eq!(expected, ProtocolVersion::V2.format_code());
+            }
+            unexpected => panic!("Expected UnsupportedFormat error, got {:?}", unexpected),
+        }
+    }
 }
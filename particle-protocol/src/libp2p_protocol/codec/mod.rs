@@ -1,3 +1,5 @@
 mod fluence;
 
 pub use self::fluence::FluenceCodec;
+pub use self::fluence::FluenceCodecError;
+pub use self::fluence::SUPPORTED_FORMAT_CODE;
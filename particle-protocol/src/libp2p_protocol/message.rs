@@ -19,7 +19,7 @@ use std::time::Duration;
 use serde::{Deserialize, Serialize};
 use tokio::sync::oneshot;
 
-use crate::Particle;
+use crate::{Particle, ProtocolVersion};
 
 #[derive(Debug, Default)]
 pub enum SendStatus {
@@ -55,33 +55,53 @@ pub enum HandlerMessage {
     /// Particle being sent to remote peer. Contains a channel to signal write completion.
     /// Send-only, can't be received.
     OutParticle(Particle, CompletionChannel),
-    /// Particle being received from a remote peer.
+    /// Particle being received from a remote peer with the negotiated protocol version.
     /// Receive-only, can't be sent.
-    InParticle(Particle),
-    /// Dummy plug. Generated by the `OneshotHandler` when Inbound or Outbound Upgrade happened.
+    InParticle(Particle, ProtocolVersion),
+    /// Dummy plug. Generated by the `OneshotHandler` when Inbound Upgrade happened.
     Upgrade,
+    /// Generated by the `OneshotHandler` when Outbound Upgrade happened with that protocol version.
+    /// Receive-only, can't be sent.
+    Negotiated(ProtocolVersion),
+    /// Message from a remote peer serialized with a format other than the negotiated version's one.
+    /// Receive-only, can't be sent.
+    UnsupportedFormat { code: u64, version: ProtocolVersion },
 }
 
 impl HandlerMessage {
+    /// Message received from a remote peer with the negotiated protocol version
+    pub fn received(msg: ProtocolMessage, version: ProtocolVersion) -> HandlerMessage {
+        match msg {
+            ProtocolMessage::Particle(p) => HandlerMessage::InParticle(p, version),
+            ProtocolMessage::Upgrade => HandlerMessage::Upgrade,
+        }
+    }
+
     pub fn into_protocol_message(self) -> (ProtocolMessage, Option<oneshot::Sender<SendStatus>>) {
         match self {
             HandlerMessage::OutParticle(particle, channel) => {
                 (ProtocolMessage::Particle(particle), channel.outlet())
             }
             HandlerMessage::Upgrade => (ProtocolMessage::Upgrade, None),
-            HandlerMessage::InParticle(_) => {
+            HandlerMessage::InParticle(..) => {
                 unreachable!("InParticle is never sent, only received")
             }
+            HandlerMessage::Negotiated(_) => {
+                unreachable!("Negotiated is never sent, only received")
+            }
+            HandlerMessage::UnsupportedFormat { .. } => {
+                unreachable!("UnsupportedFormat is never sent, only received")
+            }
         }
     }
 }
 
 // Required by OneShotHandler in inject_fully_negotiated_outbound. And that's because
-// <ProtocolMessage as UpgradeOutbound>::Output is (), and OneshotHandler requires it to be
-// convertible to OneshotHandler::TEvent which is a ProtocolMessage
-impl From<()> for HandlerMessage {
-    fn from(_: ()) -> HandlerMessage {
-        HandlerMessage::Upgrade
+// <ProtocolMessage as UpgradeOutbound>::Output is the negotiated version, and OneshotHandler
+// requires it to be convertible to OneshotHandler::TEvent which is a ProtocolMessage
+impl From<ProtocolVersion> for HandlerMessage {
+    fn from(version: ProtocolVersion) -> HandlerMessage {
+        HandlerMessage::Negotiated(version)
     }
 }
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -100,12 +120,3 @@ impl std::fmt::Display for ProtocolMessage {
         }
     }
 }
-
-impl From<ProtocolMessage> for HandlerMessage {
-    fn from(msg: ProtocolMessage) -> HandlerMessage {
-        match msg {
-            ProtocolMessage::Particle(p) => HandlerMessage::InParticle(p),
-            ProtocolMessage::Upgrade => HandlerMessage::Upgrade,
-        }
-    }
-}
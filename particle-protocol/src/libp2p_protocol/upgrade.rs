@@ -16,7 +16,7 @@
 
 use asynchronous_codec::{FramedRead, FramedWrite};
 use std::fmt::Debug;
-use std::{io, iter, time::Duration};
+use std::{io, iter, slice, time::Duration};
 
 use futures::{
     future::BoxFuture, AsyncRead, AsyncWrite, AsyncWriteExt, FutureExt, SinkExt, StreamExt,
@@ -29,8 +29,8 @@ use libp2p::{
 use log::LevelFilter;
 use serde::{Deserialize, Serialize};
 
-use crate::libp2p_protocol::codec::FluenceCodec;
-use crate::{HandlerMessage, SendStatus, PROTOCOL_NAME};
+use crate::libp2p_protocol::codec::{FluenceCodec, FluenceCodecError};
+use crate::{HandlerMessage, ProtocolVersion, SendStatus, SUPPORTED_VERSIONS};
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct ProtocolConfig {
@@ -88,11 +88,11 @@ impl<OutProto: libp2p::swarm::handler::OutboundUpgradeSend, OutEvent> From<Proto
 macro_rules! impl_upgrade_info {
     ($tname:ident) => {
         impl UpgradeInfo for $tname {
-            type Info = &'static str;
-            type InfoIter = iter::Once<Self::Info>;
+            type Info = ProtocolVersion;
+            type InfoIter = iter::Copied<slice::Iter<'static, ProtocolVersion>>;
 
             fn protocol_info(&self) -> Self::InfoIter {
-                iter::once(PROTOCOL_NAME)
+                SUPPORTED_VERSIONS.iter().copied()
             }
         }
     };
@@ -109,25 +109,31 @@ where
     type Error = std::io::Error;
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
-    fn upgrade_inbound(self, socket: Socket, _: Self::Info) -> Self::Future {
+    fn upgrade_inbound(self, socket: Socket, version: Self::Info) -> Self::Future {
         async move {
-            let msg = FramedRead::new(socket, FluenceCodec::new())
+            let msg = FramedRead::new(socket, FluenceCodec::new(version))
                 .next()
                 .await
-                .ok_or(io::ErrorKind::UnexpectedEof)??;
-
-            Ok(msg)
-        }
-        .map(|result| match result {
-            Ok(msg) => {
-                if log::log_enabled!(log::Level::Debug) {
-                    log::debug!("Got inbound ProtocolMessage: {:?}", msg);
-                } else {
-                    log::info!("Got inbound ProtocolMessage: {}", msg);
+                .ok_or(io::ErrorKind::UnexpectedEof)?;
+
+            match msg {
+                Ok(msg) => {
+                    if log::log_enabled!(log::Level::Debug) {
+                        log::debug!("Got inbound ProtocolMessage: {:?}", msg);
+                    } else {
+                        log::info!("Got inbound ProtocolMessage: {}", msg);
+                    }
+                    Ok(HandlerMessage::received(msg, version))
                 }
-                let msg: HandlerMessage = msg.into();
-                Ok(msg)
+                // Reported as a message, since OneShotHandler drops inbound upgrade errors
+                Err(FluenceCodecError::UnsupportedFormat { code, .. }) => {
+                    Ok(HandlerMessage::UnsupportedFormat { code, version })
+                }
+                Err(err) => Err(err.into()),
             }
+        }
+        .map(|result: Result<HandlerMessage, io::Error>| match result {
+            Ok(msg) => Ok(msg),
             Err(err) => {
                 log::warn!("Error processing inbound ProtocolMessage: {:?}", err);
                 Err(err)
@@ -141,11 +147,11 @@ impl<Socket> OutboundUpgrade<Socket> for HandlerMessage
 where
     Socket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
-    type Output = ();
+    type Output = ProtocolVersion;
     type Error = io::Error;
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
-    fn upgrade_outbound(self, mut socket: Socket, _: Self::Info) -> Self::Future {
+    fn upgrade_outbound(self, mut socket: Socket, version: Self::Info) -> Self::Future {
         async move {
             let (msg, channel) = self.into_protocol_message();
 
@@ -157,7 +163,7 @@ where
             }
 
             let write = async move || -> Result<_, io::Error> {
-                FramedWrite::new(&mut socket, FluenceCodec::new())
+                FramedWrite::new(&mut socket, FluenceCodec::new(version))
                     .send(msg)
                     .await?;
 
@@ -166,7 +172,7 @@ where
                 //          error on InboundUpgrade side.
                 //          See e.g. https://github.com/libp2p/rust-yamux/issues/117
                 socket.close().await?;
-                Ok(())
+                Ok(version)
             };
 
             let result = write().await.map_err(|err| {
@@ -193,15 +199,21 @@ where
 mod tests {
     use futures::prelude::*;
     use libp2p::core::transport::{ListenerId, TransportEvent};
+    use libp2p::core::UpgradeInfo;
     use libp2p::core::{
         multiaddr::multiaddr,
         transport::{memory::MemoryTransport, Transport},
     };
     use libp2p::{InboundUpgrade, OutboundUpgrade};
+    use multistream_select::{
+        dialer_select_proto, listener_select_proto, NegotiationError, Version,
+    };
     use rand::{thread_rng, Rng};
 
     use crate::libp2p_protocol::message::ProtocolMessage;
-    use crate::{HandlerMessage, ProtocolConfig};
+    use crate::{HandlerMessage, ProtocolConfig, ProtocolVersion, SUPPORTED_VERSIONS};
+
+    const UNSUPPORTED_PROTOCOL: &str = "/fluence/particle/1.0.0";
 
     const BYTES: [u8; 175] = [
         123, 34, 97, 99, 116, 105, 111, 110, 34, 58, 34, 80, 97, 114, 116, 105, 99, 108, 101, 34,
@@ -235,7 +247,10 @@ mod tests {
             let conn = listener_upgrade.await.unwrap();
 
             let config = ProtocolConfig::default();
-            config.upgrade_inbound(conn, "/test/1").await.unwrap()
+            config
+                .upgrade_inbound(conn, ProtocolVersion::V2)
+                .await
+                .unwrap()
         });
         let msg: ProtocolMessage = serde_json::from_slice(&BYTES).unwrap();
         let sent_particle = match msg {
@@ -245,17 +260,96 @@ mod tests {
         let msg = HandlerMessage::OutParticle(sent_particle.clone(), <_>::default());
         let mut transport = MemoryTransport::new();
         let c = transport.dial(listener_addr).unwrap().await.unwrap();
-        msg.upgrade_outbound(c, "/test/1").await.unwrap();
+        msg.upgrade_outbound(c, ProtocolVersion::V2).await.unwrap();
         let received_particle = inbound.await.unwrap();
 
         match received_particle {
-            HandlerMessage::InParticle(received_particle) => {
+            HandlerMessage::InParticle(received_particle, _) => {
                 assert_eq!(sent_particle, received_particle)
             }
             _ => unreachable!("must be InParticle"),
         }
     }
 
+    #[tokio::test]
+    async fn negotiate_version_test() {
+        let mem_addr = multiaddr![Memory(thread_rng().gen::<u64>())];
+        let mut transport = MemoryTransport::new().boxed();
+        let listener_id = ListenerId::next();
+        transport.listen_on(listener_id, mem_addr).unwrap();
+
+        let listener_addr = match transport.select_next_some().now_or_never() {
+            Some(TransportEvent::NewAddress { listen_addr, .. }) => listen_addr,
+            p => panic!("MemoryTransport not listening on an address!: {:?}", p),
+        };
+
+        let inbound = tokio::task::spawn(async move {
+            let (listener_upgrade, _) = transport.select_next_some().await.into_incoming().unwrap();
+            let conn = listener_upgrade.await.unwrap();
+
+            let config = ProtocolConfig::default();
+            let (version, conn) = listener_select_proto(conn, config.protocol_info())
+                .await
+                .unwrap();
+            config.upgrade_inbound(conn, version).await.unwrap()
+        });
+
+        // Remote peer prefers a version this peer doesn't speak, so they agree on the common one
+        let mut transport = MemoryTransport::new();
+        let c = transport.dial(listener_addr).unwrap().await.unwrap();
+        let protocols = [UNSUPPORTED_PROTOCOL, ProtocolVersion::V2.protocol_name()];
+        let (negotiated, c) = dialer_select_proto(c, protocols, Version::V1)
+            .await
+            .unwrap();
+        assert_eq!(negotiated, ProtocolVersion::V2.protocol_name());
+
+        let msg: ProtocolMessage = serde_json::from_slice(&BYTES).unwrap();
+        let sent_particle = match msg {
+            ProtocolMessage::Particle(p) => p,
+            _ => unreachable!("must be particle"),
+        };
+        let msg = HandlerMessage::OutParticle(sent_particle.clone(), <_>::default());
+        let version = msg.upgrade_outbound(c, ProtocolVersion::V2).await.unwrap();
+        assert_eq!(version, ProtocolVersion::V2);
+
+        match inbound.await.unwrap() {
+            HandlerMessage::InParticle(received_particle, version) => {
+                assert_eq!(sent_particle, received_particle);
+                assert_eq!(version, ProtocolVersion::V2);
+            }
+            _ => unreachable!("must be InParticle"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reject_unsupported_version_test() {
+        let mem_addr = multiaddr![Memory(thread_rng().gen::<u64>())];
+        let mut transport = MemoryTransport::new().boxed();
+        let listener_id = ListenerId::next();
+        transport.listen_on(listener_id, mem_addr).unwrap();
+
+        let listener_addr = match transport.select_next_some().now_or_never() {
+            Some(TransportEvent::NewAddress { listen_addr, .. }) => listen_addr,
+            p => panic!("MemoryTransport not listening on an address!: {:?}", p),
+        };
+
+        let inbound = tokio::task::spawn(async move {
+            let (listener_upgrade, _) = transport.select_next_some().await.into_incoming().unwrap();
+            let conn = listener_upgrade.await.unwrap();
+
+            listener_select_proto(conn, SUPPORTED_VERSIONS.iter().copied())
+                .await
+                .map(|(version, _)| version)
+        });
+
+        let mut transport = MemoryTransport::new();
+        let c = transport.dial(listener_addr).unwrap().await.unwrap();
+        let outbound = dialer_select_proto(c, [UNSUPPORTED_PROTOCOL], Version::V1).await;
+
+        assert!(matches!(outbound, Err(NegotiationError::Failed)));
+        assert!(inbound.await.unwrap().is_err());
+    }
+
     #[test]
     fn deserialize() {
         let str = r#"{"action":"Particle","id":"2","init_peer_id":"12D3KooWAcn1f5iZ7wbo9QrYPFgq6o7DGkh7VwC8Zucn6DgWZQDo","timestamp":1617733422130,"ttl":65525,"script":"!","signature":[],"data":"MTJEM0tvb1dDM3dhcjhqcTJzaGFVQ2hSZWttYjNNN0RGRGl4ZkdVTm5ydGY0VlRGQVlVdywxMkQzS29vV0o2bVZLYXpKQzdyd2dtd0JpZm5LZ0JoR2NSTWtaOXdRTjY4dmJ1UGdIUjlO"}"#;
@@ -0,0 +1,58 @@
+/*
+ * Copyright 2024 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt::{Display, Formatter};
+
+use crate::libp2p_protocol::codec::SUPPORTED_FORMAT_CODE;
+use crate::PROTOCOL_NAME;
+
+/// Versions of the protocol this peer speaks, the preferred one first
+pub const SUPPORTED_VERSIONS: &[ProtocolVersion] = &[ProtocolVersion::V2];
+
+/// Version of the protocol, negotiated with the remote peer for every substream
+/// by its protocol name. Peers that have no version in common fail the negotiation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProtocolVersion {
+    /// Messages are serialized with MessagePack
+    V2,
+}
+
+impl ProtocolVersion {
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            ProtocolVersion::V2 => PROTOCOL_NAME,
+        }
+    }
+
+    /// Multicodec code of the serialization format that messages of the version are tagged with
+    pub fn format_code(&self) -> u64 {
+        match self {
+            ProtocolVersion::V2 => SUPPORTED_FORMAT_CODE,
+        }
+    }
+}
+
+impl AsRef<str> for ProtocolVersion {
+    fn as_ref(&self) -> &str {
+        self.protocol_name()
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.protocol_name())
+    }
+}
@@ -14,6 +14,8 @@
  * limitations under the License.
  */
 
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::sync::Arc;
 use std::time::Duration;
@@ -27,7 +29,7 @@ use crate::error::ParticleError;
 use crate::error::ParticleError::{
     DecodingError, InvalidKeypair, SignatureVerificationFailed, SigningFailed,
 };
-use fluence_keypair::{KeyPair, PublicKey, Signature};
+use fluence_keypair::{KeyFormat, KeyPair, PublicKey, Signature};
 use fluence_libp2p::RandomPeerId;
 use now_millis::now_ms;
 use types::peer_id;
@@ -36,6 +38,8 @@ use types::peer_id;
 pub struct ExtendedParticle {
     pub particle: Particle,
     pub span: Arc<Span>,
+    /// How many times the particle was re-ingested locally as an effect of its own execution
+    pub hops: u32,
 }
 
 impl AsRef<Particle> for ExtendedParticle {
@@ -55,6 +59,7 @@ impl ExtendedParticle {
         Self {
             particle,
             span: Arc::new(span),
+            hops: 0,
         }
     }
 
@@ -62,8 +67,13 @@ impl ExtendedParticle {
         Self {
             particle,
             span: span.clone(),
+            hops: 0,
         }
     }
+
+    pub fn with_hops(self, hops: u32) -> Self {
+        Self { hops, ..self }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Derivative)]
@@ -164,10 +174,89 @@ impl Particle {
     }
 
     pub fn verify(&self) -> Result<(), ParticleError> {
-        let pk: PublicKey = self.init_peer_id.try_into().map_err(|err| DecodingError {
+        let pk = self.public_key()?;
+        self.verify_with(&pk)
+    }
+
+    /// Verifies the signatures of the particles, decoding the public key of each init peer
+    /// only once. Ed25519 signatures are verified together in a single batch, and one by one
+    /// only if the batch fails, to find the invalid ones.
+    /// Signatures of other key types are verified one by one.
+    /// Results are returned in the order of the particles.
+    pub fn verify_batch(particles: &[&Particle]) -> Vec<Result<(), ParticleError>> {
+        let mut keys: HashMap<PeerId, PublicKey> = HashMap::new();
+        let mut results: Vec<Option<Result<(), ParticleError>>> =
+            Vec::with_capacity(particles.len());
+        let mut batch = vec![];
+        for (idx, particle) in particles.iter().enumerate() {
+            let pk = match keys.entry(particle.init_peer_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match particle.public_key() {
+                    Ok(pk) => entry.insert(pk),
+                    Err(err) => {
+                        results.push(Some(Err(err)));
+                        continue;
+                    }
+                },
+            };
+            match particle.ed25519_signature(pk) {
+                Some((key, signature)) => {
+                    batch.push((idx, particle.as_bytes(), signature, key));
+                    results.push(None);
+                }
+                None => results.push(Some(particle.verify_with(pk))),
+            }
+        }
+
+        if !batch.is_empty() {
+            let messages: Vec<&[u8]> = batch.iter().map(|(_, msg, _, _)| msg.as_slice()).collect();
+            let signatures: Vec<_> = batch.iter().map(|(_, _, sig, _)| *sig).collect();
+            let verifying_keys: Vec<_> = batch.iter().map(|(_, _, _, key)| *key).collect();
+            let batch_valid =
+                ed25519_dalek::verify_batch(&messages, &signatures, &verifying_keys).is_ok();
+            for (idx, _, _, _) in batch {
+                let result = if batch_valid {
+                    Ok(())
+                } else {
+                    let particle = particles[idx];
+                    let pk = keys
+                        .get(&particle.init_peer_id)
+                        .expect("public key is decoded for each batched particle");
+                    particle.verify_with(pk)
+                };
+                results[idx] = Some(result);
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("verification result for each particle"))
+            .collect()
+    }
+
+    /// Ed25519 key and signature of the particle, `None` for other key types
+    /// or if they are malformed, so that the particle is verified on its own
+    fn ed25519_signature(
+        &self,
+        pk: &PublicKey,
+    ) -> Option<(ed25519_dalek::VerifyingKey, ed25519_dalek::Signature)> {
+        if !matches!(pk.get_key_format(), KeyFormat::Ed25519) {
+            return None;
+        }
+        let key_bytes: [u8; 32] = pk.to_vec().try_into().ok()?;
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&key_bytes).ok()?;
+        let signature = ed25519_dalek::Signature::from_slice(&self.signature).ok()?;
+        Some((key, signature))
+    }
+
+    fn public_key(&self) -> Result<PublicKey, ParticleError> {
+        self.init_peer_id.try_into().map_err(|err| DecodingError {
             err,
             particle_id: self.id.clone(),
-        })?;
+        })
+    }
+
+    fn verify_with(&self, pk: &PublicKey) -> Result<(), ParticleError> {
         let sig = Signature::from_bytes(pk.get_key_format(), self.signature.clone());
         pk.verify(&self.as_bytes(), &sig)
             .map_err(|err| SignatureVerificationFailed {
@@ -201,10 +290,25 @@ impl std::fmt::Display for Particle {
 
 #[cfg(test)]
 mod tests {
+    use crate::error::ParticleError::SignatureVerificationFailed;
     use crate::Particle;
     use base64::{engine::general_purpose::STANDARD as base64, Engine};
     use fluence_keypair::{KeyFormat, KeyPair};
 
+    fn signed_particle(kp: &KeyPair, id: &str) -> Particle {
+        let mut particle = Particle {
+            id: id.to_string(),
+            init_peer_id: kp.get_peer_id(),
+            timestamp: 1696934545662,
+            ttl: 7000,
+            script: "abc".to_string(),
+            signature: vec![],
+            data: vec![],
+        };
+        particle.sign(kp).unwrap();
+        particle
+    }
+
     #[test]
     fn test_signature() {
         let kp_bytes = base64
@@ -248,4 +352,38 @@ mod tests {
         assert!(p.verify().is_ok());
         assert_eq!(base64.encode(&p.signature), "KceXDnOfqe0dOnAxiDsyWBIvUq6WHoT0ge+VMHXOZsjZvCNH7/10oufdlYfcPomfv28On6E87ZhDcHGBZcb7Bw==");
     }
+
+    #[test]
+    fn test_verify_batch() {
+        let alice = KeyPair::generate_ed25519();
+        let bob = KeyPair::generate_ed25519();
+        let carol = KeyPair::generate_secp256k1();
+
+        let valid = [
+            signed_particle(&alice, "alice_1"),
+            signed_particle(&bob, "bob"),
+            signed_particle(&alice, "alice_2"),
+            signed_particle(&carol, "carol"),
+        ];
+        let results = Particle::verify_batch(&valid.iter().collect::<Vec<_>>());
+        assert_eq!(results.len(), valid.len());
+        assert!(results.iter().all(Result::is_ok));
+
+        // a forged particle fails the batch, so that each one is verified on its own
+        let mut forged = signed_particle(&alice, "forged");
+        forged.script = "(null)".to_string();
+        let mut batch: Vec<&Particle> = valid.iter().collect();
+        batch.insert(1, &forged);
+        let results = Particle::verify_batch(&batch);
+        assert_eq!(results.len(), batch.len());
+        for (particle, result) in batch.iter().zip(results) {
+            match result {
+                Err(SignatureVerificationFailed { particle_id, .. }) => {
+                    assert_eq!(particle_id, "forged")
+                }
+                Err(err) => panic!("Unexpected error for {}: {:?}", particle.id, err),
+                Ok(()) => assert_ne!(particle.id, "forged"),
+            }
+        }
+    }
 }